@@ -32,12 +32,12 @@ impl<'s> BDPT<'s> {
             MAX_CAMERA_DEPTH,
         );
         if camera_path.len() == 1 {
-            return self.scene.background;
+            return self.scene.background.eval(ray.dir);
         }
         if camera_path.len() == 2 && camera_path[1].kind.is_light() {
             return camera_path[1].throughput;
         }
-        let light_path = self.gen_light_path();
+        let light_path = self.gen_light_path(ray.time);
         if _debug {
             for v in &camera_path {
                 println!(
@@ -144,20 +144,20 @@ impl<'s> BDPT<'s> {
         let d_norm = d.normalize();
         let vt_cos = v_cam.normal.dot(&d_norm).abs();
         let vs_cos = v_light.normal.dot(&d_norm).abs();
-        let ft = v_cam.f(Ray {
-            origin: camera_path[t - 2].point,
-            dir: camera_path[t - 1].point - camera_path[t - 2].point,
-        });
-        let fs = v_light.f(Ray {
-            origin: camera_path[t - 1].point,
-            dir: light_path[s - 1].point - camera_path[t - 1].point,
-        });
+        let ft = v_cam.f(Ray::new(
+            camera_path[t - 2].point,
+            camera_path[t - 1].point - camera_path[t - 2].point,
+        ));
+        let fs = v_light.f(Ray::new(
+            camera_path[t - 1].point,
+            light_path[s - 1].point - camera_path[t - 1].point,
+        ));
         let g = v_cam.throughput.component_mul(&v_light.throughput);
         (g, w_st)
     }
 
-    fn gen_light_path(&self) -> Vec<Vertex> {
-        let (ray, normal, color) = self.scene.lights.emit();
+    fn gen_light_path(&self, time: f64) -> Vec<Vertex> {
+        let (ray, normal, color) = self.scene.emit(time);
         self.random_walk(WalkKind::Light, ray, normal, color, MAX_LIGHT_DEPTH)
     }
 
@@ -215,14 +215,14 @@ impl<'s> BDPT<'s> {
             }
             match self.scene.hit(ray, 0.0001..f64::INFINITY) {
                 None => {
-                    throughput =
-                        throughput.component_mul(&self.scene.background);
+                    throughput = throughput
+                        .component_mul(&self.scene.background.eval(ray.dir));
                     prev.throughput = throughput;
                     prev.pdf_rev = 0.0;
                     break;
                 }
                 Some(hit) => {
-                    let emit = hit.material.emitted(&hit);
+                    let emit = hit.material.emitted(&ray, &hit);
                     if emit.norm_squared() > 0.0 {
                         throughput = throughput.component_mul(&emit);
                         prev.pdf_rev = 0.0;
@@ -296,10 +296,8 @@ impl<'s> BDPT<'s> {
                             }
                             unimplemented!("Specular unimplemented");
                         } else if let Some(scatter_pdf) = scatter.pdf {
-                            let scatter_ray = Ray {
-                                origin: hit.point,
-                                dir: scatter_pdf.gen(),
-                            };
+                            let scatter_ray =
+                                Ray::new(hit.point, scatter_pdf.gen());
                             let g_fwd =
                                 self.g(hit.point, hit.normal, prev.point);
                             let pdf_fwd = scatter_pdf.value(scatter_ray.dir);
@@ -375,10 +373,7 @@ impl<'s> BDPT<'s> {
         let eps = 0.001;
         let (p1, p2) =
             (v1.point + eps * *v1.normal, v2.point + eps * *v2.normal);
-        let ray = Ray {
-            origin: p1,
-            dir: p2 - p1,
-        };
+        let ray = Ray::new(p1, p2 - p1);
         let tmin = 0.0;
         let tmax = 1.0;
         self.scene.hit(ray, tmin..tmax)
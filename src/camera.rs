@@ -1,18 +1,135 @@
+use crate::distribution::Distribution2D;
 use crate::geometry::*;
 use crate::util::*;
+use serde::Deserialize;
+use std::f64::consts::PI;
 
+/// Generates the primary rays for points (`u`, `v`) in [0, 1]^2 on the
+/// image, with `v` increasing upwards.
 pub struct Camera {
     origin: Point,
-    lower_left: Point,
-    horiz: Vector,
-    vert: Vector,
+    /// Right, up and backwards axes of the camera.
     u: Vector,
     v: Vector,
-    lens_radius: f64,
+    w: Vector,
+    aspect_ratio: f64,
+    projection: Projection,
+    /// Scale from radiance to pixel values.
+    pub exposure: f64,
+    /// Times at which the shutter opens and closes, between which rays are
+    /// spread to blur moving objects.
+    pub shutter: (f64, f64),
+}
+
+pub enum Projection {
+    /// Thin lens perspective projection.
+    Perspective {
+        lower_left: Point,
+        horiz: Vector,
+        vert: Vector,
+        lens_radius: f64,
+        aperture: Aperture,
+        /// Offset of the lens barrel's front opening at the corners of the
+        /// image, relative to the radius of the aperture. Light from off
+        /// axis points is cut off where the two overlap, darkening the
+        /// corners and giving their out of focus highlights a cat's eye
+        /// shape.
+        cat_eye: f64,
+    },
+    /// Parallel rays along the view direction, across a view `height` in
+    /// scene units.
+    Orthographic { height: f64 },
+    /// Fisheye lens whose image circle fills the shorter side of the image
+    /// and covers a field of view of `fov` radians across its diameter.
+    Fisheye { fov: f64, mapping: FisheyeMapping },
+    /// Every direction around the camera, by longitude across the image
+    /// and latitude up it, as used for light probes and VR.
+    Equirectangular,
+    /// Equirectangular panoramas for the left and right eyes, above and
+    /// below each other, seen from opposite sides of a circle of diameter
+    /// `ipd` so that every direction shows stereo parallax.
+    OmniStereo { ipd: f64 },
+}
+
+/// Shape of the lens aperture, which out of focus highlights take on.
+pub enum Aperture {
+    Disc,
+    /// Regular polygon formed by the given number of blades, rotated by an
+    /// angle in radians.
+    Polygon(u32, f64),
+    /// Opening whose transmission is given by the brightness of an image
+    /// filling the square around the disc.
+    Image(Distribution2D),
+}
+
+impl Aperture {
+    /// Samples a point uniformly over the aperture, within the unit disc.
+    fn sample(&self) -> (f64, f64) {
+        match self {
+            Aperture::Disc => {
+                let r = random().sqrt();
+                let theta = 2.0 * PI * random();
+                (r * theta.cos(), r * theta.sin())
+            }
+            &Aperture::Polygon(blades, rotation) => {
+                // Pick one of the triangles between the center and each
+                // edge, and a point within it.
+                let edge = (random() * blades as f64) as u32 % blades;
+                let angle =
+                    |i: u32| rotation + 2.0 * PI * i as f64 / blades as f64;
+                let (a, b) = (angle(edge), angle(edge + 1));
+                let (mut s, mut t) = (random(), random());
+                if s + t > 1.0 {
+                    s = 1.0 - s;
+                    t = 1.0 - t;
+                }
+                (s * a.cos() + t * b.cos(), s * a.sin() + t * b.sin())
+            }
+            Aperture::Image(distribution) => {
+                let ((x, y), _) = distribution.sample((random(), random()));
+                (2.0 * x - 1.0, 1.0 - 2.0 * y)
+            }
+        }
+    }
+}
+
+/// How a fisheye lens maps the angle from its axis to the distance from the
+/// center of its image circle.
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+pub enum FisheyeMapping {
+    /// Distance proportional to the angle.
+    Equidistant,
+    /// Distance proportional to the sine of half the angle, which keeps
+    /// areas in proportion to solid angle.
+    Equisolid,
 }
 
 impl Camera {
+    /// Creates a camera at `from` looking at `at`, with `up` giving the
+    /// direction of the top of the image.
     pub fn new(
+        from: Point,
+        at: Point,
+        up: Vector,
+        aspect_ratio: f64,
+        projection: Projection,
+    ) -> Camera {
+        let (u, v, w) = axes(from, at, up);
+        Camera {
+            origin: from,
+            u,
+            v,
+            w,
+            aspect_ratio,
+            projection,
+            exposure: 1.0,
+            shutter: (0.0, 0.0),
+        }
+    }
+
+    /// Creates a thin lens perspective camera with a vertical field of view
+    /// of `vfov` degrees, focused at `focus_dist`.
+    pub fn perspective(
         from: Point,
         at: Point,
         up: Vector,
@@ -26,43 +143,233 @@ impl Camera {
         let vp_height: f64 = 2.0 * h;
         let vp_width: f64 = aspect_ratio * vp_height;
 
-        let origin = from;
-
-        // Points from target position to camera.
-        let w = (from - at).normalize();
-        // Horizontal axis of the camera plane.
-        let u = up.cross(&w).normalize();
-        // Projects the up vector onto the plane normal to the w vector.
-        let v = w.cross(&u);
+        let (u, v, w) = axes(from, at, up);
 
         // Move the viewport focus_dist away from the camera origin
         // to allow simulating DoF.
         let horiz = focus_dist * u * vp_width;
         let vert = focus_dist * v * vp_height;
 
-        let lower_left = origin - (horiz / 2.0) - (vert / 2.0) - focus_dist * w;
+        let lower_left = from - (horiz / 2.0) - (vert / 2.0) - focus_dist * w;
 
-        Camera {
-            origin,
+        let projection = Projection::Perspective {
             lower_left,
             horiz,
             vert,
-            u,
-            v,
             lens_radius: aperture / 2.0,
+            aperture: Aperture::Disc,
+            cat_eye: 0.0,
+        };
+        Camera::new(from, at, up, aspect_ratio, projection)
+    }
+
+    /// Gives a perspective camera an aperture of the given shape, with
+    /// `cat_eye` vignetting.
+    pub fn with_aperture(mut self, shape: Aperture, cat_eye: f64) -> Camera {
+        if let Projection::Perspective {
+            aperture,
+            cat_eye: c,
+            ..
+        } = &mut self.projection
+        {
+            *aperture = shape;
+            *c = cat_eye;
         }
+        self
     }
 
-    pub fn get_ray(&self, u: f64, v: f64) -> Ray {
-        let rd = self.lens_radius * random_in_unit_disc();
-        // let rd = Vector::zeros();
-        let offset: Vector = self.u * rd.x + self.v * rd.y;
-        let dir = (self.lower_left + (u * self.horiz) + (v * self.vert))
-            - self.origin
-            - offset;
-        Ray {
-            origin: self.origin + offset,
+    /// Generates a ray through the image plane at (`u`, `v`), with
+    /// differentials offset by (`du`, `dv`) to estimate the pixel footprint.
+    /// Points outside the image circle of a fisheye lens have no ray, nor
+    /// do rays blocked by the barrel of a lens with cat's eye vignetting.
+    pub fn get_ray(&self, u: f64, v: f64, (du, dv): (f64, f64)) -> Option<Ray> {
+        let (open, close) = self.shutter;
+        let time = open + random() * (close - open);
+        if let Projection::Perspective {
+            lower_left,
+            horiz,
+            vert,
+            lens_radius,
+            ref aperture,
+            cat_eye,
+        } = self.projection
+        {
+            let (x, y) = aperture.sample();
+            if cat_eye > 0.0 {
+                // Position on the image relative to its corners.
+                let half_diagonal =
+                    0.5 * (self.aspect_ratio.powi(2) + 1.0).sqrt();
+                let shift = cat_eye / half_diagonal;
+                let sx = shift * (u - 0.5) * self.aspect_ratio;
+                let sy = shift * (v - 0.5);
+                if (x - sx).powi(2) + (y - sy).powi(2) > 1.0 {
+                    return None;
+                }
+            }
+            let offset: Vector = lens_radius * (self.u * x + self.v * y);
+            let origin = self.origin + offset;
+            let target = lower_left + (u * horiz) + (v * vert);
+            let dir = target - origin;
+            return Some(Ray {
+                origin,
+                dir,
+                diff: Some(RayDiff {
+                    dx_origin: origin,
+                    dx_dir: dir + du * horiz,
+                    dy_origin: origin,
+                    dy_dir: dir + dv * vert,
+                }),
+                wavelength: None,
+                time,
+            });
+        }
+
+        let (origin, dir) = self.project(u, v)?;
+        // Offset rays may fall outside a fisheye's image circle at its edge,
+        // in which case the differentials are left out.
+        let diff = match (self.project(u + du, v), self.project(u, v + dv)) {
+            (Some((dx_origin, dx_dir)), Some((dy_origin, dy_dir))) => {
+                Some(RayDiff {
+                    dx_origin,
+                    dx_dir,
+                    dy_origin,
+                    dy_dir,
+                })
+            }
+            _ => None,
+        };
+        Some(Ray {
+            origin,
             dir,
+            diff,
+            wavelength: None,
+            time,
+        })
+    }
+
+    /// Origin and direction of the ray through (`u`, `v`) for projections
+    /// without a lens.
+    fn project(&self, u: f64, v: f64) -> Option<(Point, Vector)> {
+        match self.projection {
+            Projection::Perspective { .. } => unreachable!(),
+            Projection::Orthographic { height } => {
+                let offset = height
+                    * ((u - 0.5) * self.aspect_ratio * self.u
+                        + (v - 0.5) * self.v);
+                Some((self.origin + offset, -self.w))
+            }
+            Projection::Fisheye { fov, mapping } => {
+                // Coordinates relative to the radius of the image circle.
+                let radius = 0.5 * self.aspect_ratio.min(1.0);
+                let x = (u - 0.5) * self.aspect_ratio / radius;
+                let y = (v - 0.5) / radius;
+                let r = (x * x + y * y).sqrt();
+                if r > 1.0 {
+                    return None;
+                }
+                let theta = match mapping {
+                    FisheyeMapping::Equidistant => r * fov / 2.0,
+                    FisheyeMapping::Equisolid => {
+                        2.0 * (r * (fov / 4.0).sin()).asin()
+                    }
+                };
+                let phi = y.atan2(x);
+                let dir = theta.sin()
+                    * (phi.cos() * self.u + phi.sin() * self.v)
+                    - theta.cos() * self.w;
+                Some((self.origin, dir))
+            }
+            Projection::Equirectangular => {
+                Some((self.origin, self.panorama_dir(u, v)))
+            }
+            Projection::OmniStereo { ipd } => {
+                // The left eye fills the top half of the image.
+                let (v, side) = if v >= 0.5 {
+                    (2.0 * v - 1.0, -1.0)
+                } else {
+                    (2.0 * v, 1.0)
+                };
+                let phi = 2.0 * PI * (u - 0.5);
+                let elevation = PI * (v - 0.5);
+                let right = phi.cos() * self.u + phi.sin() * self.w;
+                // Shrink the baseline towards the poles, where the eyes
+                // would otherwise see each other's views swapped.
+                let offset = side * 0.5 * ipd * elevation.cos() * right;
+                Some((self.origin + offset, self.panorama_dir(u, v)))
+            }
         }
     }
+
+    /// Direction at longitude `u` and latitude `v`, with the view direction
+    /// at the center.
+    fn panorama_dir(&self, u: f64, v: f64) -> Vector {
+        let phi = 2.0 * PI * (u - 0.5);
+        let elevation = PI * (v - 0.5);
+        elevation.cos() * (phi.sin() * self.u - phi.cos() * self.w)
+            + elevation.sin() * self.v
+    }
+}
+
+/// Right, up and backwards axes of a camera at `from` looking at `at`.
+fn axes(from: Point, at: Point, up: Vector) -> (Vector, Vector, Vector) {
+    // Points from target position to camera.
+    let w = (from - at).normalize();
+    // Horizontal axis of the camera plane.
+    let u = up.cross(&w).normalize();
+    // Projects the up vector onto the plane normal to the w vector.
+    let v = w.cross(&u);
+    (u, v, w)
+}
+
+#[cfg(test)]
+#[test]
+fn panoramic_projections_cover_every_direction() {
+    let from = Point::new(0.0, 1.0, 0.0);
+    let at = Point::new(0.0, 1.0, 5.0);
+    let camera =
+        |projection| Camera::new(from, at, Vector::y(), 2.0, projection);
+    let dir = |camera: &Camera, u, v| {
+        camera
+            .get_ray(u, v, (0.0, 0.0))
+            .map(|ray| ray.dir.normalize())
+    };
+    let near = |a: Vector, b: Vector| (a - b).norm() < 1e-9;
+
+    let equirect = camera(Projection::Equirectangular);
+    assert!(near(dir(&equirect, 0.5, 0.5).unwrap(), Vector::z()));
+    assert!(near(dir(&equirect, 0.0, 0.5).unwrap(), -Vector::z()));
+    assert!(near(dir(&equirect, 0.75, 0.5).unwrap(), -Vector::x()));
+    assert!(near(dir(&equirect, 0.3, 1.0).unwrap(), Vector::y()));
+
+    let fisheye = camera(Projection::Fisheye {
+        fov: PI,
+        mapping: FisheyeMapping::Equisolid,
+    });
+    assert!(near(dir(&fisheye, 0.5, 0.5).unwrap(), Vector::z()));
+    assert!(near(dir(&fisheye, 0.5, 1.0).unwrap(), Vector::y()));
+    assert!(dir(&fisheye, 0.0, 0.5).is_none());
+
+    // Both eyes look the same way from either side of the camera.
+    let stereo = camera(Projection::OmniStereo { ipd: 0.1 });
+    let left = stereo.get_ray(0.5, 0.75, (0.0, 0.0)).unwrap();
+    let right = stereo.get_ray(0.5, 0.25, (0.0, 0.0)).unwrap();
+    assert!(near(left.dir, right.dir));
+    assert!(near(left.origin - right.origin, 0.1 * Vector::x()));
+}
+
+#[cfg(test)]
+#[test]
+fn aperture_samples_stay_inside_its_shape() {
+    // A square aperture from four blades, and an image with only its
+    // bottom right quarter open.
+    let square = Aperture::Polygon(4, PI / 4.0);
+    let image =
+        Aperture::Image(Distribution2D::new(&[0.0, 0.0, 0.0, 1.0], 2, 2));
+    let bound = 0.5f64.sqrt() + 1e-9;
+    for _ in 0..1000 {
+        let (x, y) = square.sample();
+        assert!(x.abs() <= bound && y.abs() <= bound, "{} {}", x, y);
+        let (x, y) = image.sample();
+        assert!(x >= 0.0 && y <= 0.0, "{} {}", x, y);
+    }
 }
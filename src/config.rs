@@ -1,4 +1,5 @@
 use std::path::PathBuf;
+use std::str::FromStr;
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
@@ -21,4 +22,41 @@ pub struct Config {
 
     #[structopt(long, default_value = "360")]
     pub height: usize,
+
+    #[structopt(long, help = "Trace sampled wavelengths instead of RGB")]
+    pub spectral: bool,
+
+    #[structopt(
+        long,
+        help = "Render a range of animation frames, such as 1-48, saving each \
+                to the output with its number appended"
+    )]
+    pub frames: Option<Frames>,
+}
+
+/// Inclusive range of animation frames.
+#[derive(Debug, Copy, Clone)]
+pub struct Frames {
+    pub first: u32,
+    pub last: u32,
+}
+
+impl FromStr for Frames {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Frames, String> {
+        let parse = |n: &str| {
+            n.trim()
+                .parse::<u32>()
+                .map_err(|_| format!("invalid frame `{}`", n))
+        };
+        let (first, last) = match s.find('-') {
+            Some(i) => (parse(&s[..i])?, parse(&s[i + 1..])?),
+            None => (parse(s)?, parse(s)?),
+        };
+        if first > last {
+            return Err(format!("frames {} end before they start", s));
+        }
+        Ok(Frames { first, last })
+    }
 }
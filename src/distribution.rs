@@ -0,0 +1,139 @@
+/// Piecewise-constant probability distribution over [0, 1), built from
+/// non-negative function values over equally sized intervals.
+pub struct Distribution1D {
+    func: Vec<f64>,
+    /// Running sum of the normalized function, with `cdf[0] == 0` and
+    /// `cdf[n] == 1`.
+    cdf: Vec<f64>,
+    /// Integral of the function over [0, 1).
+    integral: f64,
+}
+
+impl Distribution1D {
+    pub fn new(func: Vec<f64>) -> Distribution1D {
+        let n = func.len();
+        let mut cdf = vec![0.0; n + 1];
+        for i in 0..n {
+            cdf[i + 1] = cdf[i] + func[i] / n as f64;
+        }
+        let integral = cdf[n];
+        for (i, c) in cdf.iter_mut().enumerate().skip(1) {
+            // Fall back to a uniform distribution if the function is zero.
+            *c = if integral > 0.0 {
+                *c / integral
+            } else {
+                i as f64 / n as f64
+            };
+        }
+        Distribution1D {
+            func,
+            cdf,
+            integral,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.func.len()
+    }
+
+    pub fn integral(&self) -> f64 {
+        self.integral
+    }
+
+    /// Maps `u` in [0, 1) to a point in [0, 1) distributed proportionally to
+    /// the function, returning it with its density and the interval index.
+    pub fn sample(&self, u: f64) -> (f64, f64, usize) {
+        // Binary search for the interval with cdf[i] <= u < cdf[i + 1],
+        // which skips over intervals of zero probability.
+        let (mut lo, mut hi) = (0, self.len());
+        while hi - lo > 1 {
+            let mid = (lo + hi) / 2;
+            if self.cdf[mid] <= u {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+        let i = lo;
+        let width = self.cdf[i + 1] - self.cdf[i];
+        let offset = if width > 0.0 {
+            (u - self.cdf[i]) / width
+        } else {
+            0.0
+        };
+        let x = (i as f64 + offset) / self.len() as f64;
+        (x, self.pdf_index(i), i)
+    }
+
+    /// Chooses an interval index with probability proportional to its
+    /// function value, returning it with that probability.
+    pub fn sample_discrete(&self, u: f64) -> (usize, f64) {
+        let (_, pdf, i) = self.sample(u);
+        (i, pdf / self.len() as f64)
+    }
+
+    /// Density of `sample` at a point in interval `i`.
+    pub fn pdf_index(&self, i: usize) -> f64 {
+        if self.integral > 0.0 {
+            self.func[i] / self.integral
+        } else {
+            1.0
+        }
+    }
+
+    /// Density of `sample` at `x` in [0, 1).
+    pub fn pdf(&self, x: f64) -> f64 {
+        let i = ((x * self.len() as f64) as usize).min(self.len() - 1);
+        self.pdf_index(i)
+    }
+}
+
+/// Piecewise-constant distribution over [0, 1)^2, sampled by choosing a row
+/// from the marginal distribution and then a column within it.
+pub struct Distribution2D {
+    rows: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    /// Builds the distribution from `width * height` values in row-major
+    /// order.
+    pub fn new(func: &[f64], width: usize, height: usize) -> Distribution2D {
+        let rows: Vec<Distribution1D> = func
+            .chunks(width)
+            .take(height)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect();
+        let marginal =
+            Distribution1D::new(rows.iter().map(|r| r.integral()).collect());
+        Distribution2D { rows, marginal }
+    }
+
+    /// Samples a point `(x, y)` and returns it with its density.
+    pub fn sample(&self, (u, v): (f64, f64)) -> ((f64, f64), f64) {
+        let (y, pdf_y, row) = self.marginal.sample(v);
+        let (x, pdf_x, _) = self.rows[row].sample(u);
+        ((x, y), pdf_x * pdf_y)
+    }
+
+    pub fn pdf(&self, (x, y): (f64, f64)) -> f64 {
+        let n = self.rows.len();
+        let row = ((y * n as f64) as usize).min(n - 1);
+        self.marginal.pdf_index(row) * self.rows[row].pdf(x)
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn samples_follow_function_values() {
+    let dist = Distribution2D::new(&[0.0, 1.0, 3.0, 0.0, 0.0, 4.0], 3, 2);
+    let ((x, y), pdf) = dist.sample((0.5, 0.25));
+    // Both rows carry equal weight, and the third column of the first row
+    // holds three quarters of its weight in a third of its width.
+    assert!(y < 0.5 && x > 2.0 / 3.0, "{} {}", x, y);
+    assert!((pdf - 0.75 * 3.0).abs() < 1e-9);
+    assert!((dist.pdf((x, y)) - pdf).abs() < 1e-9);
+    let ((_, y), _) = dist.sample((0.1, 0.75));
+    assert!(y >= 0.5);
+    assert_eq!(dist.pdf((0.1, 0.1)), 0.0);
+}
@@ -36,6 +36,20 @@ impl AABB {
         end > start
     }
 
+    /// Part of `range` over which the ray is inside the box.
+    pub fn clip(&self, ray: Ray, range: Range<f64>) -> Option<Range<f64>> {
+        let t0s = (self.min - ray.origin).component_div(&ray.dir);
+        let t1s = (self.max - ray.origin).component_div(&ray.dir);
+        let (t_in, t_out) = t0s.inf_sup(&t1s);
+        let start = f64::max(range.start, t_in.max());
+        let end = f64::min(range.end, t_out.min());
+        if end > start {
+            Some(start..end)
+        } else {
+            None
+        }
+    }
+
     pub fn cmp_axis(&self, other: &AABB, axis: usize) -> Ordering {
         self.min[axis].partial_cmp(&other.min[axis]).unwrap()
     }
@@ -0,0 +1,317 @@
+use serde::Deserialize;
+
+/// Keyframed changes to a scene over time. Each track sets a `target` in
+/// the scene description, such as `camera.from`, `camera.vfov`,
+/// `objects.<name>.translate`, `objects.<name>.rotate` or
+/// `materials.<name>.<parameter>`, to a value interpolated between its keys.
+/// Objects are found by their `name`, and times are in seconds, with frames
+/// `fps` apart.
+#[derive(Debug, Deserialize)]
+pub(super) struct AnimationDesc {
+    fps: Option<f64>,
+    tracks: Vec<TrackDesc>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TrackDesc {
+    target: String,
+    interpolation: Option<Interpolation>,
+    keys: Vec<KeyDesc>,
+}
+
+#[derive(Debug, Deserialize)]
+struct KeyDesc {
+    time: f64,
+    value: ValueDesc,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ValueDesc {
+    Scalar(f64),
+    Vector(Vec<f64>),
+}
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+enum Interpolation {
+    /// Straight lines between keys.
+    Linear,
+    /// Cubic Bezier curves passing smoothly through the keys, which ease in
+    /// and out of the first and last ones.
+    Bezier,
+}
+
+/// Part of the scene description set by a track.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Target {
+    Camera(String),
+    Object(String, String),
+    Material(String, String),
+}
+
+impl Target {
+    fn parse(target: &str) -> Result<Target, String> {
+        let parts: Vec<&str> = target.split('.').collect();
+        match parts.as_slice() {
+            ["camera", field] => Ok(Target::Camera(field.to_string())),
+            ["objects", name, field]
+                if *field == "translate" || *field == "rotate" =>
+            {
+                Ok(Target::Object(name.to_string(), field.to_string()))
+            }
+            ["materials", name, field] => {
+                Ok(Target::Material(name.to_string(), field.to_string()))
+            }
+            _ => Err(format!("cannot animate `{}`", target)),
+        }
+    }
+
+    /// Sets the target in the raw scene description to `value`, rounded to
+    /// integers where the description already holds integers.
+    fn set(
+        &self,
+        raw: &mut toml::Value,
+        value: toml::Value,
+    ) -> Result<(), String> {
+        let table = match self {
+            Target::Camera(..) => raw.get_mut("camera"),
+            Target::Object(name, _) => {
+                raw.get_mut("objects").and_then(|objects| {
+                    objects.as_array_mut()?.iter_mut().find(|obj| {
+                        obj.get("name").and_then(toml::Value::as_str)
+                            == Some(name)
+                    })
+                })
+            }
+            Target::Material(name, _) => raw
+                .get_mut("materials")
+                .and_then(|materials| materials.get_mut(name)),
+        };
+        let table = table.and_then(toml::Value::as_table_mut);
+        match (self, table) {
+            (Target::Camera(field), Some(table))
+            | (Target::Object(_, field), Some(table))
+            | (Target::Material(_, field), Some(table)) => {
+                let value = match table.get(field) {
+                    Some(old) => like(old, value),
+                    None => value,
+                };
+                table.insert(field.clone(), value);
+                Ok(())
+            }
+            (Target::Camera(..), None) => Err("scene has no camera".into()),
+            (Target::Object(name, _), None) => {
+                Err(format!("unknown object `{}`", name))
+            }
+            (Target::Material(name, _), None) => {
+                Err(format!("unknown material `{}`", name))
+            }
+        }
+    }
+}
+
+/// Converts floats in `value` to integers where `old` holds integers, so
+/// that integer parameters can be animated.
+fn like(old: &toml::Value, value: toml::Value) -> toml::Value {
+    match (old, value) {
+        (toml::Value::Integer(_), toml::Value::Float(x)) => {
+            toml::Value::Integer(x.round() as i64)
+        }
+        (toml::Value::Array(old), toml::Value::Array(values))
+            if old.len() == values.len() =>
+        {
+            toml::Value::Array(
+                old.iter().zip(values).map(|(o, v)| like(o, v)).collect(),
+            )
+        }
+        (_, value) => value,
+    }
+}
+
+pub(super) struct Track {
+    pub target: Target,
+    interpolation: Interpolation,
+    /// Keys in increasing order of time.
+    keys: Vec<(f64, Vec<f64>)>,
+    /// Whether the values are numbers rather than vectors.
+    scalar: bool,
+}
+
+impl Track {
+    fn new(desc: &TrackDesc) -> Result<Track, String> {
+        let target = Target::parse(&desc.target)?;
+        let error = |msg: &str| format!("track `{}` {}", desc.target, msg);
+        let mut keys: Vec<(f64, Vec<f64>)> = desc
+            .keys
+            .iter()
+            .map(|key| match &key.value {
+                ValueDesc::Scalar(x) => (key.time, vec![*x]),
+                ValueDesc::Vector(v) => (key.time, v.clone()),
+            })
+            .collect();
+        if keys.iter().any(|key| !key.0.is_finite()) {
+            return Err(error("has a key at a non-finite time"));
+        }
+        keys.sort_by(|a, b| a.0.total_cmp(&b.0));
+        if keys.is_empty() {
+            return Err(error("has no keys"));
+        }
+        if keys.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+            return Err(error("has two keys at the same time"));
+        }
+        if keys.iter().any(|key| key.1.len() != keys[0].1.len()) {
+            return Err(error("has keys of different lengths"));
+        }
+        Ok(Track {
+            target,
+            interpolation: desc.interpolation.unwrap_or(Interpolation::Linear),
+            keys,
+            scalar: matches!(desc.keys[0].value, ValueDesc::Scalar(..)),
+        })
+    }
+
+    /// Value of the track at `time`, held at the first and last keys.
+    pub fn eval(&self, time: f64) -> Vec<f64> {
+        let keys = &self.keys;
+        let i = keys.iter().filter(|key| key.0 <= time).count();
+        if i == 0 || i == keys.len() {
+            return keys[i.saturating_sub(1)].1.clone();
+        }
+        let ((t0, a), (t1, b)) = (&keys[i - 1], &keys[i]);
+        let s = (time - t0) / (t1 - t0);
+        match self.interpolation {
+            Interpolation::Linear => {
+                a.iter().zip(b).map(|(a, b)| a + s * (b - a)).collect()
+            }
+            Interpolation::Bezier => {
+                // Tangents follow the neighbouring keys, as for Catmull-Rom
+                // splines, and are flat at either end.
+                let tangent = |k: usize, c: usize| {
+                    if k == 0 || k + 1 == keys.len() {
+                        0.0
+                    } else {
+                        (keys[k + 1].1[c] - keys[k - 1].1[c])
+                            / (keys[k + 1].0 - keys[k - 1].0)
+                    }
+                };
+                let h = (t1 - t0) / 3.0;
+                (0..a.len())
+                    .map(|c| {
+                        let p1 = a[c] + h * tangent(i - 1, c);
+                        let p2 = b[c] - h * tangent(i, c);
+                        let r = 1.0 - s;
+                        r * r * r * a[c]
+                            + 3.0 * r * r * s * p1
+                            + 3.0 * r * s * s * p2
+                            + s * s * s * b[c]
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    /// Sets the target in the raw scene description to the value `eval`
+    /// returned.
+    pub fn apply(
+        &self,
+        raw: &mut toml::Value,
+        value: &[f64],
+    ) -> Result<(), String> {
+        let value = if self.scalar {
+            toml::Value::Float(value[0])
+        } else {
+            toml::Value::Array(
+                value.iter().map(|&x| toml::Value::Float(x)).collect(),
+            )
+        };
+        self.target.set(raw, value)
+    }
+}
+
+pub(super) struct Animation {
+    pub fps: f64,
+    pub tracks: Vec<Track>,
+}
+
+impl Animation {
+    pub fn new(desc: Option<&AnimationDesc>) -> Result<Animation, String> {
+        let desc = match desc {
+            Some(desc) => desc,
+            None => {
+                return Ok(Animation {
+                    fps: 24.0,
+                    tracks: vec![],
+                })
+            }
+        };
+        let fps = desc.fps.unwrap_or(24.0);
+        if fps <= 0.0 {
+            return Err(format!("animation has {} frames per second", fps));
+        }
+        let tracks = desc
+            .tracks
+            .iter()
+            .map(Track::new)
+            .collect::<Result<_, _>>()?;
+        Ok(Animation { fps, tracks })
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn tracks_pass_through_their_keys() {
+    let desc: AnimationDesc = toml::from_str(
+        r#"
+        [[tracks]]
+        target = "camera.vfov"
+        keys = [{ time = 0, value = 40 }, { time = 2, value = 20 }]
+        [[tracks]]
+        target = "objects.box.translate"
+        interpolation = "Bezier"
+        keys = [
+            { time = 0, value = [0, 0, 0] },
+            { time = 1, value = [1, 2, 0] },
+            { time = 3, value = [5, 2, 0] },
+        ]
+        "#,
+    )
+    .unwrap();
+    let animation = Animation::new(Some(&desc)).unwrap();
+    let (linear, bezier) = (&animation.tracks[0], &animation.tracks[1]);
+    assert_eq!(linear.eval(-1.0), vec![40.0]);
+    assert_eq!(linear.eval(0.5), vec![35.0]);
+    assert_eq!(linear.eval(3.0), vec![20.0]);
+    assert_eq!(bezier.eval(1.0), vec![1.0, 2.0, 0.0]);
+    // The curve eases out of the first key, and overshoots the flat y
+    // values after the second because of the tangent leading into it.
+    let early = bezier.eval(0.1);
+    assert!(early[0] > 0.0 && early[0] < 0.1, "{:?}", early);
+    assert!(bezier.eval(1.5)[1] > 2.0);
+    assert_eq!(
+        Target::parse("objects.box.translate").unwrap(),
+        Target::Object("box".into(), "translate".into())
+    );
+    assert!(Target::parse("objects.box.radius").is_err());
+
+    // Integer parameters stay integers.
+    let mut raw: toml::Value =
+        toml::from_str("[materials.shiny]\nshininess = 10").unwrap();
+    Target::parse("materials.shiny.shininess")
+        .unwrap()
+        .set(&mut raw, toml::Value::Float(31.6))
+        .unwrap();
+    assert_eq!(
+        raw["materials"]["shiny"]["shininess"].as_integer(),
+        Some(32)
+    );
+
+    let nan: AnimationDesc = toml::from_str(
+        r#"
+        [[tracks]]
+        target = "camera.vfov"
+        keys = [{ time = nan, value = 40 }, { time = 2, value = 20 }]
+        "#,
+    )
+    .unwrap();
+    assert!(Animation::new(Some(&nan)).is_err());
+}
@@ -7,59 +7,75 @@ use std::ops::Range;
 pub struct Block {
     material: Arc<Material>,
     sides: Arc<BVHNode>,
+    /// The same sides, for sampling them as lights.
+    faces: Vec<Arc<Rect>>,
+    area: f64,
 }
 
 impl Block {
     pub fn new(material: Arc<Material>, p1: Point, p2: Point) -> Arc<Block> {
-        let mut sides: Vec<Arc<dyn Hittable>> = vec![];
-        sides.push(Rect::new(
-            material.clone(),
-            RectAxis::XY,
-            (p1.x, p1.y),
-            (p2.x, p2.y),
-            p1.z,
-        ));
-        sides.push(Rect::new(
-            material.clone(),
-            RectAxis::XY,
-            (p1.x, p1.y),
-            (p2.x, p2.y),
-            p2.z,
-        ));
-
-        sides.push(Rect::new(
-            material.clone(),
-            RectAxis::XZ,
-            (p1.x, p1.z),
-            (p2.x, p2.z),
-            p1.y,
-        ));
-        sides.push(Rect::new(
-            material.clone(),
-            RectAxis::XZ,
-            (p1.x, p1.z),
-            (p2.x, p2.z),
-            p2.y,
-        ));
-
-        sides.push(Rect::new(
-            material.clone(),
-            RectAxis::YZ,
-            (p1.y, p1.z),
-            (p2.y, p2.z),
-            p1.x,
-        ));
-        sides.push(Rect::new(
-            material.clone(),
-            RectAxis::YZ,
-            (p1.y, p1.z),
-            (p2.y, p2.z),
-            p2.x,
-        ));
+        let (min, max) = (p1.inf(&p2), p1.sup(&p2));
+        // The faces on the minimum side are flipped so that the fronts of
+        // all faces point outwards.
+        let faces = vec![
+            Rect::new(
+                material.clone(),
+                RectAxis::XY,
+                (min.x, min.y),
+                (max.x, max.y),
+                min.z,
+            )
+            .flipped(),
+            Rect::new(
+                material.clone(),
+                RectAxis::XY,
+                (min.x, min.y),
+                (max.x, max.y),
+                max.z,
+            ),
+            Rect::new(
+                material.clone(),
+                RectAxis::XZ,
+                (min.x, min.z),
+                (max.x, max.z),
+                min.y,
+            )
+            .flipped(),
+            Rect::new(
+                material.clone(),
+                RectAxis::XZ,
+                (min.x, min.z),
+                (max.x, max.z),
+                max.y,
+            ),
+            Rect::new(
+                material.clone(),
+                RectAxis::YZ,
+                (min.y, min.z),
+                (max.y, max.z),
+                min.x,
+            )
+            .flipped(),
+            Rect::new(
+                material.clone(),
+                RectAxis::YZ,
+                (min.y, min.z),
+                (max.y, max.z),
+                max.x,
+            ),
+        ];
 
+        let size = max - min;
+        let area = 2.0 * (size.x * size.y + size.x * size.z + size.y * size.z);
+        let sides = faces
+            .iter()
+            .map(|face| face.clone() as Arc<dyn Hittable>)
+            .collect();
         Arc::new(Block {
             material,
             sides: BVHNode::from_hittables(sides),
+            faces,
+            area,
         })
     }
 }
@@ -79,4 +95,30 @@ impl Hittable for Block {
     fn hit(&self, ray: Ray, range: Range<f64>) -> Option<Hit> {
         self.sides.hit(ray, range)
     }
+
+    fn pdf(&self, ray: Ray) -> f64 {
+        let sum: f64 = self.faces.iter().map(|face| face.pdf(ray)).sum();
+        sum / self.faces.len() as f64
+    }
+
+    fn random(&self, origin: Point, time: f64) -> Vector {
+        let i = ((random() * 6.0) as usize).min(5);
+        self.faces[i].random(origin, time)
+    }
+
+    fn emit(&self, time: f64) -> (Ray, Unit<Vector>, Color) {
+        // Choose a face in proportion to its area.
+        let mut x = random() * self.area / 2.0;
+        for face in &self.faces {
+            x -= face.area();
+            if x <= 0.0 {
+                return face.emit(time);
+            }
+        }
+        self.faces[5].emit(time)
+    }
+
+    fn power(&self) -> f64 {
+        self.material.emitted_power(self.area)
+    }
 }
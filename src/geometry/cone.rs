@@ -0,0 +1,214 @@
+use super::disk::Disk;
+use super::surface::{area_pdf, disk_bounds, emit_from};
+use super::*;
+use std::f64::consts::PI;
+
+/// Cone truncated to run from a circle at `start` to one at `end`, which is
+/// a cylinder when their radii match and comes to a point when either is 0.
+/// Caps close off the ends. The `u` coordinate runs around the axis and `v`
+/// along it, from `start` to `end`.
+pub struct Cone {
+    material: Arc<Material>,
+    start: Point,
+    /// Frame with `w` along the axis.
+    frame: ONB,
+    height: f64,
+    radius: f64,
+    /// Change in radius per unit of height.
+    slope: f64,
+    caps: Vec<Arc<Disk>>,
+    /// Area of the side, without the caps.
+    side_area: f64,
+}
+
+impl Cone {
+    pub fn new(
+        material: Arc<Material>,
+        start: Point,
+        end: Point,
+        radius: f64,
+        end_radius: f64,
+        capped: bool,
+    ) -> Arc<Cone> {
+        let axis = end - start;
+        let height = axis.norm();
+        let frame = ONB::from_w(Unit::new_normalize(axis));
+        let mut caps = vec![];
+        if capped {
+            for &(center, normal, r) in
+                &[(start, -frame.w, radius), (end, frame.w, end_radius)]
+            {
+                if r > 0.0 {
+                    caps.push(Disk::new(
+                        material.clone(),
+                        center,
+                        normal,
+                        r,
+                        0.0,
+                    ));
+                }
+            }
+        }
+        let slant = (end_radius - radius).hypot(height);
+        Arc::new(Cone {
+            material,
+            start,
+            frame,
+            height,
+            radius,
+            slope: (end_radius - radius) / height,
+            caps,
+            side_area: PI * (radius + end_radius) * slant,
+        })
+    }
+
+    pub fn area(&self) -> f64 {
+        let caps: f64 = self.caps.iter().map(|cap| cap.area()).sum();
+        self.side_area + caps
+    }
+
+    fn radius_at(&self, z: f64) -> f64 {
+        self.radius + self.slope * z
+    }
+
+    /// Point on the side at angle `phi` around the axis and height `z`,
+    /// with its outward normal.
+    fn side_point(&self, phi: f64, z: f64) -> (Point, Unit<Vector>) {
+        let (sin, cos) = phi.sin_cos();
+        let r = self.radius_at(z);
+        let local = Vector::new(r * cos, r * sin, z);
+        let normal = Vector::new(cos, sin, -self.slope);
+        (
+            self.start + self.frame.localize(local),
+            Unit::new_normalize(self.frame.localize(normal)),
+        )
+    }
+
+    /// Point chosen uniformly over the area of the side, where the circles
+    /// around the axis grow in proportion to their radii.
+    fn random_side_point(&self) -> (Point, Unit<Vector>, (f64, f64)) {
+        let end_radius = self.radius_at(self.height);
+        let z = if (end_radius - self.radius).abs() < 1e-9 {
+            random() * self.height
+        } else {
+            let (r0, r1) = (self.radius * self.radius, end_radius * end_radius);
+            let r = (r0 + random() * (r1 - r0)).sqrt();
+            fclamp((r - self.radius) / self.slope, 0.0, self.height)
+        };
+        let u = random();
+        let (point, normal) = self.side_point(2.0 * PI * u, z);
+        (point, normal, (u, z / self.height))
+    }
+
+    /// Chooses a cap in proportion to its area, or `None` for the side.
+    fn choose_cap(&self) -> Option<&Arc<Disk>> {
+        let mut x = random() * self.area();
+        if x < self.side_area {
+            return None;
+        }
+        x -= self.side_area;
+        for cap in &self.caps {
+            x -= cap.area();
+            if x < 0.0 {
+                return Some(cap);
+            }
+        }
+        self.caps.last()
+    }
+
+    fn hit_side(&self, ray: Ray, range: Range<f64>) -> Option<Hit> {
+        let o = self.frame.project(ray.origin - self.start);
+        let d = self.frame.project(ray.dir);
+        let k = self.slope;
+        let r = self.radius_at(o.z);
+        let a = d.x * d.x + d.y * d.y - k * k * d.z * d.z;
+        let half_b = o.x * d.x + o.y * d.y - k * d.z * r;
+        let c = o.x * o.x + o.y * o.y - r * r;
+        let roots = if a.abs() < 1e-12 {
+            // The ray runs parallel to the slope of the side.
+            [-c / (2.0 * half_b), f64::NAN]
+        } else {
+            let discriminant = half_b * half_b - a * c;
+            if discriminant < 0.0 {
+                return None;
+            }
+            let sqrt_disc = discriminant.sqrt();
+            let (t1, t2) =
+                ((-half_b - sqrt_disc) / a, (-half_b + sqrt_disc) / a);
+            [t1.min(t2), t1.max(t2)]
+        };
+        let t = roots.iter().copied().find(|&t| {
+            let z = o.z + t * d.z;
+            range.contains(&t) && z >= 0.0 && z <= self.height
+        })?;
+
+        let local = o + t * d;
+        let phi = local.y.atan2(local.x);
+        let u = if phi < 0.0 {
+            phi / (2.0 * PI) + 1.0
+        } else {
+            phi / (2.0 * PI)
+        };
+        let (_, normal) = self.side_point(phi, local.z);
+        let (sin, cos) = phi.sin_cos();
+        let dpdu = 2.0 * PI * Vector::new(-local.y, local.x, 0.0);
+        let dpdv = self.height * Vector::new(k * cos, k * sin, 1.0);
+        let uv = (u, local.z / self.height);
+        Some(Hit::new(ray, normal, t, &self.material, uv).with_partials(
+            ray,
+            self.frame.localize(dpdu),
+            self.frame.localize(dpdv),
+        ))
+    }
+}
+
+impl Hittable for Cone {
+    fn is_light(&self) -> bool {
+        matches!(self.material.as_ref(), Material::Emission(..))
+    }
+
+    fn bounding_box(&self) -> AABB {
+        let end = self.start + self.height * *self.frame.w;
+        AABB::containing(
+            disk_bounds(self.start, self.frame.w, self.radius),
+            disk_bounds(end, self.frame.w, self.radius_at(self.height)),
+        )
+    }
+
+    fn hit(&self, ray: Ray, range: Range<f64>) -> Option<Hit> {
+        let mut closest = self.hit_side(ray, range.clone());
+        let mut end = closest.map_or(range.end, |hit| hit.t);
+        for cap in &self.caps {
+            if let Some(hit) = cap.hit(ray, range.start..end) {
+                end = hit.t;
+                closest = Some(hit);
+            }
+        }
+        closest
+    }
+
+    fn pdf(&self, ray: Ray) -> f64 {
+        area_pdf(self, ray, self.area())
+    }
+
+    fn random(&self, origin: Point, time: f64) -> Vector {
+        match self.choose_cap() {
+            Some(cap) => cap.random(origin, time),
+            None => self.random_side_point().0 - origin,
+        }
+    }
+
+    fn emit(&self, time: f64) -> (Ray, Unit<Vector>, Color) {
+        match self.choose_cap() {
+            Some(cap) => cap.emit(time),
+            None => {
+                let (point, normal, uv) = self.random_side_point();
+                emit_from(&self.material, point, normal, uv)
+            }
+        }
+    }
+
+    fn power(&self) -> f64 {
+        self.material.emitted_power(self.area())
+    }
+}
@@ -0,0 +1,151 @@
+use super::*;
+
+/// Way of combining the insides of two closed objects.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CsgOp {
+    /// Inside either object.
+    Union,
+    /// Inside both objects.
+    Intersection,
+    /// Inside the first object but not the second.
+    Difference,
+}
+
+impl CsgOp {
+    fn inside(self, left: bool, right: bool) -> bool {
+        match self {
+            CsgOp::Union => left || right,
+            CsgOp::Intersection => left && right,
+            CsgOp::Difference => left && !right,
+        }
+    }
+}
+
+/// Solid made by combining two closed objects, whose surfaces face outwards.
+/// Rays are followed through the surfaces of both objects in turn, keeping
+/// track of whether they are inside each, and the combination's surface is
+/// wherever the ray moves between its inside and outside.
+pub struct Csg {
+    op: CsgOp,
+    left: Arc<dyn Hittable>,
+    right: Arc<dyn Hittable>,
+    aabb: AABB,
+}
+
+impl Csg {
+    pub fn new(
+        op: CsgOp,
+        left: Arc<dyn Hittable>,
+        right: Arc<dyn Hittable>,
+    ) -> Arc<Csg> {
+        let (a, b) = (left.bounding_box(), right.bounding_box());
+        let aabb = match op {
+            CsgOp::Union => AABB::containing(a, b),
+            CsgOp::Intersection => {
+                let min = a.min.sup(&b.min);
+                // Objects which do not overlap leave an empty box.
+                AABB::new(min, a.max.inf(&b.max).sup(&min))
+            }
+            CsgOp::Difference => a,
+        };
+        Arc::new(Csg {
+            op,
+            left,
+            right,
+            aabb,
+        })
+    }
+}
+
+/// Surfaces are crossed at most this many times before giving up on a ray.
+const MAX_CROSSINGS: usize = 64;
+
+impl Hittable for Csg {
+    fn is_light(&self) -> bool {
+        false
+    }
+
+    fn bounding_box(&self) -> AABB {
+        self.aabb
+    }
+
+    fn hit(&self, ray: Ray, range: Range<f64>) -> Option<Hit> {
+        let mut left = self.left.hit(ray, range.start..f64::INFINITY);
+        let mut right = self.right.hit(ray, range.start..f64::INFINITY);
+        // A ray which first meets the back of a surface starts inside.
+        let mut in_left = matches!(left, Some(hit) if !hit.front_facing);
+        let mut in_right = matches!(right, Some(hit) if !hit.front_facing);
+
+        for _ in 0..MAX_CROSSINGS {
+            let is_left = match (&left, &right) {
+                (Some(l), Some(r)) => l.t <= r.t,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (None, None) => return None,
+            };
+            let mut hit = if is_left { left } else { right }.unwrap();
+            if hit.t >= range.end {
+                return None;
+            }
+            let inside = self.op.inside(in_left, in_right);
+            if is_left {
+                in_left = hit.front_facing;
+                left = self.left.hit(ray, hit.t + 0.0001..f64::INFINITY);
+            } else {
+                in_right = hit.front_facing;
+                right = self.right.hit(ray, hit.t + 0.0001..f64::INFINITY);
+            }
+            if self.op.inside(in_left, in_right) != inside {
+                // The surfaces of subtracted objects face into the result.
+                if !is_left && self.op == CsgOp::Difference {
+                    hit.front_facing = !hit.front_facing;
+                }
+                return Some(hit);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn csg_surfaces_bound_the_combined_inside() {
+    let material = Arc::new(Material::Metal(Color::repeat(0.5), 0.0));
+    let a = Sphere::new(material.clone(), Point::new(-0.5, 0.0, 0.0), 1.0);
+    let b = Sphere::new(material, Point::new(0.5, 0.0, 0.0), 1.0);
+    let range = 0.0001..f64::INFINITY;
+    let ray = Ray::new(Point::new(-5.0, 0.0, 0.0), Vector::x());
+    // Entry and exit points along the x axis, from both ends of it.
+    let crossings = |csg: &Csg| {
+        let hit = csg.hit(ray, range.clone()).unwrap();
+        assert!(hit.front_facing);
+        let back = Ray::new(Point::new(5.0, 0.0, 0.0), -Vector::x());
+        let exit = csg.hit(back, range.clone()).unwrap();
+        assert!(exit.front_facing);
+        (hit.point.x, exit.point.x)
+    };
+    let close = |(a, b): (f64, f64), (c, d): (f64, f64)| {
+        (a - c).abs() < 1e-9 && (b - d).abs() < 1e-9
+    };
+
+    let union = Csg::new(CsgOp::Union, a.clone(), b.clone());
+    assert!(close(crossings(&union), (-1.5, 1.5)));
+    // The overlapping surfaces inside the union are hidden.
+    let hit = union.hit(ray, 3.6..f64::INFINITY).unwrap();
+    assert!((hit.point.x - 1.5).abs() < 1e-9 && !hit.front_facing);
+
+    let intersection = Csg::new(CsgOp::Intersection, a.clone(), b.clone());
+    assert!(close(crossings(&intersection), (-0.5, 0.5)));
+    assert!((intersection.bounding_box().max.x - 0.5).abs() < 1e-9);
+
+    let difference = Csg::new(CsgOp::Difference, a.clone(), b.clone());
+    assert!(close(crossings(&difference), (-1.5, -0.5)));
+    // Rays missing the subtracted sphere still see the whole first one.
+    let ray = Ray::new(Point::new(-1.1, 0.0, -5.0), Vector::z());
+    let hit = difference.hit(ray, range.clone()).unwrap();
+    assert!((hit.t - 4.2).abs() < 1e-9);
+    // Rays starting inside the result leave it where the hollow begins.
+    let ray = Ray::new(Point::new(-1.0, 0.0, 0.0), Vector::x());
+    let hit = difference.hit(ray, range).unwrap();
+    assert!((hit.point.x + 0.5).abs() < 1e-9 && !hit.front_facing);
+}
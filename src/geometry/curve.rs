@@ -0,0 +1,362 @@
+use super::*;
+use nalgebra::Rotation3;
+use std::f64::consts::{FRAC_PI_2, SQRT_2};
+use std::path::Path;
+
+/// How a curve presents its width to rays.
+#[derive(Debug, Copy, Clone)]
+pub enum CurveShape {
+    /// Flat strip which always faces the ray.
+    Flat,
+    /// Flat strip which faces the ray, with normals curving around it as
+    /// though it were round.
+    Cylinder,
+    /// Strip whose normal turns from the first given at its start to the
+    /// second at its end, for blades of grass and other flat shapes.
+    Ribbon(Unit<Vector>, Unit<Vector>),
+}
+
+/// Cubic Bézier curve swept out to a width which varies linearly along it,
+/// as in "Physically Based Rendering", Pharr et al. Rays are intersected in
+/// a frame in which they run along the z axis, halving the curve until its
+/// pieces are nearly straight and testing those as segments. UVs run along
+/// the curve and across it.
+pub struct Curve {
+    material: Arc<Material>,
+    points: [Point; 4],
+    /// Widths at the start and end.
+    width: (f64, f64),
+    shape: CurveShape,
+    /// Number of times the curve is halved before testing its pieces.
+    depth: u32,
+}
+
+/// Point at which a ray crosses a curve.
+struct Crossing {
+    u: f64,
+    v: f64,
+    /// Distance along the ray.
+    z: f64,
+    /// Width of the curve as seen by the ray.
+    width: f64,
+}
+
+impl Curve {
+    pub fn new(
+        material: Arc<Material>,
+        points: [Point; 4],
+        width: (f64, f64),
+        shape: CurveShape,
+    ) -> Arc<Curve> {
+        // Halve the curve until its pieces deviate from straight lines by a
+        // small fraction of its width.
+        let bend = (0..2)
+            .map(|i| {
+                (points[i].coords - 2.0 * points[i + 1].coords
+                    + points[i + 2].coords)
+                    .amax()
+            })
+            .fold(0.0, f64::max);
+        let tolerance = 0.05 * width.0.max(width.1);
+        let depth = if bend > 0.0 && tolerance > 0.0 {
+            let depth = (SQRT_2 * 6.0 * bend / (8.0 * tolerance)).log2() / 2.0;
+            fclamp(depth, 0.0, 10.0) as u32
+        } else {
+            0
+        };
+        Arc::new(Curve {
+            material,
+            points,
+            width,
+            shape,
+            depth,
+        })
+    }
+
+    /// Curves passing smoothly through each of `points` in turn, as a
+    /// Catmull-Rom spline, with the widths given at each point.
+    pub fn strand(
+        material: &Arc<Material>,
+        points: &[(Point, f64)],
+        shape: CurveShape,
+    ) -> Vec<Arc<Curve>> {
+        let n = points.len();
+        // The ends continue straight on from the points next to them.
+        let at = |i: isize| -> Point {
+            if i < 0 {
+                points[0].0 + (points[0].0 - points[1].0)
+            } else if i as usize >= n {
+                points[n - 1].0 + (points[n - 1].0 - points[n - 2].0)
+            } else {
+                points[i as usize].0
+            }
+        };
+        (0..n as isize - 1)
+            .map(|i| {
+                let (start, end) = (at(i), at(i + 1));
+                let control = [
+                    start,
+                    start + (end - at(i - 1)) / 6.0,
+                    end - (at(i + 2) - start) / 6.0,
+                    end,
+                ];
+                let width = (points[i as usize].1, points[i as usize + 1].1);
+                Curve::new(material.clone(), control, width, shape)
+            })
+            .collect()
+    }
+
+    fn width_at(&self, u: f64) -> f64 {
+        self.width.0 + u * (self.width.1 - self.width.0)
+    }
+
+    /// Finds the nearest crossing of the ray along the z axis with the
+    /// piece of the curve from `u.0` to `u.1`, whose control points are
+    /// `cp`, within `z`. The end of `z` moves to each crossing found.
+    fn nearest(
+        &self,
+        cp: &[Vector; 4],
+        u: (f64, f64),
+        depth: u32,
+        dir: &Unit<Vector>,
+        z: &mut Range<f64>,
+    ) -> Option<Crossing> {
+        if depth == 0 {
+            return self.crossing(cp, u, dir, z);
+        }
+        let (first, second) = split(cp);
+        let mid = (u.0 + u.1) / 2.0;
+        let mut nearest = None;
+        for &(piece, u) in &[(first, (u.0, mid)), (second, (mid, u.1))] {
+            let r = 0.5 * self.width_at(u.0).max(self.width_at(u.1));
+            let (min, max) = piece.iter().fold(
+                (
+                    Vector::repeat(f64::INFINITY),
+                    -Vector::repeat(f64::INFINITY),
+                ),
+                |(min, max), p| (min.inf(p), max.sup(p)),
+            );
+            if max.x + r < 0.0
+                || min.x - r > 0.0
+                || max.y + r < 0.0
+                || min.y - r > 0.0
+                || max.z + r < z.start
+                || min.z - r > z.end
+            {
+                continue;
+            }
+            if let Some(crossing) = self.nearest(&piece, u, depth - 1, dir, z) {
+                nearest = Some(crossing);
+            }
+        }
+        nearest
+    }
+
+    /// Crossing of the ray along the z axis with a piece of the curve
+    /// treated as the segment between its ends.
+    fn crossing(
+        &self,
+        cp: &[Vector; 4],
+        u: (f64, f64),
+        dir: &Unit<Vector>,
+        z: &mut Range<f64>,
+    ) -> Option<Crossing> {
+        // The ray must pass between the lines across the ends of the piece,
+        // so that neighbouring pieces do not both claim it.
+        let start =
+            (cp[1].y - cp[0].y) * -cp[0].y + cp[0].x * (cp[0].x - cp[1].x);
+        let end =
+            (cp[2].y - cp[3].y) * -cp[3].y + cp[3].x * (cp[3].x - cp[2].x);
+        if start < 0.0 || end < 0.0 {
+            return None;
+        }
+        let (sx, sy) = (cp[3].x - cp[0].x, cp[3].y - cp[0].y);
+        let length = sx * sx + sy * sy;
+        if length == 0.0 {
+            return None;
+        }
+        let w = fclamp(-(cp[0].x * sx + cp[0].y * sy) / length, 0.0, 1.0);
+        let hit_u = u.0 + w * (u.1 - u.0);
+        let mut width = self.width_at(hit_u);
+        if let CurveShape::Ribbon(n0, n1) = self.shape {
+            width *= n0.slerp(&n1, hit_u).dot(dir).abs();
+        }
+        let (p, dpdw) = bezier(cp, w);
+        let dist = (p.x * p.x + p.y * p.y).sqrt();
+        // Rays leaving a fibre start on its surface, and would otherwise
+        // find it again on their way out.
+        if dist > 0.5 * width || !z.contains(&p.z) || p.norm() < width {
+            return None;
+        }
+        let side = dpdw.x * -p.y + p.x * dpdw.y;
+        let v = if side > 0.0 {
+            0.5 + dist / width
+        } else {
+            0.5 - dist / width
+        };
+        z.end = p.z;
+        Some(Crossing {
+            u: hit_u,
+            v,
+            z: p.z,
+            width,
+        })
+    }
+}
+
+/// Splits the Bézier curve with control points `cp` in half.
+fn split(cp: &[Vector; 4]) -> ([Vector; 4], [Vector; 4]) {
+    let (a, b, c) = (
+        (cp[0] + cp[1]) / 2.0,
+        (cp[1] + cp[2]) / 2.0,
+        (cp[2] + cp[3]) / 2.0,
+    );
+    let (d, e) = ((a + b) / 2.0, (b + c) / 2.0);
+    let mid = (d + e) / 2.0;
+    ([cp[0], a, d, mid], [mid, e, c, cp[3]])
+}
+
+/// Point at `t` along the Bézier curve with control points `cp`, and the
+/// derivative there.
+fn bezier(cp: &[Vector; 4], t: f64) -> (Vector, Vector) {
+    let a = [
+        cp[0].lerp(&cp[1], t),
+        cp[1].lerp(&cp[2], t),
+        cp[2].lerp(&cp[3], t),
+    ];
+    let b = [a[0].lerp(&a[1], t), a[1].lerp(&a[2], t)];
+    let d = b[1] - b[0];
+    // Coincident control points at the ends leave no derivative there.
+    let d = if d.norm_squared() > 0.0 {
+        3.0 * d
+    } else {
+        cp[3] - cp[0]
+    };
+    (b[0].lerp(&b[1], t), d)
+}
+
+impl Hittable for Curve {
+    fn is_light(&self) -> bool {
+        false
+    }
+
+    fn bounding_box(&self) -> AABB {
+        let (min, max) = self.points[1..]
+            .iter()
+            .fold((self.points[0], self.points[0]), |(min, max), p| {
+                (min.inf(p), max.sup(p))
+            });
+        let r = Vector::repeat(0.5 * self.width.0.max(self.width.1));
+        AABB::new(min - r, max + r)
+    }
+
+    fn hit(&self, ray: Ray, range: Range<f64>) -> Option<Hit> {
+        let length = ray.dir.norm();
+        let dir = Unit::new_unchecked(ray.dir / length);
+        let frame = ONB::from_w(dir);
+        let cp = self.points.map(|p| frame.project(p - ray.origin));
+        let mut z = range.start * length..range.end * length;
+        let crossing =
+            self.nearest(&cp, (0.0, 1.0), self.depth, &dir, &mut z)?;
+
+        let points = self.points.map(|p| p.coords);
+        let dpdu = bezier(&points, crossing.u).1;
+        let dpdv = match self.shape {
+            CurveShape::Ribbon(n0, n1) => {
+                let n = n0.slerp(&n1, crossing.u);
+                n.cross(&dpdu).normalize() * crossing.width
+            }
+            CurveShape::Flat | CurveShape::Cylinder => {
+                let along = frame.project(dpdu);
+                let across = Vector::new(-along.y, along.x, 0.0)
+                    .try_normalize(0.0)
+                    .unwrap_or_else(Vector::x)
+                    * crossing.width;
+                let across = match (self.shape, Unit::try_new(along, 0.0)) {
+                    (CurveShape::Cylinder, Some(axis)) => {
+                        let theta = (2.0 * crossing.v - 1.0) * FRAC_PI_2;
+                        Rotation3::from_axis_angle(&axis, -theta) * across
+                    }
+                    _ => across,
+                };
+                frame.localize(across)
+            }
+        };
+        let normal = Unit::new_normalize(dpdu.cross(&dpdv));
+        let t = crossing.z / length;
+        let mut hit =
+            Hit::new(ray, normal, t, &self.material, (crossing.u, crossing.v))
+                .with_partials(ray, dpdu, dpdv);
+        hit.tangent = Some(Unit::new_normalize(dpdu));
+        Some(hit)
+    }
+}
+
+/// Reads strands of hair or fur from a text file with one strand per line,
+/// each a list of the points it passes through and its width there, as
+/// `x y z width`. Blank lines and lines starting with `#` are skipped.
+pub fn load_strands(path: &Path) -> Result<Vec<Vec<(Point, f64)>>, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|err| format!("{}: {}", path.display(), err))?;
+    let mut strands = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error =
+            |msg: &str| format!("{}:{}: {}", path.display(), i + 1, msg);
+        let values = line
+            .split_whitespace()
+            .map(|s| s.parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| error(&err.to_string()))?;
+        if values.len() % 4 != 0 || values.len() < 8 {
+            return Err(error(
+                "strands need at least two points of x y z width",
+            ));
+        }
+        let strand: Vec<_> = values
+            .chunks(4)
+            .map(|v| (Point::new(v[0], v[1], v[2]), v[3]))
+            .collect();
+        if strand.iter().any(|&(_, width)| width < 0.0) {
+            return Err(error("strand width is negative"));
+        }
+        strands.push(strand);
+    }
+    Ok(strands)
+}
+
+#[cfg(test)]
+#[test]
+fn rays_cross_curves_within_their_width() {
+    let material = Arc::new(Material::Metal(Color::repeat(0.5), 0.0));
+    let points = [
+        Point::new(-1.0, 0.0, 0.0),
+        Point::new(-0.3, 1.0, 0.0),
+        Point::new(0.3, 1.0, 0.0),
+        Point::new(1.0, 0.0, 0.0),
+    ];
+    let curve = Curve::new(material, points, (0.1, 0.1), CurveShape::Cylinder);
+    let range = 0.0001..f64::INFINITY;
+    // The top of the arch is at a height of 0.75.
+    let hit = |y: f64| {
+        let ray = Ray::new(Point::new(0.0, y, -5.0), Vector::z());
+        curve.hit(ray, range.clone())
+    };
+    let centre = hit(0.75).unwrap();
+    assert!((centre.t - 5.0).abs() < 1e-6);
+    assert!((centre.uv.0 - 0.5).abs() < 1e-6);
+    assert!((centre.uv.1 - 0.5).abs() < 1e-3);
+    assert!(centre.normal.z < -0.999);
+    let tangent = centre.tangent.unwrap();
+    assert!(tangent.x.abs() > 0.999);
+    // Near the edge the normal curves round as it would on a cylinder.
+    let edge = hit(0.79).unwrap();
+    assert!((edge.uv.1 - 0.5).abs() > 0.35 && edge.normal.y.abs() > 0.8);
+    assert!(hit(0.81).is_none() && hit(0.69).is_none());
+    // Rays leaving the curve do not find it again.
+    let ray = Ray::new(centre.point, Vector::new(0.0, 0.3, 1.0));
+    assert!(curve.hit(ray, range).is_none());
+}
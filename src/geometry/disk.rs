@@ -0,0 +1,120 @@
+use super::surface::{area_pdf, disk_bounds, emit_from};
+use super::*;
+use std::f64::consts::PI;
+
+/// Flat disk, or an annulus when it has a hole of `inner` radius in the
+/// middle. The `u` coordinate runs around the disk and `v` inwards from its
+/// edge.
+pub struct Disk {
+    material: Arc<Material>,
+    center: Point,
+    /// Frame with `w` along the normal of the disk.
+    frame: ONB,
+    radius: f64,
+    inner: f64,
+}
+
+impl Disk {
+    pub fn new(
+        material: Arc<Material>,
+        center: Point,
+        normal: Unit<Vector>,
+        radius: f64,
+        inner: f64,
+    ) -> Arc<Disk> {
+        Arc::new(Disk {
+            material,
+            center,
+            frame: ONB::from_w(normal),
+            radius,
+            inner,
+        })
+    }
+
+    pub fn area(&self) -> f64 {
+        PI * (self.radius * self.radius - self.inner * self.inner)
+    }
+
+    /// Point on the disk at `(u, v)`.
+    fn point(&self, (u, v): (f64, f64)) -> Point {
+        let r = self.radius - v * (self.radius - self.inner);
+        let phi = 2.0 * PI * u;
+        let local = Vector::new(r * phi.cos(), r * phi.sin(), 0.0);
+        self.center + self.frame.localize(local)
+    }
+
+    /// Point chosen uniformly over the area of the disk, with its UVs.
+    fn random_point(&self) -> (Point, (f64, f64)) {
+        let (r2, r1) = (self.radius * self.radius, self.inner * self.inner);
+        let r = (r1 + random() * (r2 - r1)).sqrt();
+        let v = if self.radius > self.inner {
+            (self.radius - r) / (self.radius - self.inner)
+        } else {
+            0.0
+        };
+        let uv = (random(), v);
+        (self.point(uv), uv)
+    }
+}
+
+impl Hittable for Disk {
+    fn is_light(&self) -> bool {
+        matches!(self.material.as_ref(), Material::Emission(..))
+    }
+
+    fn bounding_box(&self) -> AABB {
+        disk_bounds(self.center, self.frame.w, self.radius)
+    }
+
+    fn hit(&self, ray: Ray, range: Range<f64>) -> Option<Hit> {
+        let normal = self.frame.w;
+        let t = (self.center - ray.origin).dot(&normal) / ray.dir.dot(&normal);
+        if !range.contains(&t) {
+            return None;
+        }
+        let local = self.frame.project(ray.at(t) - self.center);
+        let r = local.x.hypot(local.y);
+        if r > self.radius || r < self.inner {
+            return None;
+        }
+
+        let phi = local.y.atan2(local.x);
+        let u = if phi < 0.0 {
+            phi / (2.0 * PI) + 1.0
+        } else {
+            phi / (2.0 * PI)
+        };
+        let width = self.radius - self.inner;
+        let v = if width > 0.0 {
+            (self.radius - r) / width
+        } else {
+            0.0
+        };
+        let dpdu = 2.0 * PI * Vector::new(-local.y, local.x, 0.0);
+        let dpdv = -width / r.max(1e-8) * Vector::new(local.x, local.y, 0.0);
+        Some(
+            Hit::new(ray, normal, t, &self.material, (u, v)).with_partials(
+                ray,
+                self.frame.localize(dpdu),
+                self.frame.localize(dpdv),
+            ),
+        )
+    }
+
+    fn pdf(&self, ray: Ray) -> f64 {
+        area_pdf(self, ray, self.area())
+    }
+
+    fn random(&self, origin: Point, _time: f64) -> Vector {
+        self.random_point().0 - origin
+    }
+
+    fn emit(&self, _time: f64) -> (Ray, Unit<Vector>, Color) {
+        let (point, uv) = self.random_point();
+        emit_from(&self.material, point, self.frame.w, uv)
+    }
+
+    fn power(&self) -> f64 {
+        self.material.emitted_power(self.area())
+    }
+}
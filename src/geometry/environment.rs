@@ -0,0 +1,116 @@
+use super::*;
+use crate::color::luminance;
+use crate::distribution::Distribution2D;
+use crate::pdf::PDF;
+use crate::texture::HdrImage;
+use nalgebra::Rotation3;
+use std::f64::consts::PI;
+
+/// Radiance arriving along rays which leave the scene.
+pub enum Background {
+    Color(Color),
+    Map(EnvMap),
+    Sky(Box<Sky>),
+}
+
+impl Background {
+    pub fn eval(&self, dir: Vector) -> Color {
+        match self {
+            Background::Color(color) => *color,
+            Background::Map(map) => map.eval(dir),
+            Background::Sky(sky) => sky.eval(dir),
+        }
+    }
+
+    /// Whether the background varies enough to be worth sampling as a light.
+    pub fn is_light(&self) -> bool {
+        match self {
+            Background::Color(..) => false,
+            Background::Map(..) | Background::Sky(..) => true,
+        }
+    }
+
+    /// Distribution of directions for sampling the background as a light.
+    pub fn pdf(&self) -> Option<PDF<'_>> {
+        match self {
+            Background::Color(..) => None,
+            Background::Map(map) => Some(PDF::Environment(map)),
+            Background::Sky(sky) => Some(PDF::Sky(sky)),
+        }
+    }
+}
+
+/// Equirectangular environment map, with importance sampling proportional to
+/// the luminance of each pixel.
+pub struct EnvMap {
+    image: HdrImage,
+    /// Rotation from map space, in which the poles lie along the y axis, to
+    /// world space.
+    rotation: Rotation3<f64>,
+    intensity: f64,
+    distribution: Distribution2D,
+}
+
+impl EnvMap {
+    pub fn new(
+        image: HdrImage,
+        rotation: Rotation3<f64>,
+        intensity: f64,
+    ) -> EnvMap {
+        let mut func = Vec::with_capacity(image.pixels.len());
+        for y in 0..image.height {
+            // Rows near the poles cover less solid angle.
+            let sin_theta = (PI * (y as f64 + 0.5) / image.height as f64).sin();
+            for x in 0..image.width {
+                func.push(luminance(&image.pixel(x, y)).max(0.0) * sin_theta);
+            }
+        }
+        let distribution =
+            Distribution2D::new(&func, image.width, image.height);
+        EnvMap {
+            image,
+            rotation,
+            intensity,
+            distribution,
+        }
+    }
+
+    pub fn eval(&self, dir: Vector) -> Color {
+        self.intensity * self.image.lookup(self.dir_to_uv(dir))
+    }
+
+    /// Samples a direction with probability roughly proportional to the
+    /// radiance arriving from it.
+    pub fn sample(&self) -> Vector {
+        let (uv, _) = self.distribution.sample((random(), random()));
+        self.uv_to_dir(uv)
+    }
+
+    /// Solid angle density with which `sample` generates `dir`.
+    pub fn pdf(&self, dir: Vector) -> f64 {
+        let (u, v) = self.dir_to_uv(dir);
+        let sin_theta = (PI * v).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        self.distribution.pdf((u, v)) / (2.0 * PI * PI * sin_theta)
+    }
+
+    fn dir_to_uv(&self, dir: Vector) -> (f64, f64) {
+        let local = self.rotation.inverse_transform_vector(&dir).normalize();
+        let theta = fclamp(local.y, -1.0, 1.0).acos();
+        let phi = local.z.atan2(local.x);
+        let u = (phi / (2.0 * PI)).rem_euclid(1.0);
+        (u, theta / PI)
+    }
+
+    fn uv_to_dir(&self, (u, v): (f64, f64)) -> Vector {
+        let (theta, phi) = (PI * v, 2.0 * PI * u);
+        let local = Vector::new(
+            theta.sin() * phi.cos(),
+            theta.cos(),
+            theta.sin() * phi.sin(),
+        );
+        self.rotation * local
+    }
+}
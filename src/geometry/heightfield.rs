@@ -0,0 +1,362 @@
+use super::*;
+use image::DynamicImage;
+use std::path::Path;
+
+/// Terrain whose height is sampled on a regular grid over the xz plane, as
+/// two triangles per cell with normals interpolated smoothly across them.
+/// Rays are traced through a quadtree of the lowest and highest points
+/// within each block of cells.
+pub struct Heightfield {
+    material: Arc<Material>,
+    /// Heights in scene units, row by row along x.
+    heights: Vec<f32>,
+    /// Normals at each sample.
+    normals: Vec<Unit<Vector>>,
+    /// Number of samples along x and z.
+    size: (usize, usize),
+    origin: Point,
+    /// Distance between samples along x and z.
+    spacing: (f64, f64),
+    /// Levels of the quadtree, finest first.
+    levels: Vec<Level>,
+}
+
+/// Minimum and maximum heights within blocks of cells, each of which covers
+/// four blocks of the level below.
+struct Level {
+    blocks: Vec<(f32, f32)>,
+    /// Number of blocks along x and z.
+    size: (usize, usize),
+}
+
+impl Heightfield {
+    /// Loads a heightfield from a grayscale image spanning the box from
+    /// `start` to `end`, with its first row along the lower x edge, and black
+    /// and white at the bottom and top of the box. Images with 16 bits per
+    /// sample keep their precision.
+    pub fn load(
+        material: Arc<Material>,
+        path: &Path,
+        start: Point,
+        end: Point,
+    ) -> Result<Arc<Heightfield>, String> {
+        let img = image::open(path)
+            .map_err(|err| format!("{}: {}", path.display(), err))?;
+        let (values, width, height): (Vec<f64>, _, _) = match img {
+            DynamicImage::ImageLuma16(img) => {
+                let values =
+                    img.pixels().map(|p| p.0[0] as f64 / 65535.0).collect();
+                (values, img.width(), img.height())
+            }
+            img => {
+                let img = img.to_luma();
+                let values =
+                    img.pixels().map(|p| p.0[0] as f64 / 255.0).collect();
+                (values, img.width(), img.height())
+            }
+        };
+        if width < 2 || height < 2 {
+            return Err(format!(
+                "{}: heightfield is too small",
+                path.display()
+            ));
+        }
+        let size = (width as usize, height as usize);
+        Ok(Heightfield::new(material, &values, size, start, end))
+    }
+
+    /// Creates a heightfield from `values` between 0 and 1 at `size` samples
+    /// along x and z, spanning the box from `start` to `end`.
+    pub fn new(
+        material: Arc<Material>,
+        values: &[f64],
+        size: (usize, usize),
+        start: Point,
+        end: Point,
+    ) -> Arc<Heightfield> {
+        let (min, max) = (start.inf(&end), start.sup(&end));
+        let heights: Vec<f32> = values
+            .iter()
+            .map(|v| (min.y + v * (max.y - min.y)) as f32)
+            .collect();
+        let spacing = (
+            (max.x - min.x) / (size.0 - 1) as f64,
+            (max.z - min.z) / (size.1 - 1) as f64,
+        );
+        let mut field = Heightfield {
+            material,
+            heights,
+            normals: vec![],
+            size,
+            origin: min,
+            spacing,
+            levels: vec![],
+        };
+        field.normals = (0..size.0 * size.1)
+            .map(|k| field.sample_normal(k % size.0, k / size.0))
+            .collect();
+        field.build_levels();
+        Arc::new(field)
+    }
+
+    fn height(&self, i: usize, j: usize) -> f64 {
+        self.heights[j * self.size.0 + i] as f64
+    }
+
+    fn point(&self, i: usize, j: usize) -> Point {
+        Point::new(
+            self.origin.x + i as f64 * self.spacing.0,
+            self.height(i, j),
+            self.origin.z + j as f64 * self.spacing.1,
+        )
+    }
+
+    /// Normal at a sample, from the slopes between its neighbours.
+    fn sample_normal(&self, i: usize, j: usize) -> Unit<Vector> {
+        let (i0, i1) = (i.saturating_sub(1), (i + 1).min(self.size.0 - 1));
+        let (j0, j1) = (j.saturating_sub(1), (j + 1).min(self.size.1 - 1));
+        let dx = (self.height(i1, j) - self.height(i0, j))
+            / ((i1 - i0) as f64 * self.spacing.0);
+        let dz = (self.height(i, j1) - self.height(i, j0))
+            / ((j1 - j0) as f64 * self.spacing.1);
+        Unit::new_normalize(Vector::new(-dx, 1.0, -dz))
+    }
+
+    fn build_levels(&mut self) {
+        let (cx, cz) = (self.size.0 - 1, self.size.1 - 1);
+        let mut blocks = Vec::with_capacity(cx * cz);
+        for j in 0..cz {
+            for i in 0..cx {
+                let corners = [
+                    self.heights[j * self.size.0 + i],
+                    self.heights[j * self.size.0 + i + 1],
+                    self.heights[(j + 1) * self.size.0 + i],
+                    self.heights[(j + 1) * self.size.0 + i + 1],
+                ];
+                let min = corners.iter().copied().fold(f32::INFINITY, f32::min);
+                let max =
+                    corners.iter().copied().fold(-f32::INFINITY, f32::max);
+                blocks.push((min, max));
+            }
+        }
+        self.levels.push(Level {
+            blocks,
+            size: (cx, cz),
+        });
+        while self.levels.last().unwrap().size != (1, 1) {
+            let below = self.levels.last().unwrap();
+            let (bx, bz) = below.size;
+            let (nx, nz) = (bx.div_ceil(2), bz.div_ceil(2));
+            let mut blocks = Vec::with_capacity(nx * nz);
+            for j in 0..nz {
+                for i in 0..nx {
+                    let mut range = (f32::INFINITY, -f32::INFINITY);
+                    for (ci, cj) in children(i, j, below.size) {
+                        let (min, max) = below.blocks[cj * bx + ci];
+                        range = (range.0.min(min), range.1.max(max));
+                    }
+                    blocks.push(range);
+                }
+            }
+            self.levels.push(Level {
+                blocks,
+                size: (nx, nz),
+            });
+        }
+    }
+
+    /// Bounds of block `(i, j)` at `level`, padded so that flat blocks have
+    /// some thickness.
+    fn block_bounds(&self, level: usize, i: usize, j: usize) -> AABB {
+        let Level { blocks, size } = &self.levels[level];
+        let (min, max) = blocks[j * size.0 + i];
+        let cells = 1 << level;
+        let (cx, cz) = (self.size.0 - 1, self.size.1 - 1);
+        let (i0, i1) = (i * cells, ((i + 1) * cells).min(cx));
+        let (j0, j1) = (j * cells, ((j + 1) * cells).min(cz));
+        let pad = 1e-4;
+        AABB::new(
+            Point::new(
+                self.origin.x + i0 as f64 * self.spacing.0 - pad,
+                min as f64 - pad,
+                self.origin.z + j0 as f64 * self.spacing.1 - pad,
+            ),
+            Point::new(
+                self.origin.x + i1 as f64 * self.spacing.0 + pad,
+                max as f64 + pad,
+                self.origin.z + j1 as f64 * self.spacing.1 + pad,
+            ),
+        )
+    }
+
+    /// Intersects the two triangles of cell `(i, j)`.
+    fn hit_cell(
+        &self,
+        ray: Ray,
+        range: Range<f64>,
+        i: usize,
+        j: usize,
+    ) -> Option<Hit> {
+        let corners = [(i, j), (i + 1, j), (i + 1, j + 1), (i, j + 1)];
+        let mut closest: Option<Hit> = None;
+        let mut end = range.end;
+        for tri in &[[0, 1, 2], [0, 2, 3]] {
+            let [a, b, c] = tri.map(|k| corners[k]);
+            let (p0, p1, p2) = (
+                self.point(a.0, a.1),
+                self.point(b.0, b.1),
+                self.point(c.0, c.1),
+            );
+            let (e1, e2) = (p1 - p0, p2 - p0);
+            let pvec = ray.dir.cross(&e2);
+            let det = e1.dot(&pvec);
+            if det.abs() < 1e-12 {
+                continue;
+            }
+            let tvec = ray.origin - p0;
+            let u = tvec.dot(&pvec) / det;
+            let qvec = tvec.cross(&e1);
+            let v = ray.dir.dot(&qvec) / det;
+            if u < 0.0 || v < 0.0 || u + v > 1.0 {
+                continue;
+            }
+            let t = e2.dot(&qvec) / det;
+            if t < range.start || t >= end {
+                continue;
+            }
+
+            let normal =
+                |(i, j): (usize, usize)| *self.normals[j * self.size.0 + i];
+            let shading =
+                (1.0 - u - v) * normal(a) + u * normal(b) + v * normal(c);
+            let p = ray.at(t);
+            let (width, depth) = (
+                (self.size.0 - 1) as f64 * self.spacing.0,
+                (self.size.1 - 1) as f64 * self.spacing.1,
+            );
+            let uv =
+                ((p.x - self.origin.x) / width, (p.z - self.origin.z) / depth);
+            // The surface rises along the edges of the cell as it moves
+            // across it.
+            let plane = e1.cross(&e2);
+            let dpdu = width * Vector::new(1.0, -plane.x / plane.y, 0.0);
+            let dpdv = depth * Vector::new(0.0, -plane.z / plane.y, 1.0);
+            let hit = Hit::new(
+                ray,
+                Unit::new_normalize(shading),
+                t,
+                &self.material,
+                uv,
+            )
+            .with_partials(ray, dpdu, dpdv);
+            end = t;
+            closest = Some(hit);
+        }
+        closest
+    }
+}
+
+/// Blocks one level finer within block `(i, j)`, given the number of blocks
+/// along x and z at the finer level.
+fn children(
+    i: usize,
+    j: usize,
+    (nx, nz): (usize, usize),
+) -> impl Iterator<Item = (usize, usize)> {
+    (0..4)
+        .map(move |k| (2 * i + (k & 1), 2 * j + (k >> 1)))
+        .filter(move |&(ci, cj)| ci < nx && cj < nz)
+}
+
+impl Hittable for Heightfield {
+    fn is_light(&self) -> bool {
+        false
+    }
+
+    fn bounding_box(&self) -> AABB {
+        self.block_bounds(self.levels.len() - 1, 0, 0)
+    }
+
+    fn hit(&self, ray: Ray, range: Range<f64>) -> Option<Hit> {
+        let mut closest = None;
+        let mut end = range.end;
+        let mut stack = vec![(self.levels.len() - 1, 0, 0)];
+        while let Some((level, i, j)) = stack.pop() {
+            if !self.block_bounds(level, i, j).hit(ray, range.start..end) {
+                continue;
+            }
+            if level == 0 {
+                if let Some(hit) = self.hit_cell(ray, range.start..end, i, j) {
+                    end = hit.t;
+                    closest = Some(hit);
+                }
+                continue;
+            }
+            // Visit the children nearer along the ray first, so that hits in
+            // them cut the search of the others short.
+            let mut next = [(0, 0); 4];
+            let mut count = 0;
+            for child in children(i, j, self.levels[level - 1].size) {
+                next[count] = child;
+                count += 1;
+            }
+            let next = &mut next[..count];
+            let key = |&(ci, cj): &(usize, usize)| {
+                let x = if ray.dir.x < 0.0 {
+                    -(ci as f64)
+                } else {
+                    ci as f64
+                };
+                let z = if ray.dir.z < 0.0 {
+                    -(cj as f64)
+                } else {
+                    cj as f64
+                };
+                x * ray.dir.x.abs() + z * ray.dir.z.abs()
+            };
+            next.sort_by(|a, b| key(b).partial_cmp(&key(a)).unwrap());
+            stack.extend(next.iter().map(|&(ci, cj)| (level - 1, ci, cj)));
+        }
+        closest
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn rays_find_the_terrain_under_them() {
+    let material = Arc::new(Material::Metal(Color::repeat(0.5), 0.0));
+    // A ramp rising along x from 0 to 1 over 4 units, with a spike in the
+    // middle.
+    let (nx, nz) = (33, 17);
+    let mut values: Vec<f64> = (0..nx * nz)
+        .map(|k| (k % nx) as f64 / (nx - 1) as f64)
+        .collect();
+    values[8 * nx + 16] = 2.0;
+    let field = Heightfield::new(
+        material,
+        &values,
+        (nx, nz),
+        Point::new(-2.0, 0.0, -1.0),
+        Point::new(2.0, 1.0, 1.0),
+    );
+    let aabb = field.bounding_box();
+    assert!((aabb.max.y - 2.0).abs() < 1e-3 && aabb.min.y.abs() < 1e-3);
+
+    let range = 0.0001..f64::INFINITY;
+    for &(x, z) in &[(-1.9, -0.9), (-0.7, 0.3), (1.3, 0.77), (1.99, 0.99)] {
+        let ray = Ray::new(Point::new(x, 5.0, z), -Vector::y());
+        let hit = field.hit(ray, range.clone()).unwrap();
+        let height = (x + 2.0) / 4.0;
+        assert!((hit.point.y - height).abs() < 1e-9, "{}", hit.point);
+        let slope = Vector::new(-0.25, 1.0, 0.0).normalize();
+        assert!((*hit.normal - slope).norm() < 1e-6);
+        assert!(hit.front_facing);
+    }
+    // Rays skimming over the ramp are stopped by the spike.
+    let ray = Ray::new(Point::new(-3.0, 1.2, 0.0), Vector::x());
+    let hit = field.hit(ray, range.clone()).unwrap();
+    assert!(hit.point.x > -0.125 && hit.point.x < 0.0, "{}", hit.point);
+    // And pass beyond the terrain if they miss it.
+    let ray = Ray::new(Point::new(-3.0, 1.2, 0.5), Vector::x());
+    assert!(field.hit(ray, range).is_none());
+}
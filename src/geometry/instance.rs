@@ -0,0 +1,290 @@
+use super::*;
+use nalgebra::{Matrix3, Matrix4, U1, U3};
+use std::cmp::Ordering;
+
+/// Copy of a shared object under an affine transform, optionally made of a
+/// different material. Only the transform into the object's space is kept,
+/// so that scenes can hold millions of instances.
+pub struct Instance {
+    /// Linear part and offset of the transform from the scene to the object.
+    to_local: Matrix3<f64>,
+    offset: Vector,
+    /// Index of the object in the `InstanceBVH`.
+    object: u32,
+    material: Option<Arc<Material>>,
+}
+
+impl Instance {
+    /// Places object `object` in the scene by `matrix`, which must be
+    /// invertible with a last row of (0, 0, 0, 1).
+    pub fn new(
+        object: usize,
+        matrix: &Matrix4<f64>,
+        material: Option<Arc<Material>>,
+    ) -> Result<Instance, String> {
+        let inverse = match matrix.try_inverse() {
+            Some(inverse) => inverse,
+            None => return Err("instance transform is singular".into()),
+        };
+        Ok(Instance {
+            to_local: inverse.fixed_slice::<U3, U3>(0, 0).into(),
+            offset: inverse.fixed_slice::<U3, U1>(0, 3).into(),
+            object: object as u32,
+            material,
+        })
+    }
+
+    fn to_local(&self, ray: Ray) -> Ray {
+        ray.map(
+            |p| Point::from(self.to_local * p.coords + self.offset),
+            |v| self.to_local * v,
+        )
+    }
+
+    /// Bounds of the instance, given those of its object.
+    fn bounding_box(&self, aabb: AABB) -> AABB {
+        let to_world = self.to_local.try_inverse().unwrap();
+        let mut min = Point::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+        let mut max =
+            Point::new(-f64::INFINITY, -f64::INFINITY, -f64::INFINITY);
+        for i in 0..8 {
+            let corner = Vector::new(
+                if i & 1 == 0 { aabb.min.x } else { aabb.max.x },
+                if i & 2 == 0 { aabb.min.y } else { aabb.max.y },
+                if i & 4 == 0 { aabb.min.z } else { aabb.max.z },
+            );
+            let p = Point::from(to_world * (corner - self.offset));
+            min = min.inf(&p);
+            max = max.sup(&p);
+        }
+        AABB::new(min, max)
+    }
+}
+
+/// Top level of a two level bounding volume hierarchy: a flat hierarchy over
+/// instances, each of which refers to one of a few shared objects with
+/// hierarchies of their own.
+pub struct InstanceBVH {
+    objects: Vec<Arc<dyn Hittable>>,
+    instances: Vec<Instance>,
+    /// Nodes with the root first.
+    nodes: Vec<Node>,
+}
+
+struct Node {
+    aabb: AABB,
+    kind: NodeKind,
+}
+
+enum NodeKind {
+    /// Range of instances.
+    Leaf(u32, u32),
+    /// Index of the second child, which follows the whole subtree of the
+    /// first, and the axis along which they were split.
+    Interior(u32, u8),
+}
+
+/// Instances per leaf, where each instance is tested against the bounds of
+/// its object in turn.
+const LEAF_SIZE: usize = 4;
+
+impl InstanceBVH {
+    pub fn new(
+        objects: Vec<Arc<dyn Hittable>>,
+        instances: Vec<Instance>,
+    ) -> Arc<InstanceBVH> {
+        assert!(
+            !instances.is_empty(),
+            "BVH must contain at least one object"
+        );
+        let object_bounds: Vec<AABB> =
+            objects.iter().map(|o| o.bounding_box()).collect();
+        let mut bounds: Vec<(AABB, usize)> = instances
+            .iter()
+            .enumerate()
+            .map(|(i, inst)| {
+                (inst.bounding_box(object_bounds[inst.object as usize]), i)
+            })
+            .collect();
+        let mut nodes = Vec::with_capacity(2 * instances.len() / LEAF_SIZE);
+        build(&mut nodes, &mut bounds, 0);
+
+        // Store the instances in the order of the leaves.
+        let mut unordered: Vec<Option<Instance>> =
+            instances.into_iter().map(Some).collect();
+        let instances = bounds
+            .iter()
+            .map(|&(_, i)| unordered[i].take().unwrap())
+            .collect();
+        Arc::new(InstanceBVH {
+            objects,
+            instances,
+            nodes,
+        })
+    }
+
+    fn hit_instance<'a>(
+        &'a self,
+        instance: &'a Instance,
+        ray: Ray,
+        range: Range<f64>,
+    ) -> Option<Hit<'a>> {
+        let object = &self.objects[instance.object as usize];
+        let hit = object.hit(instance.to_local(ray), range)?;
+        let material = match &instance.material {
+            Some(material) => material,
+            None => hit.material,
+        };
+        let normal = instance.to_local.transpose() * *hit.normal;
+        let mut result =
+            Hit::new(ray, Unit::new_normalize(normal), hit.t, material, hit.uv);
+        result.front_facing = hit.front_facing;
+        result.duv = hit.duv;
+        // Tangents are carried to the scene by the inverse of `to_local`.
+        result.tangent = hit.tangent.and_then(|t| {
+            let t = instance.to_local.lu().solve(&t)?;
+            Unit::try_new(t, 0.0)
+        });
+        Some(result)
+    }
+}
+
+/// Adds the subtree over `bounds`, reordering them to match its leaves.
+fn build(nodes: &mut Vec<Node>, bounds: &mut [(AABB, usize)], start: usize) {
+    let aabb = bounds
+        .iter()
+        .skip(1)
+        .fold(bounds[0].0, |aabb, b| AABB::containing(aabb, b.0));
+    if bounds.len() <= LEAF_SIZE {
+        let kind = NodeKind::Leaf(start as u32, bounds.len() as u32);
+        nodes.push(Node { aabb, kind });
+        return;
+    }
+
+    // Split at the median centroid along the axis they spread most on.
+    let centroid = |b: &(AABB, usize)| (b.0.min.coords + b.0.max.coords) / 2.0;
+    let (mut min, mut max) = (centroid(&bounds[0]), centroid(&bounds[0]));
+    for b in bounds.iter() {
+        min = min.inf(&centroid(b));
+        max = max.sup(&centroid(b));
+    }
+    let axis = (max - min).imax();
+    let mid = bounds.len() / 2;
+    bounds.select_nth_unstable_by(mid, |a, b| {
+        centroid(a)[axis]
+            .partial_cmp(&centroid(b)[axis])
+            .unwrap_or(Ordering::Equal)
+    });
+
+    // Reserve this node's slot before adding its children.
+    let index = nodes.len();
+    nodes.push(Node {
+        aabb,
+        kind: NodeKind::Leaf(0, 0),
+    });
+    let (left, right) = bounds.split_at_mut(mid);
+    build(nodes, left, start);
+    let second = nodes.len();
+    build(nodes, right, start + mid);
+    nodes[index].kind = NodeKind::Interior(second as u32, axis as u8);
+}
+
+impl Hittable for InstanceBVH {
+    fn is_light(&self) -> bool {
+        false
+    }
+
+    fn bounding_box(&self) -> AABB {
+        self.nodes[0].aabb
+    }
+
+    fn hit(&self, ray: Ray, range: Range<f64>) -> Option<Hit> {
+        let mut closest = None;
+        let mut end = range.end;
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if !node.aabb.hit(ray, range.start..end) {
+                continue;
+            }
+            match node.kind {
+                NodeKind::Leaf(first, count) => {
+                    let first = first as usize;
+                    for instance in
+                        &self.instances[first..first + count as usize]
+                    {
+                        let hit =
+                            self.hit_instance(instance, ray, range.start..end);
+                        if let Some(hit) = hit {
+                            end = hit.t;
+                            closest = Some(hit);
+                        }
+                    }
+                }
+                NodeKind::Interior(second, axis) => {
+                    // Visit the nearer child first, so that hits in it
+                    // shorten the search of the other.
+                    let (first, second) = (index + 1, second as usize);
+                    if ray.dir[axis as usize] < 0.0 {
+                        stack.push(first);
+                        stack.push(second);
+                    } else {
+                        stack.push(second);
+                        stack.push(first);
+                    }
+                }
+            }
+        }
+        closest
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn instances_are_found_by_their_transforms() {
+    let material = Arc::new(Material::Metal(Color::repeat(0.5), 0.0));
+    let other = Arc::new(Material::Metal(Color::repeat(0.9), 0.0));
+    let sphere = Sphere::new(material, Point::origin(), 1.0);
+    // A row of spheres along x, halving in size, with every third one made
+    // of the other material.
+    let instances: Vec<Instance> = (0..100)
+        .map(|i| {
+            let matrix = Matrix4::new_translation(&Vector::new(
+                i as f64 * 3.0,
+                0.0,
+                0.0,
+            )) * Matrix4::new_scaling(0.5f64.powi(i % 4));
+            let material = if i % 3 == 0 {
+                Some(other.clone())
+            } else {
+                None
+            };
+            Instance::new(0, &matrix, material).unwrap()
+        })
+        .collect();
+    let bvh = InstanceBVH::new(vec![sphere], instances);
+    let aabb = bvh.bounding_box();
+    assert!(
+        (aabb.max.x - 297.125).abs() < 1e-9 && (aabb.min.x + 1.0).abs() < 1e-9
+    );
+    for i in 0..100 {
+        let x = i as f64 * 3.0;
+        let ray = Ray::new(Point::new(x, 0.0, -5.0), Vector::z());
+        let hit = bvh.hit(ray, 0.0001..f64::INFINITY).unwrap();
+        let radius = 0.5f64.powi(i % 4);
+        assert!((hit.t - (5.0 - radius)).abs() < 1e-9);
+        assert!((*hit.normal + Vector::z()).norm() < 1e-9);
+        assert_eq!(
+            matches!(hit.material, Material::Metal(c, _) if c.x > 0.8),
+            i % 3 == 0
+        );
+    }
+    // Rays along the row stop at the first sphere.
+    let ray = Ray::new(Point::new(-5.0, 0.0, 0.0), Vector::x());
+    assert!(
+        (bvh.hit(ray, 0.0001..f64::INFINITY).unwrap().t - 4.0).abs() < 1e-9
+    );
+    let ray = Ray::new(Point::new(400.0, 0.0, 0.0), -Vector::x());
+    let hit = bvh.hit(ray, 0.0001..f64::INFINITY).unwrap();
+    assert!((hit.t - (400.0 - 297.0 - 0.125)).abs() < 1e-9);
+}
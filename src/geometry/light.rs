@@ -0,0 +1,252 @@
+use super::*;
+use crate::color::luminance;
+use std::f64::consts::PI;
+
+/// Light source with no surface, which can only be reached by sampling it
+/// explicitly rather than by hitting it.
+#[derive(Debug, Copy, Clone)]
+pub enum Light {
+    /// Emits `intensity` in W/sr uniformly in every direction.
+    Point { position: Point, intensity: Color },
+    /// Emits `intensity` in W/sr within a cone around `frame.w`, falling off
+    /// smoothly between the inner and outer cone angles.
+    Spot {
+        position: Point,
+        frame: ONB,
+        intensity: Color,
+        cos_inner: f64,
+        cos_outer: f64,
+    },
+    /// Infinitely distant light travelling along `direction`, delivering
+    /// `irradiance` in W/m^2 to surfaces facing it.
+    Directional {
+        direction: Unit<Vector>,
+        irradiance: Color,
+    },
+}
+
+/// Illumination arriving at a point from a light.
+pub struct LightSample {
+    /// Unit direction from the point towards the light.
+    pub dir: Unit<Vector>,
+    /// Distance to the light, infinite for directional lights.
+    pub dist: f64,
+    /// Incident radiance integrated over the solid angle of the light.
+    pub irradiance: Color,
+}
+
+impl Light {
+    pub fn point(position: Point, intensity: Color) -> Light {
+        Light::Point {
+            position,
+            intensity,
+        }
+    }
+
+    /// Creates a spot light pointing along `dir`, where `inner` and `outer`
+    /// are the cone half-angles in degrees.
+    pub fn spot(
+        position: Point,
+        dir: Vector,
+        intensity: Color,
+        inner: f64,
+        outer: f64,
+    ) -> Light {
+        let outer = outer.max(inner);
+        Light::Spot {
+            position,
+            frame: ONB::from_w(Unit::new_normalize(dir)),
+            intensity,
+            cos_inner: inner.to_radians().cos(),
+            cos_outer: outer.to_radians().cos(),
+        }
+    }
+
+    pub fn directional(dir: Vector, irradiance: Color) -> Light {
+        Light::Directional {
+            direction: Unit::new_normalize(dir),
+            irradiance,
+        }
+    }
+
+    /// Samples the illumination reaching `point`.
+    pub fn sample(&self, point: Point) -> LightSample {
+        match *self {
+            Light::Point {
+                position,
+                intensity,
+            } => {
+                let (dir, dist) = Unit::new_and_get(position - point);
+                LightSample {
+                    dir,
+                    dist,
+                    irradiance: intensity / (dist * dist),
+                }
+            }
+            Light::Spot {
+                position,
+                frame,
+                intensity,
+                cos_inner,
+                cos_outer,
+            } => {
+                let (dir, dist) = Unit::new_and_get(position - point);
+                let falloff =
+                    spot_falloff(-dir.dot(&frame.w), cos_inner, cos_outer);
+                LightSample {
+                    dir,
+                    dist,
+                    irradiance: falloff * intensity / (dist * dist),
+                }
+            }
+            Light::Directional {
+                direction,
+                irradiance,
+            } => LightSample {
+                dir: -direction,
+                dist: f64::INFINITY,
+                irradiance,
+            },
+        }
+    }
+
+    /// Estimated luminance of the total power emitted, where directional
+    /// lights cover the sphere which bounds `bounds`.
+    pub fn power(&self, bounds: &AABB) -> f64 {
+        match *self {
+            Light::Point { intensity, .. } => 4.0 * PI * luminance(&intensity),
+            Light::Spot {
+                intensity,
+                cos_inner,
+                cos_outer,
+                ..
+            } => spot_solid_angle(cos_inner, cos_outer) * luminance(&intensity),
+            Light::Directional { irradiance, .. } => {
+                let radius = (bounds.max - bounds.min).norm() / 2.0;
+                PI * radius * radius * luminance(&irradiance)
+            }
+        }
+    }
+
+    /// Generates a ray leaving the light, along with its normal and the power
+    /// carried by the ray. Directional lights emit from a disc covering the
+    /// sphere which bounds `bounds`.
+    pub fn emit(&self, bounds: &AABB) -> (Ray, Unit<Vector>, Color) {
+        match *self {
+            Light::Point {
+                position,
+                intensity,
+            } => {
+                let dir = Unit::new_normalize(random_unit_vector());
+                let ray = Ray::new(position, *dir);
+                (ray, dir, 4.0 * PI * intensity)
+            }
+            Light::Spot {
+                position,
+                frame,
+                intensity,
+                cos_inner,
+                cos_outer,
+            } => {
+                // Sample uniformly within the outer cone.
+                let cos_theta = 1.0 - random() * (1.0 - cos_outer);
+                let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+                let phi = 2.0 * PI * random();
+                let dir = Unit::new_normalize(frame.localize(Vector::new(
+                    sin_theta * phi.cos(),
+                    sin_theta * phi.sin(),
+                    cos_theta,
+                )));
+                let solid_angle = 2.0 * PI * (1.0 - cos_outer);
+                let falloff = spot_falloff(cos_theta, cos_inner, cos_outer);
+                let ray = Ray::new(position, *dir);
+                (ray, dir, falloff * solid_angle * intensity)
+            }
+            Light::Directional {
+                direction,
+                irradiance,
+            } => {
+                let center =
+                    Point::from((bounds.min.coords + bounds.max.coords) / 2.0);
+                let radius = (bounds.max - center).norm();
+                let frame = ONB::from_w(direction);
+                let r = radius * random().sqrt();
+                let phi = 2.0 * PI * random();
+                let offset = frame.localize(Vector::new(
+                    r * phi.cos(),
+                    r * phi.sin(),
+                    0.0,
+                ));
+                let origin = center + offset - radius * *direction;
+                let ray = Ray::new(origin, *direction);
+                (ray, direction, PI * radius * radius * irradiance)
+            }
+        }
+    }
+}
+
+/// Integral of the spot light falloff over the sphere of directions. The
+/// falloff is a smoothstep in the cosine, which integrates to half the width
+/// of the transition, so this is exactly the solid angle of the cone halfway
+/// between the inner and outer ones.
+pub fn spot_solid_angle(cos_inner: f64, cos_outer: f64) -> f64 {
+    let cos_mid = (cos_inner + cos_outer) / 2.0;
+    2.0 * PI * (1.0 - cos_mid)
+}
+
+/// Smoothly interpolates spot light intensity between the inner and outer
+/// cones, given the cosine of the angle to the spot axis.
+fn spot_falloff(cos: f64, cos_inner: f64, cos_outer: f64) -> f64 {
+    if cos >= cos_inner {
+        1.0
+    } else if cos <= cos_outer {
+        0.0
+    } else {
+        let t = (cos - cos_outer) / (cos_inner - cos_outer);
+        t * t * (3.0 - 2.0 * t)
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn lights_fall_off_with_distance_and_angle() {
+    let close = Light::point(Point::origin(), Color::repeat(8.0))
+        .sample(Point::new(0.0, 0.0, 2.0));
+    let far = Light::point(Point::origin(), Color::repeat(8.0))
+        .sample(Point::new(0.0, 0.0, 4.0));
+    assert_eq!((close.irradiance.x, far.irradiance.x), (2.0, 0.5));
+    assert_eq!(close.dir.into_inner(), -Vector::z());
+
+    let spot = Light::spot(
+        Point::origin(),
+        -Vector::y(),
+        Color::repeat(4.0),
+        30.0,
+        60.0,
+    );
+    let at_angle = |degrees: f64| {
+        let theta = degrees.to_radians();
+        let point = Point::new(theta.sin(), -theta.cos(), 0.0);
+        spot.sample(point).irradiance.x / 4.0
+    };
+    assert_eq!(at_angle(0.0), 1.0);
+    assert!((at_angle(29.0) - 1.0).abs() < 1e-12);
+    assert_eq!(at_angle(61.0), 0.0);
+    assert!(at_angle(40.0) > at_angle(50.0) && at_angle(50.0) > 0.0);
+
+    // Integrating the falloff over the sphere recovers the solid angle that
+    // light power is spread over.
+    let (cos_inner, cos_outer) =
+        (30f64.to_radians().cos(), 60f64.to_radians().cos());
+    let n = 100_000;
+    let integral: f64 = (0..n)
+        .map(|i| {
+            let cos = 2.0 * (i as f64 + 0.5) / n as f64 - 1.0;
+            spot_falloff(cos, cos_inner, cos_outer) * 2.0 * PI * 2.0 / n as f64
+        })
+        .sum();
+    let solid_angle = spot_solid_angle(cos_inner, cos_outer);
+    assert!((integral - solid_angle).abs() < 1e-6);
+    let bounds = AABB::new(Point::origin(), Point::origin());
+    assert!((spot.power(&bounds) - 4.0 * solid_angle).abs() < 1e-9);
+}
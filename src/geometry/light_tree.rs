@@ -0,0 +1,256 @@
+use super::*;
+use crate::distribution::Distribution1D;
+use std::cmp::Ordering;
+use std::ops::Range;
+
+/// Bounding volume hierarchy over the emissive objects of a scene, used to
+/// choose a light to sample with probability proportional to an estimate of
+/// its contribution at the shading point: its power over the squared
+/// distance to it.
+pub struct LightTree {
+    lights: Vec<Arc<dyn Hittable>>,
+    /// Nodes with the root first. Empty if there are no lights.
+    nodes: Vec<Node>,
+    /// Selection by power alone, for starting paths at the lights.
+    power: Distribution1D,
+}
+
+struct Node {
+    aabb: AABB,
+    power: f64,
+    kind: NodeKind,
+}
+
+enum NodeKind {
+    /// Index into the lights.
+    Leaf(usize),
+    /// Indices of the two child nodes.
+    Interior(usize, usize),
+}
+
+impl LightTree {
+    pub fn new(lights: Vec<Arc<dyn Hittable>>) -> LightTree {
+        let power = Distribution1D::new(
+            lights.iter().map(|l| l.power().max(0.0)).collect(),
+        );
+        let mut tree = LightTree {
+            lights,
+            nodes: vec![],
+            power,
+        };
+        let mut indices: Vec<usize> = (0..tree.lights.len()).collect();
+        if !indices.is_empty() {
+            tree.build(&mut indices);
+        }
+        tree
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lights.is_empty()
+    }
+
+    /// Adds the subtree over `indices`, returning the index of its root.
+    fn build(&mut self, indices: &mut [usize]) -> usize {
+        let index = self.nodes.len();
+        if let [light] = *indices {
+            self.nodes.push(Node {
+                aabb: self.lights[light].bounding_box(),
+                power: self.lights[light].power().max(0.0),
+                kind: NodeKind::Leaf(light),
+            });
+            return index;
+        }
+
+        // Split at the median centroid along the axis they spread most on.
+        let centroid = |i: usize| {
+            let aabb = self.lights[i].bounding_box();
+            (aabb.min.coords + aabb.max.coords) / 2.0
+        };
+        let (mut min, mut max) = (centroid(indices[0]), centroid(indices[0]));
+        for &i in indices.iter() {
+            let c = centroid(i);
+            min = min.inf(&c);
+            max = max.sup(&c);
+        }
+        let axis = (max - min).imax();
+        indices.sort_by(|&a, &b| {
+            centroid(a)[axis]
+                .partial_cmp(&centroid(b)[axis])
+                .unwrap_or(Ordering::Equal)
+        });
+
+        // Reserve this node's slot before adding its children.
+        self.nodes.push(Node {
+            aabb: self.lights[indices[0]].bounding_box(),
+            power: 0.0,
+            kind: NodeKind::Leaf(indices[0]),
+        });
+        let (left, right) = indices.split_at_mut(indices.len() / 2);
+        let left = self.build(left);
+        let right = self.build(right);
+        let (a, b) = (&self.nodes[left], &self.nodes[right]);
+        self.nodes[index] = Node {
+            aabb: AABB::containing(a.aabb, b.aabb),
+            power: a.power + b.power,
+            kind: NodeKind::Interior(left, right),
+        };
+        index
+    }
+
+    /// Probability of descending into the left rather than the right child
+    /// when sampling from `point`.
+    fn left_probability(&self, left: usize, right: usize, point: Point) -> f64 {
+        let importance = |node: &Node| {
+            let center = Point::from(
+                (node.aabb.min.coords + node.aabb.max.coords) / 2.0,
+            );
+            // Avoid the singularity when the point lies within the bounds.
+            let radius_squared = (node.aabb.max - center).norm_squared();
+            let dist_squared = (center - point).norm_squared();
+            node.power / dist_squared.max(radius_squared)
+        };
+        let (l, r) = (
+            importance(&self.nodes[left]),
+            importance(&self.nodes[right]),
+        );
+        if l + r > 0.0 {
+            l / (l + r)
+        } else {
+            0.5
+        }
+    }
+
+    /// Sums the densities of the lights below `node` which `ray` may hit,
+    /// each weighted by the probability `prob` of reaching the node and of
+    /// choosing the light from there.
+    fn node_pdf(&self, node: usize, ray: Ray, prob: f64) -> f64 {
+        let node = &self.nodes[node];
+        if prob <= 0.0 || !node.aabb.hit(ray, 0.0001..f64::INFINITY) {
+            return 0.0;
+        }
+        match node.kind {
+            NodeKind::Leaf(light) => prob * self.lights[light].pdf(ray),
+            NodeKind::Interior(left, right) => {
+                let p = self.left_probability(left, right, ray.origin);
+                self.node_pdf(left, ray, prob * p)
+                    + self.node_pdf(right, ray, prob * (1.0 - p))
+            }
+        }
+    }
+
+    fn node_hit(
+        &self,
+        node: usize,
+        ray: Ray,
+        range: Range<f64>,
+    ) -> Option<Hit<'_>> {
+        let node = &self.nodes[node];
+        if !node.aabb.hit(ray, range.clone()) {
+            return None;
+        }
+        match node.kind {
+            NodeKind::Leaf(light) => self.lights[light].hit(ray, range),
+            NodeKind::Interior(left, right) => {
+                let left = self.node_hit(left, ray, range.clone());
+                let end = left.as_ref().map_or(range.end, |hit| hit.t);
+                self.node_hit(right, ray, range.start..end).or(left)
+            }
+        }
+    }
+}
+
+impl Hittable for LightTree {
+    fn is_light(&self) -> bool {
+        false
+    }
+
+    fn bounding_box(&self) -> AABB {
+        match self.nodes.first() {
+            Some(root) => root.aabb,
+            None => AABB::new(Point::origin(), Point::origin()),
+        }
+    }
+
+    fn hit(&self, ray: Ray, range: Range<f64>) -> Option<Hit> {
+        if self.is_empty() {
+            return None;
+        }
+        self.node_hit(0, ray, range)
+    }
+
+    fn pdf(&self, ray: Ray) -> f64 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        self.node_pdf(0, ray, 1.0)
+    }
+
+    fn random(&self, origin: Point, time: f64) -> Vector {
+        let mut node = 0;
+        loop {
+            match self.nodes[node].kind {
+                NodeKind::Leaf(light) => {
+                    return self.lights[light].random(origin, time)
+                }
+                NodeKind::Interior(left, right) => {
+                    let p = self.left_probability(left, right, origin);
+                    node = if random() < p { left } else { right };
+                }
+            }
+        }
+    }
+
+    fn emit(&self, time: f64) -> (Ray, Unit<Vector>, Color) {
+        let (light, prob) = self.power.sample_discrete(random());
+        let (ray, normal, color) = self.lights[light].emit(time);
+        (ray, normal, color / prob)
+    }
+
+    fn power(&self) -> f64 {
+        self.nodes.first().map_or(0.0, |root| root.power)
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn prefers_bright_and_nearby_lights() {
+    use crate::material::{Emitter, Material};
+    use std::f64::consts::PI;
+    let emission = |strength: f64| {
+        Arc::new(Material::Emission(
+            Arc::new(Texture::Solid(Color::repeat(1.0))),
+            Emitter::new(Color::repeat(strength)),
+        ))
+    };
+    let lights: Vec<Arc<dyn Hittable>> = vec![
+        Sphere::new(emission(1.0), Point::new(-10.0, 0.0, 0.0), 1.0),
+        Sphere::new(emission(1.0), Point::new(10.0, 0.0, 0.0), 1.0),
+        Sphere::new(emission(100.0), Point::new(0.0, 10.0, 0.0), 1.0),
+    ];
+    let tree = LightTree::new(lights);
+    let origin = Point::new(-8.0, 0.0, 0.0);
+    // Probability of choosing the light of unit radius at `center`, given
+    // the density of sampling the direction towards it.
+    let probability = |center: Point| {
+        let dir = center - origin;
+        let sin_squared = 1.0 / dir.norm_squared();
+        let solid_angle = 2.0 * PI * (1.0 - (1.0 - sin_squared).sqrt());
+        tree.pdf(Ray::new(origin, dir)) * solid_angle
+    };
+
+    // The dim light nearby outweighs the equally dim one far away, and the
+    // bright light is worth sampling despite its distance.
+    let near = probability(Point::new(-10.0, 0.0, 0.0));
+    let far = probability(Point::new(10.0, 0.0, 0.0));
+    let bright = probability(Point::new(0.0, 10.0, 0.0));
+    assert!(near > 10.0 * far, "{} {}", near, far);
+    assert!(bright > 10.0 * far, "{} {}", bright, far);
+    assert!((near + far + bright - 1.0).abs() < 1e-9);
+
+    // Sampled directions head towards lights in proportion to their
+    // probabilities.
+    let n = 20000;
+    let hits = (0..n).filter(|_| tree.random(origin, 0.0).x < 0.0).count();
+    let actual = hits as f64 / n as f64;
+    assert!((actual - near).abs() < 0.02, "{} {}", actual, near);
+}
@@ -0,0 +1,100 @@
+use super::*;
+use std::ops::Range;
+
+/// Participating medium of constant density filling a closed boundary, which
+/// rays collide with at random distances inside it. Only the first interval
+/// a ray spends inside the boundary is considered, so the boundary should be
+/// convex.
+pub struct ConstantMedium {
+    boundary: Arc<dyn Hittable>,
+    material: Arc<Material>,
+    majorant: f64,
+}
+
+impl ConstantMedium {
+    /// Fills `boundary` with `material`, which must be a `Material::Medium`.
+    pub fn new(
+        boundary: Arc<dyn Hittable>,
+        material: Arc<Material>,
+    ) -> Result<Arc<ConstantMedium>, String> {
+        let majorant = match material.as_ref() {
+            Material::Medium(medium) => medium.majorant(),
+            _ => {
+                return Err("constant medium requires a medium material".into())
+            }
+        };
+        Ok(Arc::new(ConstantMedium {
+            boundary,
+            material,
+            majorant,
+        }))
+    }
+}
+
+impl Hittable for ConstantMedium {
+    fn is_light(&self) -> bool {
+        false
+    }
+
+    fn bounding_box(&self) -> AABB {
+        self.boundary.bounding_box()
+    }
+
+    fn hit(&self, ray: Ray, range: Range<f64>) -> Option<Hit> {
+        if self.majorant <= 0.0 {
+            return None;
+        }
+        // Find where the ray enters and leaves the boundary, which may be
+        // behind its origin.
+        let enter = self.boundary.hit(ray, -f64::INFINITY..f64::INFINITY)?;
+        let exit = self.boundary.hit(ray, enter.t + 0.0001..f64::INFINITY)?;
+        let start = enter.t.max(range.start);
+        let end = exit.t.min(range.end);
+        if start >= end {
+            return None;
+        }
+
+        let speed = ray.dir.norm();
+        let distance = -(1.0 - random()).ln() / self.majorant;
+        if distance >= (end - start) * speed {
+            return None;
+        }
+        let t = start + distance / speed;
+        // Media have no surface, so the normal only faces back along the ray.
+        let normal = Unit::new_normalize(-ray.dir);
+        Some(Hit::new(ray, normal, t, &self.material, (0.0, 0.0)))
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn transmittance_falls_off_exponentially() {
+    use crate::material::Medium;
+    let material = Arc::new(Material::Medium(Medium {
+        absorption: Color::repeat(0.2),
+        scattering: Color::repeat(0.3),
+        g: 0.0,
+    }));
+    let boundary = Block::new(
+        material.clone(),
+        Point::new(-1.0, -1.0, -1.0),
+        Point::new(1.0, 1.0, 1.0),
+    );
+    let medium = ConstantMedium::new(boundary, material).unwrap();
+    let ray = Ray::new(Point::new(0.0, 0.0, -5.0), Vector::new(0.0, 0.0, 2.0));
+    let n = 20000;
+    let passed = (0..n)
+        .filter(|_| medium.hit(ray, 0.0..f64::INFINITY).is_none())
+        .count();
+    let expected = (-0.5f64 * 2.0).exp();
+    let actual = passed as f64 / n as f64;
+    assert!((actual - expected).abs() < 0.02, "{} {}", actual, expected);
+
+    // Rays starting inside only see the rest of the medium.
+    let ray = Ray::new(Point::origin(), Vector::z());
+    let passed = (0..n)
+        .filter(|_| medium.hit(ray, 0.0..f64::INFINITY).is_none())
+        .count();
+    let actual = passed as f64 / n as f64;
+    assert!((actual - (-0.5f64).exp()).abs() < 0.02, "{}", actual);
+}
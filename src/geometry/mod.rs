@@ -1,33 +1,69 @@
 mod aabb;
+mod animation;
 mod block;
 mod bvh;
+mod cone;
+mod csg;
+mod curve;
+mod disk;
+mod environment;
+mod heightfield;
+mod instance;
+mod light;
+mod light_tree;
+mod medium;
 mod onb;
 mod parser;
+mod quad;
 mod ray;
 mod rect;
+mod sdf;
+mod sky;
 mod sphere;
+mod subsurface;
+mod surface;
+mod torus;
 mod transform;
+mod volume;
 
+pub use environment::{Background, EnvMap};
+pub use light::{spot_solid_angle, Light};
+pub use light_tree::LightTree;
 pub use onb::ONB;
-pub use ray::Ray;
+pub use parser::SceneLoader;
+pub use ray::{Ray, RayDiff};
+pub use sky::Sky;
+pub use volume::Volume;
 
 use crate::camera::Camera;
 use crate::color::Color;
 use crate::config::Config;
-use crate::material::Material;
-use crate::texture::Texture;
+use crate::distribution::Distribution1D;
+use crate::material::{Material, IOR};
+use crate::texture::{ImageMapping, MipMap, Texture};
 use crate::util::*;
-use nalgebra::{Point3, Unit, Vector3};
-use rand::seq::SliceRandom;
+use nalgebra::{Matrix4, Point3, Unit, Vector3};
 use std::ops::Range;
 use std::sync::Arc;
 
 use aabb::AABB;
 use block::Block;
 use bvh::BVHNode;
+use cone::Cone;
+use csg::{Csg, CsgOp};
+use curve::{load_strands, Curve, CurveShape};
+use disk::Disk;
+use heightfield::Heightfield;
+use instance::{Instance, InstanceBVH};
+use medium::ConstantMedium;
+use quad::Quad;
 pub use rect::{Rect, RectAxis};
+use sdf::{SdfDesc, SdfObject};
 use sphere::Sphere;
-use transform::{Rotate, Translate};
+use subsurface::SubsurfaceObject;
+use torus::Torus;
+use transform::{Affine, Animated, Keyframe, Rotate, Translate};
+use volume::{GridMedium, VoxelGrid};
 
 pub type Point = Point3<f64>;
 pub type Vector = Vector3<f64>;
@@ -40,19 +76,27 @@ pub trait Hittable: Send + Sync {
         eprintln!("Warning: Attempting to sample PDF for unimplemented object");
         0.0
     }
-    fn random(&self, _origin: Point) -> Vector {
+    fn random(&self, _origin: Point, _time: f64) -> Vector {
         eprintln!("Warning: Attempting to sample PDF for unimplemented object");
         Vector::x()
     }
-    fn emit(&self) -> (Ray, Unit<Vector>, Color) {
+    fn emit(&self, _time: f64) -> (Ray, Unit<Vector>, Color) {
         unimplemented!("Not all objects implement emit() yet");
     }
+    /// Estimated luminance of the total power emitted, used to decide how
+    /// often to sample a light.
+    fn power(&self) -> f64 {
+        0.0
+    }
 }
 
 pub struct Scene {
-    pub background: Color,
+    pub background: Arc<Background>,
     bvh: Arc<BVHNode>,
-    pub lights: HittableList,
+    pub lights: LightTree,
+    pub delta_lights: Vec<Light>,
+    /// Selection of delta lights by power.
+    delta_power: Distribution1D,
 }
 
 impl Scene {
@@ -60,6 +104,16 @@ impl Scene {
     fn from_objects(
         background: Color,
         objects: Vec<Arc<dyn Hittable>>,
+    ) -> Option<Scene> {
+        Scene::new(Arc::new(Background::Color(background)), objects, vec![])
+    }
+
+    /// Builds a scene lit by its emissive objects, `delta_lights` and
+    /// `background`, at least one of which must emit light.
+    fn new(
+        background: Arc<Background>,
+        objects: Vec<Arc<dyn Hittable>>,
+        delta_lights: Vec<Light>,
     ) -> Option<Scene> {
         let mut lights = vec![];
         for obj in &objects {
@@ -67,26 +121,70 @@ impl Scene {
                 lights.push(obj.clone());
             }
         }
-        if lights.is_empty() {
+        if lights.is_empty()
+            && delta_lights.is_empty()
+            && !background.is_light()
+        {
             return None;
         }
+        let bvh = BVHNode::from_hittables(objects);
+        let bounds = bvh.bounding_box();
+        let delta_power = Distribution1D::new(
+            delta_lights.iter().map(|l| l.power(&bounds)).collect(),
+        );
         Some(Scene {
             background,
-            bvh: BVHNode::from_hittables(objects),
-            lights: HittableList::new(lights),
+            bvh,
+            lights: LightTree::new(lights),
+            delta_lights,
+            delta_power,
         })
     }
 
+    /// Chooses a delta light with probability proportional to its power,
+    /// returning it with that probability.
+    pub fn choose_delta_light(&self) -> Option<(&Light, f64)> {
+        if self.delta_lights.is_empty() {
+            return None;
+        }
+        let (i, prob) = self.delta_power.sample_discrete(random());
+        Some((&self.delta_lights[i], prob))
+    }
+
+    /// Generates a ray leaving a light chosen among the emissive objects and
+    /// delta lights in proportion to their power, at `time`. The power the
+    /// ray carries is divided by the probability of choosing its light.
+    #[allow(dead_code)]
+    pub fn emit(&self, time: f64) -> (Ray, Unit<Vector>, Color) {
+        let bounds = self.bvh.bounding_box();
+        let objects = self.lights.power();
+        let deltas: f64 =
+            self.delta_lights.iter().map(|l| l.power(&bounds)).sum();
+        let total = objects + deltas;
+        let use_objects = if total > 0.0 {
+            random() * total < objects
+        } else {
+            !self.lights.is_empty()
+        };
+        if !use_objects {
+            if let Some((light, prob)) = self.choose_delta_light() {
+                let share = if total > 0.0 { deltas / total } else { 1.0 };
+                let (mut ray, normal, color) = light.emit(&bounds);
+                ray.time = time;
+                return (ray, normal, color / (share * prob));
+            }
+        }
+        let share = if total > 0.0 { objects / total } else { 1.0 };
+        let (mut ray, normal, color) = self.lights.emit(time);
+        ray.time = time;
+        (ray, normal, color / share)
+    }
+
     #[inline]
     pub fn hit(&self, ray: Ray, range: Range<f64>) -> Option<Hit> {
         self.bvh.hit(ray, range)
     }
 
-    #[inline(never)]
-    pub fn from_config(config: &Config) -> (Scene, Camera) {
-        parser::parse(&config)
-    }
-
     #[allow(dead_code)]
     pub fn random(config: &Config, n: u32) -> (Scene, Camera) {
         let mut objects: Vec<Arc<dyn Hittable>> = vec![];
@@ -101,11 +199,36 @@ impl Scene {
             1000.0,
         ));
 
+        // The small spheres are instances of one unit sphere, made of
+        // materials drawn from a palette, so that large `n` fit in memory.
+        let palette: Vec<Arc<Material>> = (0..256)
+            .map(|_| {
+                let mat_rand = random();
+                if mat_rand < 0.8 {
+                    let albedo = Color::new(random(), random(), random());
+                    Arc::new(Material::Lambertian(Arc::new(Texture::Solid(
+                        albedo,
+                    ))))
+                } else if mat_rand < 0.95 {
+                    let albedo = Color::new(
+                        random_range(0.5..1.0),
+                        random_range(0.5..1.0),
+                        random_range(0.5..1.0),
+                    );
+                    let fuzz = random_range(0.0..0.5);
+                    Arc::new(Material::Metal(albedo, fuzz))
+                } else {
+                    Arc::new(Material::Dielectric(IOR::Constant(1.5)))
+                }
+            })
+            .collect();
+        let unit_sphere = Sphere::new(ground_material, Point::origin(), 1.0);
+        let mut instances = vec![];
+
         let count = n as i64;
 
         for a in -count..count {
             for b in -count..count {
-                let mat_rand = random();
                 let center = Point::new(
                     a as f64 + 0.9 * random(),
                     0.2,
@@ -113,31 +236,21 @@ impl Scene {
                 );
 
                 if (center - Vector::new(4.0, 0.2, 0.0)).coords.norm() > 0.9 {
-                    let material;
-
-                    if mat_rand < 0.8 {
-                        let albedo = Color::new(random(), random(), random());
-                        material = Arc::new(Material::Lambertian(Arc::new(
-                            Texture::Solid(albedo),
-                        )));
-                    } else if mat_rand < 0.95 {
-                        let albedo = Color::new(
-                            random_range(0.5..1.0),
-                            random_range(0.5..1.0),
-                            random_range(0.5..1.0),
-                        );
-                        let fuzz = random_range(0.0..0.5);
-                        material = Arc::new(Material::Metal(albedo, fuzz));
-                    } else {
-                        material = Arc::new(Material::Dielectric(1.5));
-                    }
-
-                    objects.push(Sphere::new(material, center, 0.2));
+                    let i = (random() * palette.len() as f64) as usize;
+                    let material = palette[i.min(palette.len() - 1)].clone();
+                    let matrix = Matrix4::new_translation(&center.coords)
+                        * Matrix4::new_scaling(0.2);
+                    instances.push(
+                        Instance::new(0, &matrix, Some(material)).unwrap(),
+                    );
                 }
             }
         }
+        if !instances.is_empty() {
+            objects.push(InstanceBVH::new(vec![unit_sphere], instances));
+        }
 
-        let material1 = Arc::new(Material::Dielectric(1.5));
+        let material1 = Arc::new(Material::Dielectric(IOR::Constant(1.5)));
         objects.push(Sphere::new(material1, Point::new(0.0, 1.0, 0.0), 1.0));
 
         let material2 = Arc::new(Material::Lambertian(Arc::new(
@@ -154,7 +267,8 @@ impl Scene {
         let at = Point::new(0.0, 0.0, 0.0);
         let up = Vector::new(0.0, 1.0, 0.0);
         let dist = 10.0;
-        let camera = Camera::new(from, at, up, 20.0, aspect_ratio, 0.1, dist);
+        let camera =
+            Camera::perspective(from, at, up, 20.0, aspect_ratio, 0.1, dist);
 
         (
             Self::from_objects(Color::new(0.5, 0.7, 1.0), objects).unwrap(),
@@ -169,10 +283,16 @@ impl Scene {
         let at = Point::new(0.0, 0.0, 0.0);
         let up = Vector::new(0.0, 1.0, 0.0);
         let dist = 10.0;
-        let camera = Camera::new(from, at, up, 20.0, aspect_ratio, 0.1, dist);
+        let camera =
+            Camera::perspective(from, at, up, 20.0, aspect_ratio, 0.1, dist);
 
+        let mapping = ImageMapping::default();
         let earth_tex = Arc::new(Texture::Image(
-            image::open("images/earthmap.jpg").unwrap(),
+            Arc::new(MipMap::new(
+                &image::open("images/earthmap.jpg").unwrap(),
+                mapping.color_space,
+            )),
+            mapping,
         ));
         let global = Sphere::new(
             Arc::new(Material::Lambertian(earth_tex)),
@@ -187,6 +307,7 @@ impl Scene {
     }
 }
 
+#[derive(Copy, Clone)]
 pub struct Hit<'obj> {
     pub point: Point,
     pub normal: Unit<Vector>,
@@ -194,6 +315,11 @@ pub struct Hit<'obj> {
     pub front_facing: bool,
     pub material: &'obj Material,
     pub uv: (f64, f64),
+    /// Derivatives of `uv` with respect to the image x and y directions,
+    /// known only for rays which carry differentials.
+    pub duv: Option<((f64, f64), (f64, f64))>,
+    /// Direction along fibres, for curves, around which hair scatters light.
+    pub tangent: Option<Unit<Vector>>,
 }
 
 impl<'obj> Hit<'obj> {
@@ -214,75 +340,54 @@ impl<'obj> Hit<'obj> {
             front_facing,
             material,
             uv,
+            duv: None,
+            tangent: None,
         }
     }
-}
-
-pub struct HittableList {
-    hittables: Vec<Arc<dyn Hittable>>,
-}
-
-impl HittableList {
-    pub fn new(hittables: Vec<Arc<dyn Hittable>>) -> HittableList {
-        HittableList { hittables }
-    }
-
-    pub fn is_empty(&self) -> bool {
-        self.hittables.is_empty()
-    }
-}
-
-impl Hittable for HittableList {
-    fn is_light(&self) -> bool {
-        false
-    }
-
-    fn bounding_box(&self) -> AABB {
-        if self.is_empty() {
-            return AABB::new(Point::origin(), Point::origin());
-        }
-        let mut result = self.hittables[0].bounding_box();
-        for h in &self.hittables {
-            result = AABB::containing(result, h.bounding_box());
-        }
-        result
-    }
 
-    fn hit(&self, ray: Ray, mut range: Range<f64>) -> Option<Hit> {
-        let mut result: Option<Hit> = None;
-        for h in &self.hittables {
-            if let Some(hit) = h.hit(ray, range.clone()) {
-                range.end = hit.t;
-                result = Some(hit);
-            }
+    /// Computes `duv` from the ray differentials given the partial derivatives
+    /// of the surface position with respect to `u` and `v`.
+    pub fn with_partials(
+        mut self,
+        ray: Ray,
+        dpdu: Vector,
+        dpdv: Vector,
+    ) -> Self {
+        let diff = match ray.diff {
+            Some(diff) => diff,
+            None => return self,
+        };
+        // Intersect the offset rays with the tangent plane at the hit point.
+        let n = *self.normal;
+        let d = n.dot(&self.point.coords);
+        let dx_t = (d - n.dot(&diff.dx_origin.coords)) / n.dot(&diff.dx_dir);
+        let dy_t = (d - n.dot(&diff.dy_origin.coords)) / n.dot(&diff.dy_dir);
+        if !dx_t.is_finite() || !dy_t.is_finite() {
+            return self;
         }
-        result
-    }
-
-    fn pdf(&self, ray: Ray) -> f64 {
-        let weight = (self.hittables.len() as f64).recip();
-        let mut sum = 0.0;
-        for h in &self.hittables {
-            sum += weight * h.pdf(ray);
+        let dpdx = (diff.dx_origin + dx_t * diff.dx_dir) - self.point;
+        let dpdy = (diff.dy_origin + dy_t * diff.dy_dir) - self.point;
+
+        // Solve the overdetermined system dp = dpdu * du + dpdv * dv using the
+        // two axes along which the normal is smallest.
+        let (a, b) = if n.x.abs() > n.y.abs() && n.x.abs() > n.z.abs() {
+            (1, 2)
+        } else if n.y.abs() > n.z.abs() {
+            (0, 2)
+        } else {
+            (0, 1)
+        };
+        let det = dpdu[a] * dpdv[b] - dpdv[a] * dpdu[b];
+        if det.abs() < 1e-12 {
+            return self;
         }
-        sum
-    }
-
-    fn random(&self, origin: Point) -> Vector {
-        let h = &self
-            .hittables
-            .as_slice()
-            .choose(&mut rand::thread_rng())
-            .unwrap();
-        h.random(origin)
-    }
-
-    fn emit(&self) -> (Ray, Unit<Vector>, Color) {
-        let h = &self
-            .hittables
-            .as_slice()
-            .choose(&mut rand::thread_rng())
-            .expect("no hittables populated");
-        h.emit()
+        let solve = |dp: Vector| {
+            (
+                (dpdv[b] * dp[a] - dpdv[a] * dp[b]) / det,
+                (dpdu[a] * dp[b] - dpdu[b] * dp[a]) / det,
+            )
+        };
+        self.duv = Some((solve(dpdx), solve(dpdy)));
+        self
     }
 }
@@ -16,7 +16,7 @@ impl ONB {
         } else {
             Vector::x_axis()
         };
-        let v = Unit::new_unchecked(w.cross(&a));
+        let v = Unit::new_normalize(w.cross(&a));
         let u = Unit::new_unchecked(w.cross(&v));
         ONB { u, v, w }
     }
@@ -24,4 +24,14 @@ impl ONB {
     pub fn localize(&self, xyz: Vector) -> Vector {
         (xyz.x * *self.u) + (xyz.y * *self.v) + (xyz.z * *self.w)
     }
+
+    /// Components of `vector` along each axis of the basis, undoing
+    /// `localize`.
+    pub fn project(&self, vector: Vector) -> Vector {
+        Vector::new(
+            vector.dot(&self.u),
+            vector.dot(&self.v),
+            vector.dot(&self.w),
+        )
+    }
 }
@@ -1,24 +1,359 @@
+use super::animation::{Animation, AnimationDesc, Target};
 use super::*;
-use nalgebra::Rotation3;
+use crate::camera::{Aperture, FisheyeMapping, Projection};
+use crate::color::luminance;
+use crate::distribution::Distribution2D;
+use crate::hair::Hair;
+use crate::ies::IESProfile;
+use crate::material::{Emitter, Medium, Sides, Subsurface};
+use crate::spectrum::blackbody_rgb;
+use crate::texture::{
+    ColorSpace, FilterMode, Fractal, HdrImage, ImageMapping, MipMap,
+    NoiseBasis, Pattern, VoronoiFeature, WrapMode,
+};
+use image::DynamicImage;
+use nalgebra::{Isometry3, Matrix3, Matrix4, Rotation3, UnitQuaternion, U3};
 use serde::Deserialize;
 use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
+/// Position and projection of the camera. Perspective cameras need `vfov`
+/// in degrees, orthographic cameras the `height` of their view in scene
+/// units, and fisheye cameras may set their `fov` in degrees across the
+/// image circle and its `mapping`. Omnidirectional stereo cameras take the
+/// distance `ipd` between the eyes.
+///
+/// Perspective cameras may instead be described physically, by the
+/// `focal_length` of their lens and the width of their `sensor` in
+/// millimetres, and their `fstop`. `unit` gives the length of a scene unit in
+/// metres, to size the aperture. Setting the `shutter` time in seconds or the
+/// `iso` exposes the image as a camera would, treating radiance as being in
+/// cd/m^2. The aperture is formed by a number of `blades`, rotated by
+/// `blade_rotation` degrees, or by the bright parts of an `aperture_image`,
+/// and `cat_eye` vignettes it towards the corners of the image.
+///
+/// For motion blur, the shutter is open from `shutter_open` until
+/// `shutter_close` in scene time, over which animated objects move.
 #[derive(Debug, Deserialize)]
 struct CameraDesc {
+    kind: Option<CameraKind>,
     from: Point,
     at: Point,
     up: Vector,
-    dist: f64,
-    vfov: f64,
-    aperture: f64,
+    dist: Option<f64>,
+    vfov: Option<f64>,
+    aperture: Option<f64>,
+    height: Option<f64>,
+    fov: Option<f64>,
+    mapping: Option<FisheyeMapping>,
+    ipd: Option<f64>,
+    focal_length: Option<f64>,
+    sensor: Option<f64>,
+    fstop: Option<f64>,
+    unit: Option<f64>,
+    shutter: Option<f64>,
+    iso: Option<f64>,
+    blades: Option<u32>,
+    blade_rotation: Option<f64>,
+    aperture_image: Option<PathBuf>,
+    cat_eye: Option<f64>,
+    shutter_open: Option<f64>,
+    shutter_close: Option<f64>,
+}
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+enum CameraKind {
+    Perspective,
+    Orthographic,
+    Fisheye,
+    Equirectangular,
+    OmniStereo,
+}
+
+impl CameraDesc {
+    fn build(&self, aspect_ratio: f64, dir: &Path) -> Result<Camera, String> {
+        let mut camera = self.build_projection(aspect_ratio, dir)?;
+        let open = self.shutter_open.unwrap_or(0.0);
+        let close = self.shutter_close.unwrap_or(open);
+        if close < open {
+            return Err("camera shutter closes before it opens".into());
+        }
+        camera.shutter = (open, close);
+        Ok(camera)
+    }
+
+    fn build_projection(
+        &self,
+        aspect_ratio: f64,
+        dir: &Path,
+    ) -> Result<Camera, String> {
+        let (from, at, up) = (self.from, self.at, self.up);
+        let require = |value: Option<f64>, name: &str| {
+            value.ok_or_else(|| format!("camera needs a {}", name))
+        };
+        let projection = match self.kind.unwrap_or(CameraKind::Perspective) {
+            CameraKind::Perspective => {
+                return self.build_perspective(aspect_ratio, dir)
+            }
+            CameraKind::Orthographic => Projection::Orthographic {
+                height: require(self.height, "height")?,
+            },
+            CameraKind::Fisheye => {
+                let fov = self.fov.unwrap_or(180.0);
+                if fov <= 0.0 || fov > 360.0 {
+                    return Err(format!("fisheye fov {} is out of range", fov));
+                }
+                Projection::Fisheye {
+                    fov: fov.to_radians(),
+                    mapping: self.mapping.unwrap_or(FisheyeMapping::Equisolid),
+                }
+            }
+            CameraKind::Equirectangular => Projection::Equirectangular,
+            CameraKind::OmniStereo => Projection::OmniStereo {
+                ipd: self.ipd.unwrap_or(0.064),
+            },
+        };
+        Ok(Camera::new(from, at, up, aspect_ratio, projection))
+    }
+
+    fn build_perspective(
+        &self,
+        aspect_ratio: f64,
+        dir: &Path,
+    ) -> Result<Camera, String> {
+        let (from, at) = (self.from, self.at);
+        let vfov = match (self.vfov, self.focal_length) {
+            (Some(vfov), None) => vfov,
+            (None, Some(focal_length)) => {
+                let sensor_height = self.sensor.unwrap_or(36.0) / aspect_ratio;
+                2.0 * (sensor_height / (2.0 * focal_length)).atan().to_degrees()
+            }
+            (Some(_), Some(_)) => {
+                return Err("camera sets both vfov and focal_length".into())
+            }
+            (None, None) => {
+                return Err("camera needs a vfov or focal_length".into())
+            }
+        };
+        let aperture = match (self.aperture, self.fstop, self.focal_length) {
+            (Some(_), Some(_), _) => {
+                return Err("camera sets both aperture and fstop".into())
+            }
+            (aperture, None, _) => aperture.unwrap_or(0.0),
+            // The aperture is the focal length divided by the f-number.
+            (None, Some(fstop), Some(focal_length)) => {
+                focal_length / fstop / 1000.0 / self.unit.unwrap_or(1.0)
+            }
+            (None, Some(_), None) => {
+                return Err("camera fstop needs a focal_length".into())
+            }
+        };
+        let mut camera = Camera::perspective(
+            from,
+            at,
+            self.up,
+            vfov,
+            aspect_ratio,
+            aperture,
+            self.dist.unwrap_or_else(|| (at - from).norm()),
+        );
+
+        if self.shutter.is_some() || self.iso.is_some() {
+            let fstop = self
+                .fstop
+                .ok_or_else(|| "camera exposure needs an fstop".to_string())?;
+            // Saturation based exposure, with the usual lens transmittance
+            // and vignetting factor of 0.65.
+            let (shutter, iso) = (
+                self.shutter.unwrap_or(1.0 / 125.0),
+                self.iso.unwrap_or(100.0),
+            );
+            camera.exposure = 0.65 * shutter * iso / (78.0 * fstop * fstop);
+        }
+
+        let shape = match (self.blades, &self.aperture_image) {
+            (Some(_), Some(_)) => {
+                return Err("camera sets both blades and aperture_image".into())
+            }
+            (Some(blades), None) if blades < 3 => {
+                return Err("camera aperture needs at least 3 blades".into())
+            }
+            (Some(blades), None) => Aperture::Polygon(
+                blades,
+                self.blade_rotation.unwrap_or(0.0).to_radians(),
+            ),
+            (None, Some(path)) => {
+                let path = dir.join(path);
+                let image = image::open(&path)
+                    .map_err(|e| format!("{}: {}", path.display(), e))?
+                    .to_luma();
+                let (width, height) = image.dimensions();
+                let func: Vec<f64> =
+                    image.pixels().map(|p| p[0] as f64 / 255.0).collect();
+                if func.iter().all(|&v| v <= 0.0) {
+                    return Err(format!(
+                        "{}: aperture is closed",
+                        path.display()
+                    ));
+                }
+                Aperture::Image(Distribution2D::new(
+                    &func,
+                    width as usize,
+                    height as usize,
+                ))
+            }
+            (None, None) => Aperture::Disc,
+        };
+        Ok(camera.with_aperture(shape, self.cat_eye.unwrap_or(0.0)))
+    }
 }
 
 #[derive(Debug, Deserialize)]
 #[serde(tag = "kind")]
 enum TextureDesc {
-    Solid { color: Color },
-    Checker { texture1: String, texture2: String },
+    Solid {
+        color: Color,
+    },
+    Checker {
+        texture1: String,
+        texture2: String,
+    },
+    Image {
+        /// Relative to the directory containing the scene file.
+        path: PathBuf,
+        scale: Option<(f64, f64)>,
+        offset: Option<(f64, f64)>,
+        rotation: Option<f64>,
+        wrap: Option<WrapMode>,
+        color_space: Option<ColorSpace>,
+        filter: Option<FilterMode>,
+    },
+    Noise {
+        low: String,
+        high: String,
+        basis: Option<NoiseBasis>,
+        scale: Option<f64>,
+    },
+    FBm {
+        low: String,
+        high: String,
+        scale: Option<f64>,
+        #[serde(flatten)]
+        fractal: FractalDesc,
+    },
+    Turbulence {
+        low: String,
+        high: String,
+        scale: Option<f64>,
+        #[serde(flatten)]
+        fractal: FractalDesc,
+    },
+    Marble {
+        low: String,
+        high: String,
+        scale: Option<f64>,
+        strength: Option<f64>,
+        #[serde(flatten)]
+        fractal: FractalDesc,
+    },
+    Wood {
+        low: String,
+        high: String,
+        scale: Option<f64>,
+        rings: Option<f64>,
+        strength: Option<f64>,
+        #[serde(flatten)]
+        fractal: FractalDesc,
+    },
+    Voronoi {
+        low: String,
+        high: String,
+        feature: Option<VoronoiFeature>,
+        scale: Option<f64>,
+    },
+    Gradient {
+        low: String,
+        high: String,
+        start: Point,
+        end: Point,
+    },
+    Mix {
+        texture1: String,
+        texture2: String,
+        factor: String,
+    },
+    Multiply {
+        texture1: String,
+        texture2: String,
+    },
+    Add {
+        texture1: String,
+        texture2: String,
+    },
+    Invert {
+        input: String,
+    },
+    ColorRamp {
+        input: String,
+        stops: Vec<RampStopDesc>,
+    },
+    HSV {
+        input: String,
+        /// Hue shift in degrees.
+        hue: Option<f64>,
+        saturation: Option<f64>,
+        value: Option<f64>,
+    },
+    UV,
+    Position,
+    Normal,
+    Triplanar {
+        input: String,
+        scale: Option<f64>,
+        sharpness: Option<f64>,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+struct RampStopDesc {
+    position: f64,
+    color: Color,
+}
+
+#[derive(Debug, Deserialize)]
+struct FractalDesc {
+    basis: Option<NoiseBasis>,
+    octaves: Option<u32>,
+    lacunarity: Option<f64>,
+    gain: Option<f64>,
+}
+
+impl FractalDesc {
+    fn build(&self) -> Fractal {
+        let default = Fractal::default();
+        Fractal {
+            basis: self.basis.unwrap_or(default.basis),
+            octaves: self.octaves.unwrap_or(default.octaves),
+            lacunarity: self.lacunarity.unwrap_or(default.lacunarity),
+            gain: self.gain.unwrap_or(default.gain),
+        }
+    }
+}
+
+/// Either a constant IOR or the coefficients of a dispersion formula.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum IORDesc {
+    Constant(f64),
+    Cauchy {
+        cauchy: (f64, f64),
+    },
+    Sellmeier {
+        sellmeier_b: [f64; 3],
+        sellmeier_c: [f64; 3],
+    },
 }
 
 #[derive(Debug, Deserialize)]
@@ -38,13 +373,77 @@ enum MaterialDesc {
         roughness: f64,
     },
     Dielectric {
-        ior: f64,
+        ior: IORDesc,
     },
+    /// Emits `texture` times a blackbody color at `temperature` Kelvin,
+    /// scaled to a radiance `intensity` in W/(sr m^2) or to a total `power`
+    /// in watts for each object using the material. An IES `profile`
+    /// modulates the emission around its nadir `axis`, in object space.
     Emission {
-        texture: String,
+        texture: Option<String>,
+        temperature: Option<f64>,
+        intensity: Option<f64>,
+        power: Option<f64>,
+        sides: Option<Sides>,
+        profile: Option<PathBuf>,
+        axis: Option<Vector>,
+    },
+    /// Fills objects using the material with a participating medium.
+    Medium(MediumDesc),
+    /// Scatters light beneath the surface of closed objects, through an
+    /// interior with the given `mean_free_path` in scene units per channel.
+    /// `albedo` is the approximate diffuse color which results.
+    Subsurface {
+        albedo: Option<Color>,
+        mean_free_path: Color,
+        ior: Option<f64>,
+        g: Option<f64>,
+    },
+    /// Fibres of hair or fur pigmented by concentrations of `eumelanin` and
+    /// `pheomelanin`, tinted roughly `color`, or with the given `absorption`
+    /// per unit of fibre radius, and brown by default. `roughness` and
+    /// `azimuthal_roughness`, from 0 to 1, blur highlights along and around
+    /// the fibres, whose scales tilt by `scale_angle` degrees.
+    Hair {
+        eumelanin: Option<f64>,
+        pheomelanin: Option<f64>,
+        color: Option<Color>,
+        absorption: Option<Color>,
+        ior: Option<f64>,
+        roughness: Option<f64>,
+        azimuthal_roughness: Option<f64>,
+        scale_angle: Option<f64>,
     },
 }
 
+/// Absorption and scattering coefficients of a homogeneous medium per unit
+/// length, and the asymmetry `g` of its phase function from -1 for back
+/// scattering to 1 for forward scattering.
+#[derive(Debug, Deserialize)]
+struct MediumDesc {
+    absorption: Option<Color>,
+    scattering: Option<Color>,
+    g: Option<f64>,
+}
+
+impl MediumDesc {
+    fn build(&self) -> Result<Medium, String> {
+        let g = self.g.unwrap_or(0.0);
+        if g <= -1.0 || g >= 1.0 {
+            return Err(format!("medium asymmetry {} is not in (-1, 1)", g));
+        }
+        let medium = Medium {
+            absorption: self.absorption.unwrap_or_else(Color::zeros),
+            scattering: self.scattering.unwrap_or_else(Color::zeros),
+            g,
+        };
+        if medium.absorption.min() < 0.0 || medium.scattering.min() < 0.0 {
+            return Err("medium coefficients must not be negative".into());
+        }
+        Ok(medium)
+    }
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(tag = "kind")]
 enum GeomDesc {
@@ -52,6 +451,8 @@ enum GeomDesc {
         material: String,
         center: Point,
         radius: f64,
+        /// Center at time 1, if the sphere moves.
+        end_center: Option<Point>,
     },
     Rect {
         material: String,
@@ -65,79 +466,973 @@ enum GeomDesc {
         start: Point,
         end: Point,
     },
+    /// Disk of `radius` facing along `normal`, which is an annulus when it
+    /// has a hole of `inner_radius`.
+    Disk {
+        material: String,
+        center: Point,
+        normal: Vector,
+        radius: f64,
+        inner_radius: Option<f64>,
+    },
+    /// Cylinder of `radius` around the line from `start` to `end`, closed
+    /// off at either end unless it is not `capped`.
+    Cylinder {
+        material: String,
+        start: Point,
+        end: Point,
+        radius: f64,
+        capped: Option<bool>,
+    },
+    /// Cone with a base of `radius` at `start`, narrowing towards `end` to a
+    /// point or to `end_radius`, and capped like cylinders.
+    Cone {
+        material: String,
+        start: Point,
+        end: Point,
+        radius: f64,
+        end_radius: Option<f64>,
+        capped: Option<bool>,
+    },
+    /// Ring facing along `axis`, made by sweeping a tube of `minor_radius`
+    /// around a circle of `major_radius`.
+    Torus {
+        material: String,
+        center: Point,
+        axis: Vector,
+        major_radius: f64,
+        minor_radius: f64,
+    },
+    /// Parallelogram spanning `edge1` and `edge2` from `corner`, facing
+    /// along their cross product.
+    Quad {
+        material: String,
+        corner: Point,
+        edge1: Vector,
+        edge2: Vector,
+    },
+    /// Heterogeneous medium filling the box from `start` to `end`, with the
+    /// coefficients of its `Medium` material scaled by a `density` grid. An
+    /// optional `temperature` grid, scaled to Kelvin by `temperature_scale`,
+    /// makes it glow with radiance `emission` at 1000 K. `.raw` grids need
+    /// their `resolution`.
+    Volume {
+        material: String,
+        start: Point,
+        end: Point,
+        density: PathBuf,
+        temperature: Option<PathBuf>,
+        resolution: Option<[usize; 3]>,
+        temperature_scale: Option<f64>,
+        emission: Option<f64>,
+    },
+    /// Terrain spanning the box from `start` to `end`, whose height is read
+    /// from a grayscale `image`, from the bottom of the box for black to its
+    /// top for white. Image rows run along x, starting from the lower z
+    /// edge.
+    Heightfield {
+        material: String,
+        image: PathBuf,
+        start: Point,
+        end: Point,
+    },
+    /// Surface of a signed distance function `shape`, found by sphere
+    /// tracing. Its `bounds`, given as opposite corners, are derived from the
+    /// shape unless given.
+    Sdf {
+        material: String,
+        shape: SdfDesc,
+        bounds: Option<(Point, Point)>,
+    },
+    /// Cubic Bézier curve with control `points`, four for its first segment
+    /// and three more for each one after, `width` wide at its start and
+    /// tapering to `end_width`. Its `shape` is `Flat`, `Cylinder` by default,
+    /// or `Ribbon`, which turns from the first of `normals` at its start to
+    /// the second at its end.
+    Curve {
+        material: String,
+        points: Vec<Point>,
+        width: f64,
+        end_width: Option<f64>,
+        shape: Option<CurveType>,
+        normals: Option<(Vector, Vector)>,
+    },
+    /// Strands of hair or fur read from a file with one strand per line,
+    /// listing `x y z width` for each point the strand passes through.
+    /// Widths are scaled by `width_scale`, and the strands are `Flat` or
+    /// `Cylinder` curves as set by `shape`.
+    Curves {
+        material: String,
+        path: PathBuf,
+        shape: Option<CurveType>,
+        width_scale: Option<f64>,
+    },
+    /// Solid inside any of `objects`, which must be closed surfaces facing
+    /// outwards, without the surfaces between them.
+    Union { objects: Vec<ObjectDesc> },
+    /// Solid inside every one of `objects`.
+    Intersection { objects: Vec<ObjectDesc> },
+    /// Solid inside the first of `objects` but outside all the others.
+    Difference { objects: Vec<ObjectDesc> },
+    /// Copy of a `group` of objects, which shares their geometry with every
+    /// other instance of the group, optionally replacing their materials
+    /// with `material`.
+    Instance {
+        group: String,
+        material: Option<String>,
+    },
 }
 
+impl GeomDesc {
+    fn material(&self) -> Option<&str> {
+        match self {
+            GeomDesc::Sphere { material, .. }
+            | GeomDesc::Rect { material, .. }
+            | GeomDesc::Block { material, .. }
+            | GeomDesc::Disk { material, .. }
+            | GeomDesc::Cylinder { material, .. }
+            | GeomDesc::Cone { material, .. }
+            | GeomDesc::Torus { material, .. }
+            | GeomDesc::Quad { material, .. }
+            | GeomDesc::Heightfield { material, .. }
+            | GeomDesc::Sdf { material, .. }
+            | GeomDesc::Curve { material, .. }
+            | GeomDesc::Curves { material, .. }
+            | GeomDesc::Volume { material, .. } => Some(material),
+            GeomDesc::Union { .. }
+            | GeomDesc::Intersection { .. }
+            | GeomDesc::Difference { .. }
+            | GeomDesc::Instance { .. } => None,
+        }
+    }
+
+    /// Surface area of the geometry before any transforms.
+    fn area(&self) -> f64 {
+        match self {
+            GeomDesc::Sphere { radius, .. } => 4.0 * PI * radius * radius,
+            GeomDesc::Rect { start, end, .. } => {
+                ((end.0 - start.0) * (end.1 - start.1)).abs()
+            }
+            GeomDesc::Block { start, end, .. } => {
+                let d = (end - start).abs();
+                2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+            }
+            GeomDesc::Disk {
+                radius,
+                inner_radius,
+                ..
+            } => PI * (radius * radius - inner_radius.unwrap_or(0.0).powi(2)),
+            &GeomDesc::Cylinder {
+                start,
+                end,
+                radius,
+                capped,
+                ..
+            } => GeomDesc::cone_area(start, end, radius, radius, capped),
+            &GeomDesc::Cone {
+                start,
+                end,
+                radius,
+                end_radius,
+                capped,
+                ..
+            } => GeomDesc::cone_area(
+                start,
+                end,
+                radius,
+                end_radius.unwrap_or(0.0),
+                capped,
+            ),
+            GeomDesc::Torus {
+                major_radius,
+                minor_radius,
+                ..
+            } => 4.0 * PI * PI * major_radius * minor_radius,
+            GeomDesc::Quad { edge1, edge2, .. } => edge1.cross(edge2).norm(),
+            GeomDesc::Volume { .. }
+            | GeomDesc::Heightfield { .. }
+            | GeomDesc::Sdf { .. }
+            | GeomDesc::Curve { .. }
+            | GeomDesc::Curves { .. }
+            | GeomDesc::Union { .. }
+            | GeomDesc::Intersection { .. }
+            | GeomDesc::Difference { .. }
+            | GeomDesc::Instance { .. } => 0.0,
+        }
+    }
+
+    fn cone_area(
+        start: Point,
+        end: Point,
+        radius: f64,
+        end_radius: f64,
+        capped: Option<bool>,
+    ) -> f64 {
+        let slant = (end_radius - radius).hypot((end - start).norm());
+        let side = PI * (radius + end_radius) * slant;
+        if capped.unwrap_or(true) {
+            side + PI * (radius * radius + end_radius * end_radius)
+        } else {
+            side
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+enum CurveType {
+    Flat,
+    Cylinder,
+    Ribbon,
+}
+
+/// Object placed in the scene by scaling it by `scale`, rotating it by
+/// `rotate` degrees about each axis, translating it by `translate`, moving
+/// it to `look_at` and finally transforming it by the 4x4 `matrix`, given
+/// row by row, each of which is optional.
 #[derive(Debug, Deserialize)]
 struct ObjectDesc {
+    /// Name by which animation tracks refer to the object.
+    name: Option<String>,
     #[serde(flatten)]
     geometry: GeomDesc,
+    scale: Option<ScaleDesc>,
     rotate: Option<Vector>,
     translate: Option<Vector>,
+    look_at: Option<LookAtDesc>,
+    matrix: Option<[[f64; 4]; 4]>,
+    keyframes: Option<Vec<KeyframeDesc>>,
+}
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(untagged)]
+enum ScaleDesc {
+    Uniform(f64),
+    Axes(Vector),
+}
+
+/// Frame which turns the z axis of an object towards `at`, with its y axis
+/// towards `up`, and moves its origin to `from`.
+#[derive(Debug, Deserialize)]
+struct LookAtDesc {
+    from: Point,
+    at: Point,
+    up: Option<Vector>,
+}
+
+impl ObjectDesc {
+    fn is_transformed(&self) -> bool {
+        self.scale.is_some()
+            || self.rotate.is_some()
+            || self.translate.is_some()
+            || self.look_at.is_some()
+            || self.matrix.is_some()
+    }
+
+    /// Transform from the object to the scene, when it takes more than a
+    /// rotation and a translation.
+    fn affine(&self) -> Result<Option<Matrix4<f64>>, String> {
+        if self.scale.is_none()
+            && self.look_at.is_none()
+            && self.matrix.is_none()
+        {
+            return Ok(None);
+        }
+        self.to_scene().map(Some)
+    }
+
+    /// Factor by which the transform scales surface areas, which is only
+    /// the same everywhere for uniform scales.
+    fn area_scale(&self) -> Result<f64, String> {
+        let m = match self.affine()? {
+            Some(m) => m,
+            None => return Ok(1.0),
+        };
+        let linear = m.fixed_slice::<U3, U3>(0, 0);
+        let gram = linear.transpose() * linear;
+        let scale = gram.trace() / 3.0;
+        if (gram - Matrix3::identity() * scale).norm() > 1e-9 * scale {
+            return Err("power needs a uniformly scaled object".into());
+        }
+        Ok(scale)
+    }
+
+    fn to_scene(&self) -> Result<Matrix4<f64>, String> {
+        let scale = match self.scale {
+            Some(ScaleDesc::Uniform(s)) => Vector::repeat(s),
+            Some(ScaleDesc::Axes(v)) => v,
+            None => Vector::repeat(1.0),
+        };
+        let mut m = Matrix4::new_nonuniform_scaling(&scale);
+        if let Some(v) = &self.rotate {
+            m = Rotation3::new(v.map(f64::to_radians)).to_homogeneous() * m;
+        }
+        if let Some(v) = &self.translate {
+            m = Matrix4::new_translation(v) * m;
+        }
+        if let Some(LookAtDesc { from, at, up }) = &self.look_at {
+            let up = up.unwrap_or_else(Vector::y);
+            if (at - from).cross(&up).norm() == 0.0 {
+                return Err("look_at needs distinct points and an up \
+                            direction across them"
+                    .into());
+            }
+            m = Isometry3::face_towards(from, at, &up).to_homogeneous() * m;
+        }
+        if let Some(rows) = &self.matrix {
+            if rows[3] != [0.0, 0.0, 0.0, 1.0] {
+                return Err("matrix must have a last row of 0, 0, 0, 1".into());
+            }
+            m = Matrix4::from_fn(|r, c| rows[r][c]) * m;
+        }
+        if m.determinant().abs() < 1e-12 {
+            return Err("object transform is singular".into());
+        }
+        Ok(m)
+    }
+}
+
+/// Objects which are built once and shared by each `Instance` of them.
+/// Groups cannot contain emitters, since instances are not sampled as
+/// lights.
+#[derive(Debug, Deserialize)]
+struct GroupDesc {
+    objects: Vec<ObjectDesc>,
+}
+
+/// Placement of an animated object at `time`, rotated by `rotate` degrees
+/// about each axis and then translated.
+#[derive(Debug, Deserialize)]
+struct KeyframeDesc {
+    time: f64,
+    rotate: Option<Vector>,
+    translate: Option<Vector>,
+}
+
+/// Color and strength of a delta light. `intensity` is in W/sr for point and
+/// spot lights, which may instead give their total `power` in watts, and is
+/// the irradiance in W/m^2 for directional lights.
+#[derive(Debug, Deserialize)]
+struct LightEmissionDesc {
+    color: Option<Color>,
+    temperature: Option<f64>,
+    intensity: Option<f64>,
+    power: Option<f64>,
+}
+
+impl LightEmissionDesc {
+    /// Computes the intensity, given the solid angle over which a light
+    /// spreads its power if that is allowed.
+    fn intensity(&self, solid_angle: Option<f64>) -> Result<Color, String> {
+        let color = self.color.unwrap_or_else(|| Color::repeat(1.0));
+        let color = match self.temperature {
+            Some(kelvin) => color.component_mul(&blackbody_rgb(kelvin)),
+            None => color,
+        };
+        match (self.intensity, self.power, solid_angle) {
+            (Some(_), Some(_), _) => {
+                Err("light sets both intensity and power".to_string())
+            }
+            (None, Some(_), None) => {
+                Err("directional lights cannot set power".to_string())
+            }
+            (None, Some(power), Some(solid_angle)) => {
+                Ok(color * power / solid_angle)
+            }
+            (intensity, None, _) => Ok(color * intensity.unwrap_or(1.0)),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "kind")]
+enum LightDesc {
+    Point {
+        position: Point,
+        #[serde(flatten)]
+        emission: LightEmissionDesc,
+    },
+    /// Spot light with full intensity within `inner` degrees of its axis,
+    /// falling off to nothing at `outer` degrees.
+    Spot {
+        position: Point,
+        direction: Vector,
+        inner: Option<f64>,
+        outer: f64,
+        #[serde(flatten)]
+        emission: LightEmissionDesc,
+    },
+    /// Light from an infinitely distant source, travelling along `direction`.
+    Directional {
+        direction: Vector,
+        #[serde(flatten)]
+        emission: LightEmissionDesc,
+    },
+}
+
+impl LightDesc {
+    fn build(&self) -> Result<Light, String> {
+        Ok(match self {
+            LightDesc::Point { position, emission } => {
+                Light::point(*position, emission.intensity(Some(4.0 * PI))?)
+            }
+            LightDesc::Spot {
+                position,
+                direction,
+                inner,
+                outer,
+                emission,
+            } => {
+                let inner = inner.unwrap_or(*outer);
+                if !(*outer > 0.0 && *outer <= 180.0) {
+                    return Err(format!(
+                        "spot light outer angle {} is not in (0, 180]",
+                        outer
+                    ));
+                }
+                if !(inner >= 0.0 && inner <= *outer) {
+                    return Err(format!(
+                        "spot light inner angle {} is not in [0, {}]",
+                        inner, outer
+                    ));
+                }
+                let solid_angle = spot_solid_angle(
+                    inner.to_radians().cos(),
+                    outer.to_radians().cos(),
+                );
+                let intensity = emission.intensity(Some(solid_angle))?;
+                Light::spot(*position, *direction, intensity, inner, *outer)
+            }
+            LightDesc::Directional {
+                direction,
+                emission,
+            } => Light::directional(*direction, emission.intensity(None)?),
+        })
+    }
+}
+
+/// Lighting from outside the scene, replacing the background color.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "kind")]
+enum EnvironmentDesc {
+    /// Equirectangular `.hdr` or `.exr` image, with its poles along the y
+    /// axis before rotating by `rotate` degrees about each axis.
+    Map {
+        path: PathBuf,
+        rotate: Option<Vector>,
+        intensity: Option<f64>,
+    },
+    /// Analytic daylight sky, with the sun `elevation` degrees above the
+    /// horizon and `azimuth` degrees from the z axis towards the x axis.
+    Sky {
+        elevation: f64,
+        azimuth: f64,
+        turbidity: Option<f64>,
+        /// Angular radius of the sun in degrees.
+        sun_radius: Option<f64>,
+        /// Albedo of the ground below the horizon.
+        ground: Option<Color>,
+        /// Scale from sky luminance in 1000 cd/m^2 to radiance.
+        intensity: Option<f64>,
+    },
 }
 
 #[derive(Debug, Deserialize)]
 struct SceneDesc {
-    background: Color,
+    background: Option<Color>,
+    environment: Option<EnvironmentDesc>,
     camera: CameraDesc,
     textures: HashMap<String, TextureDesc>,
     materials: HashMap<String, MaterialDesc>,
     objects: Vec<ObjectDesc>,
+    lights: Option<Vec<LightDesc>>,
+    /// Medium filling the bounds of the scene and the camera.
+    fog: Option<MediumDesc>,
+    animation: Option<AnimationDesc>,
+    groups: Option<HashMap<String, GroupDesc>>,
 }
 
-fn transform(desc: &SceneDesc) -> Option<Scene> {
-    let mut textures: HashMap<&str, Arc<Texture>> = HashMap::new();
-    let mut materials: HashMap<&str, Arc<Material>> = HashMap::new();
-    let mut result: Vec<Arc<dyn Hittable>> = vec![];
+/// Builds textures on demand so that they may reference each other by name
+/// regardless of the order in which they are declared.
+struct TextureResolver<'a> {
+    descs: &'a HashMap<String, TextureDesc>,
+    dir: &'a Path,
+    textures: HashMap<&'a str, Arc<Texture>>,
+    /// Names of the textures currently being built, innermost last.
+    stack: Vec<&'a str>,
+    // Images are shared between all textures which reference the same file.
+    images: HashMap<PathBuf, DynamicImage>,
+    mipmaps: HashMap<(PathBuf, ColorSpace), Arc<MipMap>>,
+}
 
-    for (name, tex) in &desc.textures {
-        let texture = match tex {
+impl<'a> TextureResolver<'a> {
+    fn new(
+        descs: &'a HashMap<String, TextureDesc>,
+        dir: &'a Path,
+    ) -> TextureResolver<'a> {
+        TextureResolver {
+            descs,
+            dir,
+            textures: HashMap::new(),
+            stack: vec![],
+            images: HashMap::new(),
+            mipmaps: HashMap::new(),
+        }
+    }
+
+    fn get(&mut self, name: &'a str) -> Result<Arc<Texture>, String> {
+        if let Some(texture) = self.textures.get(name) {
+            return Ok(texture.clone());
+        }
+        if let Some(pos) = self.stack.iter().position(|&n| n == name) {
+            let mut cycle = self.stack[pos..].to_vec();
+            cycle.push(name);
+            return Err(format!("texture cycle: {}", cycle.join(" -> ")));
+        }
+        let desc = match self.descs.get(name) {
+            Some(desc) => desc,
+            None => {
+                return Err(match self.stack.last() {
+                    Some(parent) => format!(
+                        "unknown texture `{}` referenced by texture `{}`",
+                        name, parent
+                    ),
+                    None => format!("unknown texture `{}`", name),
+                })
+            }
+        };
+        self.stack.push(name);
+        let texture = self.build(desc);
+        self.stack.pop();
+        let texture = Arc::new(texture?);
+        self.textures.insert(name, texture.clone());
+        Ok(texture)
+    }
+
+    fn build(&mut self, desc: &'a TextureDesc) -> Result<Texture, String> {
+        Ok(match desc {
             &TextureDesc::Solid { color } => Texture::Solid(color),
             TextureDesc::Checker { texture1, texture2 } => {
-                let t1 = textures.get(texture1.as_str())?.clone();
-                let t2 = textures.get(texture2.as_str())?.clone();
+                let t1 = self.get(texture1)?;
+                let t2 = self.get(texture2)?;
                 Texture::Checker(t1, t2)
             }
-        };
-        textures.insert(name, Arc::new(texture));
+            TextureDesc::Image {
+                path,
+                scale,
+                offset,
+                rotation,
+                wrap,
+                color_space,
+                filter,
+            } => {
+                let default = ImageMapping::default();
+                let mapping = ImageMapping {
+                    scale: scale.unwrap_or(default.scale),
+                    offset: offset.unwrap_or(default.offset),
+                    rotation: rotation.unwrap_or(default.rotation),
+                    wrap: wrap.unwrap_or(default.wrap),
+                    color_space: color_space.unwrap_or(default.color_space),
+                    filter: filter.unwrap_or(default.filter),
+                };
+                let path = self.dir.join(path);
+                let key = (path.clone(), mapping.color_space);
+                let mipmap = match self.mipmaps.get(&key) {
+                    Some(mipmap) => mipmap.clone(),
+                    None => {
+                        if !self.images.contains_key(&path) {
+                            let img = image::open(&path).map_err(|err| {
+                                format!("{}: {}", path.display(), err)
+                            })?;
+                            self.images.insert(path.clone(), img);
+                        }
+                        let mipmap = Arc::new(MipMap::new(
+                            &self.images[&path],
+                            mapping.color_space,
+                        ));
+                        self.mipmaps.insert(key, mipmap.clone());
+                        mipmap
+                    }
+                };
+                Texture::Image(mipmap, mapping)
+            }
+            TextureDesc::Noise {
+                low,
+                high,
+                basis,
+                scale,
+            } => {
+                let pattern = Pattern::Noise(
+                    basis.unwrap_or(NoiseBasis::Perlin),
+                    scale.unwrap_or(1.0),
+                );
+                let low = self.get(low)?;
+                let high = self.get(high)?;
+                Texture::Pattern(pattern, low, high)
+            }
+            TextureDesc::FBm {
+                low,
+                high,
+                scale,
+                fractal,
+            } => {
+                let pattern =
+                    Pattern::FBm(fractal.build(), scale.unwrap_or(1.0));
+                let low = self.get(low)?;
+                let high = self.get(high)?;
+                Texture::Pattern(pattern, low, high)
+            }
+            TextureDesc::Turbulence {
+                low,
+                high,
+                scale,
+                fractal,
+            } => {
+                let pattern =
+                    Pattern::Turbulence(fractal.build(), scale.unwrap_or(1.0));
+                let low = self.get(low)?;
+                let high = self.get(high)?;
+                Texture::Pattern(pattern, low, high)
+            }
+            TextureDesc::Marble {
+                low,
+                high,
+                scale,
+                strength,
+                fractal,
+            } => {
+                let pattern = Pattern::Marble {
+                    fractal: fractal.build(),
+                    scale: scale.unwrap_or(1.0),
+                    strength: strength.unwrap_or(5.0),
+                };
+                let low = self.get(low)?;
+                let high = self.get(high)?;
+                Texture::Pattern(pattern, low, high)
+            }
+            TextureDesc::Wood {
+                low,
+                high,
+                scale,
+                rings,
+                strength,
+                fractal,
+            } => {
+                let pattern = Pattern::Wood {
+                    fractal: fractal.build(),
+                    scale: scale.unwrap_or(1.0),
+                    rings: rings.unwrap_or(8.0),
+                    strength: strength.unwrap_or(0.1),
+                };
+                let low = self.get(low)?;
+                let high = self.get(high)?;
+                Texture::Pattern(pattern, low, high)
+            }
+            TextureDesc::Voronoi {
+                low,
+                high,
+                feature,
+                scale,
+            } => {
+                let pattern = Pattern::Voronoi(
+                    feature.unwrap_or(VoronoiFeature::F1),
+                    scale.unwrap_or(1.0),
+                );
+                let low = self.get(low)?;
+                let high = self.get(high)?;
+                Texture::Pattern(pattern, low, high)
+            }
+            &TextureDesc::Gradient {
+                ref low,
+                ref high,
+                start,
+                end,
+            } => {
+                let pattern = Pattern::Gradient(start, end);
+                let low = self.get(low)?;
+                let high = self.get(high)?;
+                Texture::Pattern(pattern, low, high)
+            }
+            TextureDesc::Mix {
+                texture1,
+                texture2,
+                factor,
+            } => Texture::Mix(
+                self.get(texture1)?,
+                self.get(texture2)?,
+                self.get(factor)?,
+            ),
+            TextureDesc::Multiply { texture1, texture2 } => {
+                Texture::Multiply(self.get(texture1)?, self.get(texture2)?)
+            }
+            TextureDesc::Add { texture1, texture2 } => {
+                Texture::Add(self.get(texture1)?, self.get(texture2)?)
+            }
+            TextureDesc::Invert { input } => Texture::Invert(self.get(input)?),
+            TextureDesc::ColorRamp { input, stops } => {
+                if stops.is_empty() {
+                    return Err("color ramp must have at least one stop".into());
+                }
+                if stops.iter().any(|s| !s.position.is_finite()) {
+                    return Err(
+                        "color ramp stop positions must be finite".into()
+                    );
+                }
+                let mut stops: Vec<(f64, Color)> =
+                    stops.iter().map(|s| (s.position, s.color)).collect();
+                stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+                Texture::ColorRamp(self.get(input)?, stops)
+            }
+            TextureDesc::HSV {
+                input,
+                hue,
+                saturation,
+                value,
+            } => Texture::HSV(
+                self.get(input)?,
+                hue.unwrap_or(0.0),
+                saturation.unwrap_or(1.0),
+                value.unwrap_or(1.0),
+            ),
+            TextureDesc::UV => Texture::UV,
+            TextureDesc::Position => Texture::Position,
+            TextureDesc::Normal => Texture::Normal,
+            TextureDesc::Triplanar {
+                input,
+                scale,
+                sharpness,
+            } => Texture::Triplanar(
+                self.get(input)?,
+                scale.unwrap_or(1.0),
+                sharpness.unwrap_or(4.0),
+            ),
+        })
     }
+}
 
-    for (name, mat) in &desc.materials {
-        let material = match mat {
-            MaterialDesc::Lambertian { texture } => {
-                let t = textures.get(texture.as_str())?.clone();
-                Material::Lambertian(t)
+fn build_background(
+    desc: &SceneDesc,
+    dir: &Path,
+) -> Result<Background, String> {
+    Ok(match (&desc.background, &desc.environment) {
+        (Some(_), Some(_)) => {
+            return Err("scene sets both background and environment".into())
+        }
+        (background, None) => {
+            Background::Color(background.unwrap_or_else(Color::zeros))
+        }
+        (
+            None,
+            Some(EnvironmentDesc::Map {
+                path,
+                rotate,
+                intensity,
+            }),
+        ) => {
+            let image = HdrImage::load(&dir.join(path))?;
+            let rotation = Rotation3::new(
+                rotate.unwrap_or_else(Vector::zeros).map(f64::to_radians),
+            );
+            Background::Map(EnvMap::new(
+                image,
+                rotation,
+                intensity.unwrap_or(1.0),
+            ))
+        }
+        (
+            None,
+            Some(EnvironmentDesc::Sky {
+                elevation,
+                azimuth,
+                turbidity,
+                sun_radius,
+                ground,
+                intensity,
+            }),
+        ) => Background::Sky(Box::new(Sky::new(
+            *elevation,
+            *azimuth,
+            turbidity.unwrap_or(3.0),
+            sun_radius.unwrap_or(0.27),
+            ground.unwrap_or_else(|| Color::repeat(0.3)),
+            intensity.unwrap_or(0.04),
+        ))),
+    })
+}
+
+/// Parts of a scene kept between the frames of an animation, so that only
+/// those which change are built again.
+#[derive(Default)]
+struct Built {
+    textures: HashMap<String, Arc<Texture>>,
+    materials: HashMap<String, Arc<Material>>,
+    /// Objects in the order they are declared, or `None` where they need to
+    /// be built.
+    objects: Vec<Option<Arc<dyn Hittable>>>,
+    groups: HashMap<String, Arc<dyn Hittable>>,
+    background: Option<Arc<Background>>,
+}
+
+/// Builds objects from their descriptions, along with the groups which they
+/// instance, each of which is built once and shared.
+struct ObjectBuilder<'a> {
+    desc: &'a SceneDesc,
+    dir: &'a Path,
+    materials: &'a HashMap<String, Arc<Material>>,
+    groups: &'a mut HashMap<String, Arc<dyn Hittable>>,
+    /// Names of the groups currently being built, innermost last.
+    stack: Vec<&'a str>,
+}
+
+impl<'a> ObjectBuilder<'a> {
+    /// Finds a material by name. Emitters specified by power have their
+    /// radiance scaled to match the area of each object they are applied to
+    /// and the average color of their texture, and goniometric profiles are
+    /// turned along with each object.
+    fn material(
+        &self,
+        name: &str,
+        obj: &ObjectDesc,
+    ) -> Result<Arc<Material>, String> {
+        let material = match self.materials.get(name) {
+            Some(m) => m,
+            None => return Err(format!("unknown material `{}`", name)),
+        };
+        let (t, emitter) = match material.as_ref() {
+            Material::Emission(t, emitter) => (t, emitter),
+            _ => return Ok(material.clone()),
+        };
+        if let Some(group) = self.stack.last() {
+            return Err(format!(
+                "group `{}` cannot contain emitters, as instances are not \
+                 sampled as lights",
+                group
+            ));
+        }
+        let power = match self.desc.materials[name] {
+            MaterialDesc::Emission { power, .. } => power,
+            _ => None,
+        };
+        let turned = emitter.profile.is_some() && obj.is_transformed();
+        if power.is_none() && !turned {
+            return Ok(material.clone());
+        }
+        let mut emitter = emitter.clone();
+        if turned {
+            emitter.transform(&obj.to_scene()?);
+        }
+        if let Some(power) = power {
+            let area = obj.geometry.area() * obj.area_scale()?;
+            if area <= 0.0 {
+                return Err("power needs a surface with a known area".into());
             }
-            MaterialDesc::Phong {
-                kd,
-                diffuse,
-                specular,
-                shininess,
-            } => {
-                let td = textures.get(diffuse.as_str())?.clone();
-                let ts = textures.get(specular.as_str())?.clone();
-                Material::Phong(*kd, td, ts, *shininess)
+            let texture = luminance(&t.average());
+            if texture <= 0.0 {
+                return Err(format!(
+                    "material `{}` sets power with a black texture",
+                    name
+                ));
             }
-            MaterialDesc::Metal { color, roughness } => {
-                Material::Metal(*color, *roughness)
+            let flux = PI * area * texture * emitter.relative_flux();
+            emitter.scale *= power / flux;
+        }
+        Ok(Arc::new(Material::Emission(t.clone(), emitter)))
+    }
+
+    /// Material replacing those of an instanced group, which must be a
+    /// surface that does not emit light, as instances are neither filled
+    /// with media nor sampled as lights.
+    fn instance_material(
+        &self,
+        name: &str,
+    ) -> Result<Option<Arc<Material>>, String> {
+        let material = match self.materials.get(name) {
+            Some(m) => m,
+            None => return Err(format!("unknown material `{}`", name)),
+        };
+        match material.as_ref() {
+            Material::Medium(..) | Material::Subsurface(..) => {
+                Err(format!("instance material `{}` is not a surface", name))
             }
-            MaterialDesc::Dielectric { ior } => Material::Dielectric(*ior),
-            MaterialDesc::Emission { texture } => {
-                let t = textures.get(texture.as_str())?.clone();
-                Material::Emission(t)
+            Material::Emission(..) => {
+                Err(format!("instance material `{}` cannot emit light", name))
             }
+            _ => Ok(Some(material.clone())),
+        }
+    }
+
+    fn group(&mut self, name: &'a str) -> Result<Arc<dyn Hittable>, String> {
+        if let Some(group) = self.groups.get(name) {
+            return Ok(group.clone());
+        }
+        if let Some(pos) = self.stack.iter().position(|&n| n == name) {
+            let mut cycle = self.stack[pos..].to_vec();
+            cycle.push(name);
+            return Err(format!("group cycle: {}", cycle.join(" -> ")));
+        }
+        let desc = match self.desc.groups.as_ref().and_then(|g| g.get(name)) {
+            Some(desc) => desc,
+            None => return Err(format!("unknown group `{}`", name)),
         };
-        materials.insert(name, Arc::new(material));
+        if desc.objects.is_empty() {
+            return Err(format!("group `{}` is empty", name));
+        }
+        self.stack.push(name);
+        let mut objects = vec![];
+        for obj in &desc.objects {
+            objects.push(self.build(obj)?);
+        }
+        self.stack.pop();
+        let group: Arc<dyn Hittable> = BVHNode::from_hittables(objects);
+        self.groups.insert(name.to_string(), group.clone());
+        Ok(group)
+    }
+
+    /// Combines `objects` in turn, from the first to the last.
+    fn csg(
+        &mut self,
+        op: CsgOp,
+        objects: &'a [ObjectDesc],
+    ) -> Result<Arc<dyn Hittable>, String> {
+        if objects.len() < 2 {
+            return Err(format!("{:?} needs at least two objects", op));
+        }
+        let mut result: Option<Arc<dyn Hittable>> = None;
+        for obj in objects {
+            let material = obj
+                .geometry
+                .material()
+                .and_then(|name| self.materials.get(name));
+            let medium = matches!(
+                material.map(|m| m.as_ref()),
+                Some(Material::Medium(..)) | Some(Material::Subsurface(..))
+            );
+            if medium || matches!(obj.geometry, GeomDesc::Volume { .. }) {
+                return Err(format!("{:?} objects must be surfaces", op));
+            }
+            let object = self.build(obj)?;
+            result = Some(match result {
+                Some(left) => Csg::new(op, left, object),
+                None => object,
+            });
+        }
+        Ok(result.unwrap())
     }
 
-    for obj in &desc.objects {
+    fn build(
+        &mut self,
+        obj: &'a ObjectDesc,
+    ) -> Result<Arc<dyn Hittable>, String> {
+        let affine = obj.affine()?;
         let mut hittable: Arc<dyn Hittable> = match &obj.geometry {
             GeomDesc::Sphere {
                 material,
                 center,
                 radius,
+                end_center,
             } => {
-                let m = materials.get(material.as_str())?.clone();
-                Sphere::new(m, *center, *radius)
+                let m = self.material(material, obj)?;
+                let end = end_center.unwrap_or(*center);
+                Sphere::moving(m, *center, end, *radius)
             }
             GeomDesc::Rect {
                 material,
@@ -146,7 +1441,7 @@ fn transform(desc: &SceneDesc) -> Option<Scene> {
                 end,
                 k,
             } => {
-                let m = materials.get(material.as_str())?.clone();
+                let m = self.material(material, obj)?;
                 Rect::new(m, *axis, *start, *end, *k)
             }
             GeomDesc::Block {
@@ -154,44 +1449,770 @@ fn transform(desc: &SceneDesc) -> Option<Scene> {
                 start,
                 end,
             } => {
-                let m = materials.get(material.as_str())?.clone();
+                let m = self.material(material, obj)?;
                 Block::new(m, *start, *end)
             }
+            GeomDesc::Disk {
+                material,
+                center,
+                normal,
+                radius,
+                inner_radius,
+            } => {
+                let inner = inner_radius.unwrap_or(0.0);
+                if inner < 0.0 || inner >= *radius {
+                    return Err(format!(
+                        "disk inner radius {} is not in [0, {})",
+                        inner, radius
+                    ));
+                }
+                let normal = Unit::try_new(*normal, 1e-12)
+                    .ok_or("disk normal is zero")?;
+                let m = self.material(material, obj)?;
+                Disk::new(m, *center, normal, *radius, inner)
+            }
+            GeomDesc::Cylinder {
+                material,
+                start,
+                end,
+                radius,
+                capped,
+            } => {
+                if start == end || *radius <= 0.0 {
+                    return Err("cylinder has no volume".into());
+                }
+                let m = self.material(material, obj)?;
+                let capped = capped.unwrap_or(true);
+                Cone::new(m, *start, *end, *radius, *radius, capped)
+            }
+            GeomDesc::Cone {
+                material,
+                start,
+                end,
+                radius,
+                end_radius,
+                capped,
+            } => {
+                let end_radius = end_radius.unwrap_or(0.0);
+                if start == end
+                    || *radius < 0.0
+                    || end_radius < 0.0
+                    || *radius + end_radius <= 0.0
+                {
+                    return Err("cone has no volume".into());
+                }
+                let m = self.material(material, obj)?;
+                let capped = capped.unwrap_or(true);
+                Cone::new(m, *start, *end, *radius, end_radius, capped)
+            }
+            GeomDesc::Torus {
+                material,
+                center,
+                axis,
+                major_radius,
+                minor_radius,
+            } => {
+                if *minor_radius <= 0.0 || minor_radius >= major_radius {
+                    return Err(format!(
+                        "torus minor radius {} is not in (0, {})",
+                        minor_radius, major_radius
+                    ));
+                }
+                let axis =
+                    Unit::try_new(*axis, 1e-12).ok_or("torus axis is zero")?;
+                let m = self.material(material, obj)?;
+                Torus::new(m, *center, axis, *major_radius, *minor_radius)
+            }
+            GeomDesc::Quad {
+                material,
+                corner,
+                edge1,
+                edge2,
+            } => {
+                if edge1.cross(edge2).norm() == 0.0 {
+                    return Err("quad edges are parallel".into());
+                }
+                let m = self.material(material, obj)?;
+                Quad::new(m, *corner, *edge1, *edge2)
+            }
+            GeomDesc::Volume {
+                material,
+                start,
+                end,
+                density,
+                temperature,
+                resolution,
+                temperature_scale,
+                emission,
+            } => {
+                if obj.is_transformed() || obj.keyframes.is_some() {
+                    return Err("volumes cannot be transformed".into());
+                }
+                let medium = match self.material(material, obj)?.as_ref() {
+                    Material::Medium(medium) => *medium,
+                    _ => {
+                        return Err(format!(
+                            "volume material `{}` is not a medium",
+                            material
+                        ))
+                    }
+                };
+                let density =
+                    VoxelGrid::load(&self.dir.join(density), *resolution)?;
+                let temperature = match temperature {
+                    Some(path) => {
+                        let mut grid =
+                            VoxelGrid::load(&self.dir.join(path), *resolution)?;
+                        grid.scale(temperature_scale.unwrap_or(1.0));
+                        Some(grid)
+                    }
+                    None => None,
+                };
+                let bounds = AABB::new(start.inf(end), start.sup(end));
+                let volume = Volume::new(
+                    bounds,
+                    density,
+                    temperature,
+                    emission.unwrap_or(1.0),
+                    medium,
+                );
+                return Ok(GridMedium::new(volume));
+            }
+            GeomDesc::Heightfield {
+                material,
+                image,
+                start,
+                end,
+            } => {
+                let m = self.material(material, obj)?;
+                Heightfield::load(m, &self.dir.join(image), *start, *end)?
+            }
+            GeomDesc::Sdf {
+                material,
+                shape,
+                bounds,
+            } => {
+                let sdf = shape.build()?;
+                let aabb = match bounds {
+                    Some((a, b)) => AABB::new(a.inf(b), a.sup(b)),
+                    None => sdf.bounds().ok_or(
+                        "SDF fills space, and needs to be given bounds",
+                    )?,
+                };
+                let m = self.material(material, obj)?;
+                SdfObject::new(m, sdf, aabb)
+            }
+            GeomDesc::Curve {
+                material,
+                points,
+                width,
+                end_width,
+                shape,
+                normals,
+            } => {
+                if points.len() < 4 || (points.len() - 1) % 3 != 0 {
+                    return Err(format!(
+                        "curve has {} control points, not 3n + 1",
+                        points.len()
+                    ));
+                }
+                let end_width = end_width.unwrap_or(*width);
+                if *width < 0.0 || end_width < 0.0 || *width + end_width <= 0.0
+                {
+                    return Err("curve has no width".into());
+                }
+                let shape = shape.unwrap_or(CurveType::Cylinder);
+                if (shape == CurveType::Ribbon) != normals.is_some() {
+                    return Err(
+                        "ribbon curves, and only they, need normals".into()
+                    );
+                }
+                let normals = match normals {
+                    Some((a, b)) => {
+                        let a = Unit::try_new(*a, 1e-12);
+                        let b = Unit::try_new(*b, 1e-12);
+                        match (a, b) {
+                            (Some(a), Some(b)) if a.dot(&b) > -1.0 + 1e-9 => {
+                                Some((a, b))
+                            }
+                            _ => {
+                                return Err(
+                                    "ribbon normals are zero or opposite"
+                                        .into(),
+                                )
+                            }
+                        }
+                    }
+                    None => None,
+                };
+                let m = self.material(material, obj)?;
+                let n = (points.len() - 1) / 3;
+                let mut segments: Vec<Arc<dyn Hittable>> = vec![];
+                for i in 0..n {
+                    let (u0, u1) =
+                        (i as f64 / n as f64, (i + 1) as f64 / n as f64);
+                    let shape = match (normals, shape) {
+                        (Some((a, b)), _) => {
+                            CurveShape::Ribbon(a.slerp(&b, u0), a.slerp(&b, u1))
+                        }
+                        (None, CurveType::Flat) => CurveShape::Flat,
+                        (None, _) => CurveShape::Cylinder,
+                    };
+                    let w = |u: f64| width + u * (end_width - width);
+                    let control = [
+                        points[3 * i],
+                        points[3 * i + 1],
+                        points[3 * i + 2],
+                        points[3 * i + 3],
+                    ];
+                    segments.push(Curve::new(
+                        m.clone(),
+                        control,
+                        (w(u0), w(u1)),
+                        shape,
+                    ));
+                }
+                BVHNode::from_hittables(segments)
+            }
+            GeomDesc::Curves {
+                material,
+                path,
+                shape,
+                width_scale,
+            } => {
+                let shape = match shape.unwrap_or(CurveType::Cylinder) {
+                    CurveType::Flat => CurveShape::Flat,
+                    CurveType::Cylinder => CurveShape::Cylinder,
+                    CurveType::Ribbon => {
+                        return Err("strands cannot be ribbons".into())
+                    }
+                };
+                let scale = width_scale.unwrap_or(1.0);
+                let m = self.material(material, obj)?;
+                let mut curves: Vec<Arc<dyn Hittable>> = vec![];
+                for strand in load_strands(&self.dir.join(path))? {
+                    let strand: Vec<_> = strand
+                        .into_iter()
+                        .map(|(p, w)| (p, w * scale))
+                        .collect();
+                    for curve in Curve::strand(&m, &strand, shape) {
+                        curves.push(curve);
+                    }
+                }
+                if curves.is_empty() {
+                    return Err(format!("{}: no strands", path.display()));
+                }
+                BVHNode::from_hittables(curves)
+            }
+            GeomDesc::Union { objects } => self.csg(CsgOp::Union, objects)?,
+            GeomDesc::Intersection { objects } => {
+                self.csg(CsgOp::Intersection, objects)?
+            }
+            GeomDesc::Difference { objects } => {
+                self.csg(CsgOp::Difference, objects)?
+            }
+            GeomDesc::Instance { group, material } => {
+                let group = self.group(group)?;
+                match material {
+                    Some(name) => {
+                        let material = self.instance_material(name)?;
+                        let instance =
+                            Instance::new(0, &Matrix4::identity(), material)?;
+                        InstanceBVH::new(vec![group], vec![instance])
+                    }
+                    None => group,
+                }
+            }
         };
 
-        if let Some(v) = &obj.rotate {
-            hittable =
-                Rotate::new(hittable, Rotation3::new(v.map(f64::to_radians)));
+        match affine {
+            Some(matrix) => hittable = Affine::new(hittable, matrix),
+            None => {
+                if let Some(v) = &obj.rotate {
+                    let rotation = Rotation3::new(v.map(f64::to_radians));
+                    hittable = Rotate::new(hittable, rotation);
+                }
+                if let Some(v) = &obj.translate {
+                    hittable = Translate::new(hittable, *v);
+                }
+            }
         }
 
-        if let Some(v) = &obj.translate {
-            hittable = Translate::new(hittable, *v);
+        if let Some(keyframes) = &obj.keyframes {
+            if obj.is_transformed() {
+                return Err(
+                    "animated objects cannot also be transformed".into()
+                );
+            }
+            if keyframes.is_empty() {
+                return Err("animated object has no keyframes".into());
+            }
+            if keyframes.iter().any(|k| !k.time.is_finite()) {
+                return Err(
+                    "animated object has a non-finite keyframe time".into()
+                );
+            }
+            let keyframes = keyframes
+                .iter()
+                .map(|k| Keyframe {
+                    time: k.time,
+                    translate: k.translate.unwrap_or_else(Vector::zeros),
+                    rotate: UnitQuaternion::from_scaled_axis(
+                        k.rotate
+                            .unwrap_or_else(Vector::zeros)
+                            .map(f64::to_radians),
+                    ),
+                })
+                .collect();
+            hittable = Animated::new(hittable, keyframes);
         }
 
+        if let Some(name) = obj.geometry.material() {
+            let material = self.material(name, obj)?;
+            match material.as_ref() {
+                Material::Medium(..) => {
+                    hittable = ConstantMedium::new(hittable, material)?;
+                }
+                Material::Subsurface(..) => {
+                    hittable = SubsurfaceObject::new(hittable, &material)?;
+                }
+                _ => {}
+            }
+        }
+        Ok(hittable)
+    }
+}
+
+fn transform(
+    desc: &SceneDesc,
+    dir: &Path,
+    built: &mut Built,
+) -> Result<Scene, String> {
+    let Built {
+        textures,
+        materials,
+        objects,
+        groups,
+        background,
+    } = built;
+    let mut result: Vec<Arc<dyn Hittable>> = vec![];
+
+    // Resolve every texture, even unused ones, so that errors are reported.
+    // Textures are not animated, so they are only built once.
+    if textures.is_empty() {
+        let mut resolver = TextureResolver::new(&desc.textures, dir);
+        for name in desc.textures.keys() {
+            textures.insert(name.clone(), resolver.get(name)?);
+        }
+    }
+    let get_texture = |name: &str| -> Result<Arc<Texture>, String> {
+        textures
+            .get(name)
+            .cloned()
+            .ok_or_else(|| format!("unknown texture `{}`", name))
+    };
+
+    for (name, mat) in &desc.materials {
+        if materials.contains_key(name) {
+            continue;
+        }
+        let material = match mat {
+            MaterialDesc::Lambertian { texture } => {
+                let t = get_texture(texture)?;
+                Material::Lambertian(t)
+            }
+            MaterialDesc::Phong {
+                kd,
+                diffuse,
+                specular,
+                shininess,
+            } => {
+                let td = get_texture(diffuse)?;
+                let ts = get_texture(specular)?;
+                Material::Phong(*kd, td, ts, *shininess)
+            }
+            MaterialDesc::Metal { color, roughness } => {
+                Material::Metal(*color, *roughness)
+            }
+            MaterialDesc::Dielectric { ior } => {
+                Material::Dielectric(match *ior {
+                    IORDesc::Constant(ior) => IOR::Constant(ior),
+                    IORDesc::Cauchy { cauchy: (a, b) } => IOR::Cauchy(a, b),
+                    IORDesc::Sellmeier {
+                        sellmeier_b,
+                        sellmeier_c,
+                    } => IOR::Sellmeier(sellmeier_b, sellmeier_c),
+                })
+            }
+            MaterialDesc::Emission {
+                texture,
+                temperature,
+                intensity,
+                power,
+                sides,
+                profile,
+                axis,
+            } => {
+                let t = match texture {
+                    Some(texture) => get_texture(texture)?,
+                    None => Arc::new(Texture::Solid(Color::repeat(1.0))),
+                };
+                if intensity.is_some() && power.is_some() {
+                    return Err(format!(
+                        "material `{}` sets both intensity and power",
+                        name
+                    ));
+                }
+                let mut emitter = Emitter::new(
+                    temperature.map_or(Color::repeat(1.0), blackbody_rgb)
+                        * intensity.unwrap_or(1.0),
+                );
+                emitter.sides = sides.unwrap_or(Sides::Both);
+                if let Some(path) = profile {
+                    let ies = IESProfile::load(&dir.join(path))?;
+                    let axis = axis.unwrap_or_else(|| -Vector::y());
+                    emitter.profile =
+                        Some((Arc::new(ies), Emitter::frame(axis)));
+                }
+                Material::Emission(t, emitter)
+            }
+            MaterialDesc::Medium(medium) => Material::Medium(medium.build()?),
+            MaterialDesc::Subsurface {
+                albedo,
+                mean_free_path,
+                ior,
+                g,
+            } => {
+                let g = g.unwrap_or(0.0);
+                if g <= -1.0 || g >= 1.0 {
+                    return Err(format!(
+                        "material `{}` has asymmetry {} not in (-1, 1)",
+                        name, g
+                    ));
+                }
+                if mean_free_path.min() <= 0.0 {
+                    return Err(format!(
+                        "material `{}` needs a positive mean free path",
+                        name
+                    ));
+                }
+                Material::Subsurface(Subsurface::new(
+                    albedo.unwrap_or_else(|| Color::repeat(0.8)),
+                    *mean_free_path,
+                    ior.unwrap_or(1.4),
+                    g,
+                ))
+            }
+            MaterialDesc::Hair {
+                eumelanin,
+                pheomelanin,
+                color,
+                absorption,
+                ior,
+                roughness,
+                azimuthal_roughness,
+                scale_angle,
+            } => {
+                let beta_m = roughness.unwrap_or(0.3);
+                let beta_n = azimuthal_roughness.unwrap_or(0.3);
+                if beta_m <= 0.0
+                    || beta_m > 1.0
+                    || beta_n <= 0.0
+                    || beta_n > 1.0
+                {
+                    return Err(format!(
+                        "material `{}` has roughness not in (0, 1]",
+                        name
+                    ));
+                }
+                let melanin = eumelanin.is_some() || pheomelanin.is_some();
+                let absorption = match (melanin, color, absorption) {
+                    (false, None, None) => Hair::melanin(1.3, 0.0),
+                    (true, None, None) => Hair::melanin(
+                        eumelanin.unwrap_or(0.0),
+                        pheomelanin.unwrap_or(0.0),
+                    ),
+                    (false, Some(color), None) => {
+                        Hair::absorption_for(*color, beta_n)
+                    }
+                    (false, None, Some(absorption)) => *absorption,
+                    _ => {
+                        return Err(format!(
+                            "material `{}` sets more than one of melanin, \
+                             color and absorption",
+                            name
+                        ))
+                    }
+                };
+                if absorption.min() < 0.0 {
+                    return Err(format!(
+                        "material `{}` has negative absorption",
+                        name
+                    ));
+                }
+                Material::Hair(Hair::new(
+                    absorption,
+                    ior.unwrap_or(1.55),
+                    beta_m,
+                    beta_n,
+                    scale_angle.unwrap_or(2.0),
+                ))
+            }
+        };
+        materials.insert(name.clone(), Arc::new(material));
+    }
+
+    let mut builder = ObjectBuilder {
+        desc,
+        dir,
+        materials,
+        groups,
+        stack: vec![],
+    };
+    if objects.len() != desc.objects.len() {
+        *objects = vec![None; desc.objects.len()];
+    }
+    // Instances of groups are gathered into a hierarchy of their own, which
+    // shares a hierarchy for each group between them.
+    let mut shared: Vec<Arc<dyn Hittable>> = vec![];
+    let mut shared_index: HashMap<&str, usize> = HashMap::new();
+    let mut instances = vec![];
+    for (obj, slot) in desc.objects.iter().zip(objects.iter_mut()) {
+        if let (GeomDesc::Instance { group, material }, None) =
+            (&obj.geometry, &obj.keyframes)
+        {
+            let index = match shared_index.get(group.as_str()) {
+                Some(&index) => index,
+                None => {
+                    shared.push(builder.group(group)?);
+                    shared_index.insert(group, shared.len() - 1);
+                    shared.len() - 1
+                }
+            };
+            let material = match material {
+                Some(name) => builder.instance_material(name)?,
+                None => None,
+            };
+            instances.push(Instance::new(index, &obj.to_scene()?, material)?);
+            continue;
+        }
+        let hittable = match slot {
+            Some(hittable) => hittable.clone(),
+            None => slot.get_or_insert(builder.build(obj)?).clone(),
+        };
         result.push(hittable);
     }
+    if !instances.is_empty() {
+        result.push(InstanceBVH::new(shared, instances));
+    }
+
+    let mut lights = vec![];
+    for light in desc.lights.iter().flatten() {
+        lights.push(light.build()?);
+    }
+
+    if background.is_none() {
+        *background = Some(Arc::new(build_background(desc, dir)?));
+    }
+    let background = background.clone().unwrap();
+
+    if let Some(fog) = &desc.fog {
+        let mut bounds = AABB::new(desc.camera.from, desc.camera.from);
+        for obj in &result {
+            bounds = AABB::containing(bounds, obj.bounding_box());
+        }
+        let material = Arc::new(Material::Medium(fog.build()?));
+        let boundary = Block::new(material.clone(), bounds.min, bounds.max);
+        result.push(ConstantMedium::new(boundary, material)?);
+    }
 
-    Scene::from_objects(desc.background, result)
+    Scene::new(background, result, lights)
+        .ok_or_else(|| "scene must contain at least one light".to_string())
 }
 
-pub(super) fn parse(config: &Config) -> (Scene, Camera) {
-    use std::fs::File;
-    use std::io::BufReader;
-    use std::io::Read;
-    let file = File::open(&config.scene).unwrap();
-    let mut reader = BufReader::new(file);
-    let mut string = String::new();
-    reader.read_to_string(&mut string).unwrap();
-    let desc: SceneDesc = toml::from_str(&string).unwrap();
+/// Loads a scene file and builds its scene and camera at each frame of its
+/// animation, keeping the parts which stay the same between frames.
+pub struct SceneLoader {
+    path: PathBuf,
+    dir: PathBuf,
+    aspect_ratio: f64,
+    /// Scene description as read, before the animation changes it.
+    raw: toml::Value,
+    animation: Animation,
+    /// Values of the tracks in the last frame built.
+    values: Vec<Option<Vec<f64>>>,
+    built: Built,
+    scene: Option<Scene>,
+    camera: Option<Camera>,
+    /// Shutter interval of the camera, relative to the time of each frame.
+    shutter: (f64, f64),
+}
 
-    let aspect_ratio: f64 = config.width as f64 / config.height as f64;
-    let from = desc.camera.from;
-    let at = desc.camera.at;
-    let up = desc.camera.up;
-    let dist = desc.camera.dist;
-    let vfov = desc.camera.vfov;
-    let aperture = desc.camera.aperture;
-    let camera = Camera::new(from, at, up, vfov, aspect_ratio, aperture, dist);
+impl SceneLoader {
+    pub fn new(config: &Config) -> SceneLoader {
+        let string = std::fs::read_to_string(&config.scene).unwrap();
+        let raw: toml::Value = toml::from_str(&string).unwrap();
+        let desc: SceneDesc = raw.clone().try_into().unwrap();
+        let animation =
+            Animation::new(desc.animation.as_ref()).unwrap_or_else(|err| {
+                panic!("Invalid scene {}: {}", config.scene.display(), err)
+            });
+        let dir = config.scene.parent().unwrap_or_else(|| Path::new(""));
+        SceneLoader {
+            path: config.scene.clone(),
+            dir: dir.to_path_buf(),
+            aspect_ratio: config.width as f64 / config.height as f64,
+            raw,
+            values: vec![None; animation.tracks.len()],
+            animation,
+            built: Built::default(),
+            scene: None,
+            camera: None,
+            shutter: (0.0, 0.0),
+        }
+    }
+
+    /// Builds the scene and camera as they are at `frame`.
+    pub fn frame(&mut self, frame: u32) -> (&Scene, &Camera) {
+        if let Err(err) = self.load(frame) {
+            panic!("Invalid scene {}: {}", self.path.display(), err)
+        }
+        (self.scene.as_ref().unwrap(), self.camera.as_ref().unwrap())
+    }
+
+    fn load(&mut self, frame: u32) -> Result<(), String> {
+        let time = frame as f64 / self.animation.fps;
+        let mut raw = self.raw.clone();
+        let mut changed = vec![];
+        for (track, last) in self.animation.tracks.iter().zip(&mut self.values)
+        {
+            let value = track.eval(time);
+            track.apply(&mut raw, &value)?;
+            if last.as_ref() != Some(&value) {
+                changed.push(&track.target);
+                *last = Some(value);
+            }
+        }
+        let desc: SceneDesc = raw.try_into().map_err(|e| format!("{}", e))?;
+
+        // Forget the parts of the scene which the animation has changed,
+        // along with the objects made of changed materials.
+        let mut camera_changed = self.camera.is_none();
+        let mut scene_changed = self.scene.is_none();
+        let objects = &mut self.built.objects;
+        let mut forget = |matches: &dyn Fn(&ObjectDesc) -> bool| {
+            for (obj, slot) in desc.objects.iter().zip(objects.iter_mut()) {
+                if matches(obj) {
+                    *slot = None;
+                }
+            }
+        };
+        for target in changed {
+            match target {
+                Target::Camera(..) => camera_changed = true,
+                Target::Object(name, _) => {
+                    forget(&|obj| obj.name.as_ref() == Some(name));
+                    scene_changed = true;
+                }
+                Target::Material(name, _) => {
+                    self.built.materials.remove(name);
+                    // Groups are rebuilt along with every instance of them.
+                    self.built.groups.clear();
+                    forget(&|obj| match obj.geometry.material() {
+                        Some(material) => material == name,
+                        None => true,
+                    });
+                    scene_changed = true;
+                }
+            }
+        }
+        // Fog fills a box around the camera as well as the objects.
+        scene_changed |= camera_changed && desc.fog.is_some();
+
+        if camera_changed {
+            let camera = desc.camera.build(self.aspect_ratio, &self.dir)?;
+            self.shutter = camera.shutter;
+            self.camera = Some(camera);
+        }
+        if let Some(camera) = &mut self.camera {
+            camera.shutter = (self.shutter.0 + time, self.shutter.1 + time);
+        }
+        if scene_changed {
+            self.scene = Some(transform(&desc, &self.dir, &mut self.built)?);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn texture_references_report_cycles_and_unknown_names() {
+    let descs: HashMap<String, TextureDesc> = toml::from_str(
+        r#"
+        [a]
+        kind = "Invert"
+        input = "b"
+        [b]
+        kind = "Multiply"
+        texture1 = "c"
+        texture2 = "a"
+        [c]
+        kind = "Solid"
+        color = [1, 1, 1]
+        [d]
+        kind = "Invert"
+        input = "missing"
+        "#,
+    )
+    .unwrap();
+    let mut textures = TextureResolver::new(&descs, Path::new(""));
+    assert!(textures.get("c").is_ok());
+    assert_eq!(
+        textures.get("a").err().unwrap(),
+        "texture cycle: a -> b -> a"
+    );
+    assert_eq!(
+        textures.get("d").err().unwrap(),
+        "unknown texture `missing` referenced by texture `d`"
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn light_power_sets_the_emitted_power() {
+    let descs: HashMap<String, LightDesc> = toml::from_str(
+        r#"
+        [point]
+        kind = "Point"
+        position = [0, 0, 0]
+        power = 100
+        [spot]
+        kind = "Spot"
+        position = [0, 0, 0]
+        direction = [0, -1, 0]
+        inner = 20
+        outer = 45
+        power = 100
+        "#,
+    )
+    .unwrap();
+    let bounds = AABB::new(Point::origin(), Point::origin());
+    for desc in descs.values() {
+        let power = desc.build().unwrap().power(&bounds);
+        assert!((power - 100.0).abs() < 1e-9, "{:?} emits {}", desc, power);
+    }
 
-    (transform(&desc).unwrap(), camera)
+    // Cones must be wider outside than inside.
+    let inverted: LightDesc = toml::from_str(
+        r#"
+        kind = "Spot"
+        position = [0, 0, 0]
+        direction = [0, -1, 0]
+        inner = 45
+        outer = 20
+        power = 100
+        "#,
+    )
+    .unwrap();
+    assert!(inverted.build().is_err());
 }
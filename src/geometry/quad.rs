@@ -0,0 +1,130 @@
+use super::rect::{SphericalRect, MIN_SOLID_ANGLE};
+use super::surface::{area_pdf, emit_from};
+use super::*;
+
+/// Parallelogram in any orientation, spanning `edge1` and `edge2` from
+/// `corner`, with UVs following the edges. Its front faces along the cross
+/// product of the edges.
+pub struct Quad {
+    material: Arc<Material>,
+    corner: Point,
+    edge1: Vector,
+    edge2: Vector,
+    normal: Unit<Vector>,
+    /// Normal scaled by the reciprocal of twice the area, which finds the
+    /// coordinates of points along the edges.
+    w: Vector,
+    area: f64,
+    /// Whether the edges meet at right angles, so that the quad can be
+    /// sampled by solid angle.
+    rectangular: bool,
+}
+
+impl Quad {
+    pub fn new(
+        material: Arc<Material>,
+        corner: Point,
+        edge1: Vector,
+        edge2: Vector,
+    ) -> Arc<Quad> {
+        let n = edge1.cross(&edge2);
+        let area = n.norm();
+        let cos = edge1.dot(&edge2) / (edge1.norm() * edge2.norm());
+        Arc::new(Quad {
+            material,
+            corner,
+            edge1,
+            edge2,
+            normal: Unit::new_normalize(n),
+            w: n / n.norm_squared(),
+            area,
+            rectangular: cos.abs() < 1e-9,
+        })
+    }
+
+    fn random_point(&self) -> (Point, (f64, f64)) {
+        let uv = (random(), random());
+        (self.corner + uv.0 * self.edge1 + uv.1 * self.edge2, uv)
+    }
+
+    /// The quad's projection onto the sphere around `origin`, if it is a
+    /// rectangle large enough to sample that way.
+    fn spherical(&self, origin: Point) -> Option<SphericalRect> {
+        if !self.rectangular {
+            return None;
+        }
+        let spherical =
+            SphericalRect::new(origin, self.corner, self.edge1, self.edge2);
+        if spherical.solid_angle > MIN_SOLID_ANGLE {
+            Some(spherical)
+        } else {
+            None
+        }
+    }
+}
+
+impl Hittable for Quad {
+    fn is_light(&self) -> bool {
+        matches!(self.material.as_ref(), Material::Emission(..))
+    }
+
+    fn bounding_box(&self) -> AABB {
+        let corners = [
+            self.corner + self.edge1,
+            self.corner + self.edge2,
+            self.corner + self.edge1 + self.edge2,
+        ];
+        let (min, max) = corners
+            .iter()
+            .fold((self.corner, self.corner), |(min, max), p| {
+                (min.inf(p), max.sup(p))
+            });
+        let pad = Vector::repeat(0.001);
+        AABB::new(min - pad, max + pad)
+    }
+
+    fn hit(&self, ray: Ray, range: Range<f64>) -> Option<Hit> {
+        let t = (self.corner - ray.origin).dot(&self.normal)
+            / ray.dir.dot(&self.normal);
+        if !range.contains(&t) {
+            return None;
+        }
+        let q = ray.at(t) - self.corner;
+        let u = self.w.dot(&q.cross(&self.edge2));
+        let v = self.w.dot(&self.edge1.cross(&q));
+        let inside = -1e-9..=1.0 + 1e-9;
+        if !inside.contains(&u) || !inside.contains(&v) {
+            return None;
+        }
+        Some(
+            Hit::new(ray, self.normal, t, &self.material, (u, v))
+                .with_partials(ray, self.edge1, self.edge2),
+        )
+    }
+
+    fn pdf(&self, ray: Ray) -> f64 {
+        match self.spherical(ray.origin) {
+            Some(spherical) => match self.hit(ray, 0.0001..f64::INFINITY) {
+                Some(_) => spherical.solid_angle.recip(),
+                None => 0.0,
+            },
+            None => area_pdf(self, ray, self.area),
+        }
+    }
+
+    fn random(&self, origin: Point, _time: f64) -> Vector {
+        match self.spherical(origin) {
+            Some(spherical) => spherical.sample((random(), random())) - origin,
+            None => self.random_point().0 - origin,
+        }
+    }
+
+    fn emit(&self, _time: f64) -> (Ray, Unit<Vector>, Color) {
+        let (point, uv) = self.random_point();
+        emit_from(&self.material, point, self.normal, uv)
+    }
+
+    fn power(&self) -> f64 {
+        self.material.emitted_power(self.area)
+    }
+}
@@ -4,10 +4,56 @@ use crate::geometry::*;
 pub struct Ray {
     pub origin: Point,
     pub dir: Vector,
+    pub diff: Option<RayDiff>,
+    /// Hero wavelength in nanometres of a path traced in spectral mode.
+    pub wavelength: Option<f64>,
+    /// Time at which the ray is traced, for motion blur.
+    pub time: f64,
+}
+
+/// Rays offset by one pixel in the x and y directions on the image plane,
+/// used to estimate the footprint of a camera ray on the surfaces it hits.
+#[derive(Debug, Copy, Clone)]
+pub struct RayDiff {
+    pub dx_origin: Point,
+    pub dx_dir: Vector,
+    pub dy_origin: Point,
+    pub dy_dir: Vector,
 }
 
 impl Ray {
+    pub fn new(origin: Point, dir: Vector) -> Ray {
+        Ray {
+            origin,
+            dir,
+            diff: None,
+            wavelength: None,
+            time: 0.0,
+        }
+    }
+
     pub fn at(&self, t: f64) -> Point {
         self.origin + t * self.dir
     }
+
+    /// Applies `f_point` and `f_vector` to the origins and directions of the
+    /// ray and its differentials.
+    pub fn map(
+        &self,
+        f_point: impl Fn(&Point) -> Point,
+        f_vector: impl Fn(&Vector) -> Vector,
+    ) -> Ray {
+        Ray {
+            origin: f_point(&self.origin),
+            dir: f_vector(&self.dir),
+            diff: self.diff.map(|d| RayDiff {
+                dx_origin: f_point(&d.dx_origin),
+                dx_dir: f_vector(&d.dx_dir),
+                dy_origin: f_point(&d.dy_origin),
+                dy_dir: f_vector(&d.dy_dir),
+            }),
+            wavelength: self.wavelength,
+            time: self.time,
+        }
+    }
 }
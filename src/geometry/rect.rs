@@ -1,6 +1,7 @@
 use super::*;
 use crate::material::Material;
 use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
 use std::ops::Range;
 use std::sync::Arc;
 
@@ -49,9 +50,12 @@ impl RectAxis {
     }
 }
 
+#[derive(Clone)]
 pub struct Rect {
     material: Arc<Material>,
     axis: RectAxis,
+    /// Geometric normal, along the positive axis unless flipped.
+    normal: Unit<Vector>,
     p1: Point,
     p2: Point,
     area: f64,
@@ -77,6 +81,7 @@ impl Rect {
         Arc::new(Rect {
             material,
             axis,
+            normal: axis.unit(),
             p1,
             p2,
             area,
@@ -84,6 +89,19 @@ impl Rect {
     }
 }
 
+impl Rect {
+    /// Turns the front of the rect to face along the negative axis.
+    pub(super) fn flipped(&self) -> Arc<Rect> {
+        let mut rect = self.clone();
+        rect.normal = -rect.normal;
+        Arc::new(rect)
+    }
+
+    pub fn area(&self) -> f64 {
+        self.area
+    }
+}
+
 impl Hittable for Rect {
     fn is_light(&self) -> bool {
         match self.material.as_ref() {
@@ -115,58 +133,216 @@ impl Hittable for Rect {
             return None;
         }
 
-        let uv = self
-            .axis
-            .uv((p - self.p1).component_div(&(self.p2 - self.p1)));
+        let size = self.p2 - self.p1;
+        let uv = self.axis.uv((p - self.p1).component_div(&size));
+        let (du, dv) = self.axis.uv(size);
+        let dpdu = self.axis.point((du, 0.0), 0.0).coords;
+        let dpdv = self.axis.point((0.0, dv), 0.0).coords;
 
-        Some(Hit::new(ray, self.axis.unit(), t, &self.material, uv))
+        Some(
+            Hit::new(ray, self.normal, t, &self.material, uv)
+                .with_partials(ray, dpdu, dpdv),
+        )
     }
 
     fn pdf(&self, ray: Ray) -> f64 {
-        match self.hit(ray, 0.0001..f64::INFINITY) {
-            None => 0.0,
-            Some(hit) => {
-                let norm_squared = hit.t * hit.t * ray.dir.norm_squared();
-                let cos = (ray.dir.dot(&hit.normal) / ray.dir.norm()).abs();
-                norm_squared / (cos * self.area)
-            }
+        let hit = match self.hit(ray, 0.0001..f64::INFINITY) {
+            None => return 0.0,
+            Some(hit) => hit,
+        };
+        let solid_angle = self.spherical(ray.origin).solid_angle;
+        if solid_angle > MIN_SOLID_ANGLE {
+            solid_angle.recip()
+        } else {
+            let norm_squared = hit.t * hit.t * ray.dir.norm_squared();
+            let cos = (ray.dir.dot(&hit.normal) / ray.dir.norm()).abs();
+            norm_squared / (cos * self.area)
         }
     }
 
-    fn random(&self, origin: Point) -> Vector {
-        const EPS: f64 = 0.0001;
-        let rand_point = Point::new(
-            random_range(self.p1.x - EPS..self.p2.x + EPS),
-            random_range(self.p1.y - EPS..self.p2.y + EPS),
-            random_range(self.p1.z - EPS..self.p2.z + EPS),
-        );
-        rand_point - origin
+    fn random(&self, origin: Point, _time: f64) -> Vector {
+        let spherical = self.spherical(origin);
+        if spherical.solid_angle > MIN_SOLID_ANGLE {
+            spherical.sample((random(), random())) - origin
+        } else {
+            self.random_point() - origin
+        }
     }
 
-    fn emit(&self) -> (Ray, Unit<Vector>, Color) {
-        const EPS: f64 = 0.0001;
-        let dir = -1.0 * *self.axis.unit() + random_unit_vector();
-        let rand_point = Point::new(
-            random_range(self.p1.x - EPS..self.p2.x + EPS),
-            random_range(self.p1.y - EPS..self.p2.y + EPS),
-            random_range(self.p1.z - EPS..self.p2.z + EPS),
-        );
-        let ray = Ray {
-            origin: rand_point,
-            // TODO: Properly use normals here.
-            dir,
-        };
-        let normal = Unit::new_unchecked(-1.0 * *self.axis.unit());
+    fn emit(&self, _time: f64) -> (Ray, Unit<Vector>, Color) {
+        let front = self.material.choose_emitting_side();
+        let normal = if front { self.normal } else { -self.normal };
+        let origin = self.random_point();
+        let dir = ONB::from_w(normal).localize(random_cosine_dir());
+        let ray = Ray::new(origin, dir);
+        // Radiance is evaluated as if seen from along the emitted ray.
+        let inbound = Ray::new(origin + dir, -dir);
+        let hit =
+            Hit::new(inbound, self.normal, 1.0, &self.material, (0.0, 0.0));
+        (ray, normal, self.material.emitted(&inbound, &hit))
+    }
+
+    fn power(&self) -> f64 {
+        self.material.emitted_power(self.area)
+    }
+}
+
+/// Below this solid angle, spherical rectangles are too small to sample
+/// accurately and rectangles are sampled by area instead.
+pub(super) const MIN_SOLID_ANGLE: f64 = 3e-4;
+
+impl Rect {
+    /// Edges from `p1` along the first and second rect axes.
+    fn edges(&self) -> (Vector, Vector) {
+        let (du, dv) = self.axis.uv(self.p2 - self.p1);
         (
-            ray,
-            normal,
-            self.material.emitted(&Hit::new(
-                ray,
-                Unit::new_normalize(dir),
-                0.0,
-                &self.material,
-                (0.0, 0.0),
-            )),
+            self.axis.point((du, 0.0), 0.0).coords,
+            self.axis.point((0.0, dv), 0.0).coords,
         )
     }
+
+    fn random_point(&self) -> Point {
+        let (eu, ev) = self.edges();
+        self.p1 + random() * eu + random() * ev
+    }
+
+    fn spherical(&self, origin: Point) -> SphericalRect {
+        let (eu, ev) = self.edges();
+        SphericalRect::new(origin, self.p1, eu, ev)
+    }
+}
+
+/// The projection of a rectangle onto the unit sphere around a point, for
+/// sampling directions uniformly within its solid angle following "An
+/// Area-Preserving Parametrization for Spherical Rectangles", Ureña, Fajardo
+/// and King, 2013.
+pub(super) struct SphericalRect {
+    origin: Point,
+    /// Local frame with `x` and `y` along the edges and `z` facing away from
+    /// the origin.
+    frame: (Vector, Vector, Vector),
+    /// Extent of the rectangle along each axis, relative to the origin.
+    x: (f64, f64),
+    y: (f64, f64),
+    z: f64,
+    b0: f64,
+    b1: f64,
+    k: f64,
+    pub solid_angle: f64,
+}
+
+impl SphericalRect {
+    pub fn new(origin: Point, corner: Point, ex: Vector, ey: Vector) -> Self {
+        let (x_axis, y_axis) = (ex.normalize(), ey.normalize());
+        let mut z_axis = x_axis.cross(&y_axis);
+        let d = corner - origin;
+        let mut z0 = d.dot(&z_axis);
+        if z0 > 0.0 {
+            z_axis = -z_axis;
+            z0 = -z0;
+        }
+        let x0 = d.dot(&x_axis);
+        let y0 = d.dot(&y_axis);
+        let (x1, y1) = (x0 + ex.norm(), y0 + ey.norm());
+
+        // Normals of the planes through the origin and each edge, and the
+        // interior angles between them.
+        let v00 = Vector::new(x0, y0, z0);
+        let v01 = Vector::new(x0, y1, z0);
+        let v10 = Vector::new(x1, y0, z0);
+        let v11 = Vector::new(x1, y1, z0);
+        let n0 = v00.cross(&v10).normalize();
+        let n1 = v10.cross(&v11).normalize();
+        let n2 = v11.cross(&v01).normalize();
+        let n3 = v01.cross(&v00).normalize();
+        let angle = |a: Vector, b: Vector| fclamp(-a.dot(&b), -1.0, 1.0).acos();
+        let (g0, g1) = (angle(n0, n1), angle(n1, n2));
+        let (g2, g3) = (angle(n2, n3), angle(n3, n0));
+        let k = 2.0 * PI - g2 - g3;
+        let solid_angle = g0 + g1 - k;
+
+        SphericalRect {
+            origin,
+            frame: (x_axis, y_axis, z_axis),
+            x: (x0, x1),
+            y: (y0, y1),
+            z: z0,
+            b0: n0.z,
+            b1: n2.z,
+            k,
+            // Points in the plane of the rectangle see none of it.
+            solid_angle: if solid_angle.is_finite() {
+                solid_angle.max(0.0)
+            } else {
+                0.0
+            },
+        }
+    }
+
+    /// Maps `(u, v)` in [0, 1)^2 to a point on the rectangle, such that the
+    /// directions towards the points are uniform in solid angle.
+    pub fn sample(&self, (u, v): (f64, f64)) -> Point {
+        let (x0, x1) = self.x;
+        let (y0, y1) = self.y;
+        let z0 = self.z;
+
+        // Find the x coordinate splitting off a fraction `u` of the solid
+        // angle.
+        let au = u * self.solid_angle + self.k;
+        let fu = (au.cos() * self.b0 - self.b1) / au.sin();
+        let cu = fclamp(
+            fu.signum() / (fu * fu + self.b0 * self.b0).sqrt(),
+            -1.0,
+            1.0,
+        );
+        let xu = fclamp(-(cu * z0) / (1.0 - cu * cu).sqrt(), x0, x1);
+
+        // Then the y coordinate along the line at `xu`.
+        let d = (xu * xu + z0 * z0).sqrt();
+        let h0 = y0 / (d * d + y0 * y0).sqrt();
+        let h1 = y1 / (d * d + y1 * y1).sqrt();
+        let hv = h0 + v * (h1 - h0);
+        let yv = if hv * hv < 1.0 - 1e-6 {
+            hv * d / (1.0 - hv * hv).sqrt()
+        } else {
+            y1
+        };
+
+        let (x_axis, y_axis, z_axis) = self.frame;
+        self.origin + xu * x_axis + yv * y_axis + z0 * z_axis
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn spherical_rect_samples_cover_its_solid_angle() {
+    let material = Arc::new(Material::Lambertian(Arc::new(
+        crate::texture::Texture::Solid(Color::zeros()),
+    )));
+    let rect = Rect::new(material, RectAxis::XZ, (-1.0, 0.0), (2.0, 1.0), 3.0);
+    let origin = Point::new(0.5, 1.0, -0.5);
+    let spherical = rect.spherical(origin);
+
+    // Integrate the solid angle over the area of the rect.
+    let n = 200;
+    let mut solid_angle = 0.0;
+    for i in 0..n {
+        for j in 0..n {
+            let (u, v) =
+                ((i as f64 + 0.5) / n as f64, (j as f64 + 0.5) / n as f64);
+            let p = Point::new(-1.0 + 3.0 * u, 3.0, v);
+            let d = p - origin;
+            solid_angle += d.y / d.norm().powi(3) * rect.area / (n * n) as f64;
+        }
+    }
+    assert!((spherical.solid_angle - solid_angle).abs() < 1e-4);
+
+    for &uv in &[(0.0, 0.0), (0.3, 0.9), (0.99, 0.5)] {
+        let p = spherical.sample(uv);
+        assert!((p.y - 3.0).abs() < 1e-9);
+        assert!(p.x >= -1.0 - 1e-9 && p.x <= 2.0 + 1e-9, "{}", p);
+        assert!(p.z >= -1e-9 && p.z <= 1.0 + 1e-9, "{}", p);
+        let pdf = rect.pdf(Ray::new(origin, p - origin));
+        assert!((pdf * solid_angle - 1.0).abs() < 1e-3);
+    }
 }
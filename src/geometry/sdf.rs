@@ -0,0 +1,535 @@
+use super::*;
+use nalgebra::{Rotation3, Similarity3, Translation3, UnitQuaternion};
+use serde::Deserialize;
+
+/// Shape given by a signed distance function, built from primitives centered
+/// on the origin and operators combining them. Every shape may be scaled by
+/// `scale`, rotated by `rotate` degrees about each axis and then translated by
+/// `translate`.
+#[derive(Debug, Deserialize)]
+pub(super) struct SdfDesc {
+    #[serde(flatten)]
+    shape: ShapeDesc,
+    scale: Option<f64>,
+    rotate: Option<Vector>,
+    translate: Option<Vector>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "kind")]
+enum ShapeDesc {
+    Sphere {
+        radius: f64,
+    },
+    /// Box of the given `size` along each axis.
+    Box {
+        size: Vector,
+    },
+    /// Torus around the y axis.
+    Torus {
+        major_radius: f64,
+        minor_radius: f64,
+    },
+    /// Cylinder of `height` along the y axis.
+    Cylinder {
+        radius: f64,
+        height: f64,
+    },
+    /// Cylinder with rounded ends around the line from `start` to `end`.
+    Capsule {
+        start: Point,
+        end: Point,
+        radius: f64,
+    },
+    /// Combines `shapes`, blending them together over distances of about
+    /// `smoothness`.
+    Union {
+        shapes: Vec<SdfDesc>,
+        smoothness: Option<f64>,
+    },
+    Intersection {
+        shapes: Vec<SdfDesc>,
+    },
+    /// The first of `shapes` with the others cut away.
+    Difference {
+        shapes: Vec<SdfDesc>,
+    },
+    /// Grows `shape` by `radius`, rounding off its edges.
+    Round {
+        radius: f64,
+        shape: Box<SdfDesc>,
+    },
+    /// Copies of `shape` every `period` along each axis, or only along those
+    /// with a nonzero period. Unless a `count` of copies to either side of
+    /// the original is given, they fill space, and the object needs bounds.
+    Repeat {
+        period: Vector,
+        count: Option<Vector>,
+        shape: Box<SdfDesc>,
+    },
+    /// Twists `shape` about the y axis by `rate` degrees per unit of height.
+    Twist {
+        rate: f64,
+        shape: Box<SdfDesc>,
+    },
+}
+
+impl SdfDesc {
+    pub fn build(&self) -> Result<Sdf, String> {
+        let all = |shapes: &[SdfDesc], op: &str| {
+            if shapes.is_empty() {
+                return Err(format!("SDF {} has no shapes", op));
+            }
+            shapes.iter().map(SdfDesc::build).collect()
+        };
+        let sdf = match &self.shape {
+            &ShapeDesc::Sphere { radius } => Sdf::Sphere(radius),
+            &ShapeDesc::Box { size } => Sdf::Box(size / 2.0),
+            &ShapeDesc::Torus {
+                major_radius,
+                minor_radius,
+            } => Sdf::Torus(major_radius, minor_radius),
+            &ShapeDesc::Cylinder { radius, height } => {
+                Sdf::Cylinder(radius, height / 2.0)
+            }
+            &ShapeDesc::Capsule { start, end, radius } => {
+                Sdf::Capsule(start, end, radius)
+            }
+            ShapeDesc::Union { shapes, smoothness } => {
+                let k = smoothness.unwrap_or(0.0);
+                if k < 0.0 {
+                    return Err(format!("SDF smoothness {} is negative", k));
+                }
+                Sdf::Union(all(shapes, "union")?, k)
+            }
+            ShapeDesc::Intersection { shapes } => {
+                Sdf::Intersection(all(shapes, "intersection")?)
+            }
+            ShapeDesc::Difference { shapes } => {
+                Sdf::Difference(all(shapes, "difference")?)
+            }
+            ShapeDesc::Round { radius, shape } => {
+                Sdf::Round(*radius, Box::new(shape.build()?))
+            }
+            ShapeDesc::Repeat {
+                period,
+                count,
+                shape,
+            } => {
+                if period.min() < 0.0
+                    || matches!(count, Some(c) if c.min() < 0.0)
+                {
+                    return Err("SDF repetition must not be negative".into());
+                }
+                Sdf::Repeat(*period, *count, Box::new(shape.build()?))
+            }
+            ShapeDesc::Twist { rate, shape } => {
+                let shape = shape.build()?;
+                let bounds = shape
+                    .bounds()
+                    .ok_or("twisted SDF shapes must be bounded")?;
+                // The farthest the shape reaches from the axis sets how far
+                // twisting can stretch distances.
+                let reach = (0..4)
+                    .map(|i| {
+                        let x = if i & 1 == 0 {
+                            bounds.min.x
+                        } else {
+                            bounds.max.x
+                        };
+                        let z = if i & 2 == 0 {
+                            bounds.min.z
+                        } else {
+                            bounds.max.z
+                        };
+                        x.hypot(z)
+                    })
+                    .fold(0.0, f64::max);
+                Sdf::Twist(rate.to_radians(), reach, Box::new(shape))
+            }
+        };
+
+        if self.scale.is_none()
+            && self.rotate.is_none()
+            && self.translate.is_none()
+        {
+            return Ok(sdf);
+        }
+        let scale = self.scale.unwrap_or(1.0);
+        if scale <= 0.0 {
+            return Err(format!("SDF scale {} is not positive", scale));
+        }
+        let rotation = Rotation3::new(
+            self.rotate
+                .unwrap_or_else(Vector::zeros)
+                .map(f64::to_radians),
+        );
+        let transform = Similarity3::from_parts(
+            Translation3::from(self.translate.unwrap_or_else(Vector::zeros)),
+            UnitQuaternion::from_rotation_matrix(&rotation),
+            scale,
+        );
+        Ok(Sdf::Transform(transform, Box::new(sdf)))
+    }
+}
+
+/// Signed distance function, negative inside the shape.
+pub enum Sdf {
+    Sphere(f64),
+    /// Box with the given half size.
+    Box(Vector),
+    /// Major and minor radii of a torus around the y axis.
+    Torus(f64, f64),
+    /// Radius and half height of a cylinder along the y axis.
+    Cylinder(f64, f64),
+    Capsule(Point, Point, f64),
+    /// Union of the shapes, blended over the given distance.
+    Union(Vec<Sdf>, f64),
+    Intersection(Vec<Sdf>),
+    Difference(Vec<Sdf>),
+    Round(f64, Box<Sdf>),
+    /// Period and optional count of copies along each axis.
+    Repeat(Vector, Option<Vector>, Box<Sdf>),
+    /// Twist in radians per unit of height, and the farthest the shape
+    /// reaches from the axis.
+    Twist(f64, f64, Box<Sdf>),
+    Transform(Similarity3<f64>, Box<Sdf>),
+}
+
+/// Polynomial smooth minimum of `a` and `b`, which is at most `k / 4` below
+/// their minimum.
+fn smooth_min(a: f64, b: f64, k: f64) -> f64 {
+    if k <= 0.0 {
+        return a.min(b);
+    }
+    let h = (k - (a - b).abs()).max(0.0) / k;
+    a.min(b) - h * h * k / 4.0
+}
+
+impl Sdf {
+    pub fn distance(&self, p: Point) -> f64 {
+        match self {
+            Sdf::Sphere(radius) => p.coords.norm() - radius,
+            Sdf::Box(half) => {
+                let q = p.coords.abs() - half;
+                q.sup(&Vector::zeros()).norm() + q.max().min(0.0)
+            }
+            Sdf::Torus(major, minor) => {
+                (p.x.hypot(p.z) - major).hypot(p.y) - minor
+            }
+            Sdf::Cylinder(radius, half_height) => {
+                let (x, y) = (p.x.hypot(p.z) - radius, p.y.abs() - half_height);
+                x.max(y).min(0.0) + x.max(0.0).hypot(y.max(0.0))
+            }
+            Sdf::Capsule(a, b, radius) => {
+                let (pa, ba) = (p - a, b - a);
+                let h = fclamp(pa.dot(&ba) / ba.norm_squared(), 0.0, 1.0);
+                (pa - h * ba).norm() - radius
+            }
+            Sdf::Union(shapes, k) => shapes
+                .iter()
+                .map(|s| s.distance(p))
+                .fold(f64::INFINITY, |a, b| smooth_min(a, b, *k)),
+            Sdf::Intersection(shapes) => shapes
+                .iter()
+                .map(|s| s.distance(p))
+                .fold(-f64::INFINITY, f64::max),
+            Sdf::Difference(shapes) => shapes[1..]
+                .iter()
+                .map(|s| -s.distance(p))
+                .fold(shapes[0].distance(p), f64::max),
+            Sdf::Round(radius, shape) => shape.distance(p) - radius,
+            Sdf::Repeat(period, count, shape) => {
+                let count =
+                    count.unwrap_or_else(|| Vector::repeat(f64::INFINITY));
+                let mut q = p;
+                for i in 0..3 {
+                    if period[i] > 0.0 {
+                        let cell = fclamp(
+                            (p[i] / period[i]).round(),
+                            -count[i],
+                            count[i],
+                        );
+                        q[i] -= period[i] * cell;
+                    }
+                }
+                shape.distance(q)
+            }
+            Sdf::Twist(rate, _, shape) => {
+                let (sin, cos) = (-rate * p.y).sin_cos();
+                let q = Point::new(
+                    cos * p.x + sin * p.z,
+                    p.y,
+                    cos * p.z - sin * p.x,
+                );
+                shape.distance(q)
+            }
+            Sdf::Transform(transform, shape) => {
+                shape.distance(transform.inverse_transform_point(&p))
+                    * transform.scaling()
+            }
+        }
+    }
+
+    /// Bounds of the shape, unless it is infinite.
+    pub fn bounds(&self) -> Option<AABB> {
+        let symmetric = |half: Vector| {
+            Some(AABB::new(Point::from(-half), Point::from(half)))
+        };
+        match self {
+            &Sdf::Sphere(radius) => symmetric(Vector::repeat(radius)),
+            &Sdf::Box(half) => symmetric(half),
+            &Sdf::Torus(major, minor) => {
+                symmetric(Vector::new(major + minor, minor, major + minor))
+            }
+            &Sdf::Cylinder(radius, half_height) => {
+                symmetric(Vector::new(radius, half_height, radius))
+            }
+            &Sdf::Capsule(a, b, radius) => {
+                let r = Vector::repeat(radius);
+                Some(AABB::new(a.inf(&b) - r, a.sup(&b) + r))
+            }
+            Sdf::Union(shapes, k) => {
+                let mut bounds = shapes[0].bounds()?;
+                for shape in &shapes[1..] {
+                    bounds = AABB::containing(bounds, shape.bounds()?);
+                }
+                // Blending can swell the shapes by up to a quarter of the
+                // smoothness.
+                Some(grow(bounds, k / 4.0))
+            }
+            Sdf::Intersection(shapes) => {
+                let mut bounds = shapes.iter().filter_map(Sdf::bounds);
+                let first = bounds.next()?;
+                Some(bounds.fold(first, |a, b| {
+                    let min = a.min.sup(&b.min);
+                    AABB::new(min, a.max.inf(&b.max).sup(&min))
+                }))
+            }
+            Sdf::Difference(shapes) => shapes[0].bounds(),
+            Sdf::Round(radius, shape) => Some(grow(shape.bounds()?, *radius)),
+            Sdf::Repeat(period, count, shape) => {
+                let bounds = shape.bounds()?;
+                let mut reach = Vector::zeros();
+                for i in 0..3 {
+                    if period[i] > 0.0 {
+                        reach[i] = period[i] * (*count)?[i];
+                    }
+                }
+                Some(AABB::new(bounds.min - reach, bounds.max + reach))
+            }
+            Sdf::Twist(_, reach, shape) => {
+                let bounds = shape.bounds()?;
+                Some(AABB::new(
+                    Point::new(-reach, bounds.min.y, -reach),
+                    Point::new(*reach, bounds.max.y, *reach),
+                ))
+            }
+            Sdf::Transform(transform, shape) => {
+                let bounds = shape.bounds()?;
+                let mut min =
+                    Point::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+                let mut max = -min;
+                for i in 0..8 {
+                    let corner = Point::new(
+                        if i & 1 == 0 {
+                            bounds.min.x
+                        } else {
+                            bounds.max.x
+                        },
+                        if i & 2 == 0 {
+                            bounds.min.y
+                        } else {
+                            bounds.max.y
+                        },
+                        if i & 4 == 0 {
+                            bounds.min.z
+                        } else {
+                            bounds.max.z
+                        },
+                    );
+                    let p = transform.transform_point(&corner);
+                    min = min.inf(&p);
+                    max = max.sup(&p);
+                }
+                Some(AABB::new(min, max))
+            }
+        }
+    }
+
+    /// Bound on how much faster the function changes than the distance to
+    /// the surface, by which steps along rays are shortened.
+    fn lipschitz(&self) -> f64 {
+        match self {
+            Sdf::Sphere(..)
+            | Sdf::Box(..)
+            | Sdf::Torus(..)
+            | Sdf::Cylinder(..)
+            | Sdf::Capsule(..) => 1.0,
+            Sdf::Union(shapes, _)
+            | Sdf::Intersection(shapes)
+            | Sdf::Difference(shapes) => {
+                shapes.iter().map(Sdf::lipschitz).fold(1.0, f64::max)
+            }
+            Sdf::Round(_, shape)
+            | Sdf::Repeat(_, _, shape)
+            | Sdf::Transform(_, shape) => shape.lipschitz(),
+            Sdf::Twist(rate, reach, shape) => {
+                shape.lipschitz() * (1.0 + (rate * reach).powi(2)).sqrt()
+            }
+        }
+    }
+}
+
+fn grow(aabb: AABB, by: f64) -> AABB {
+    let by = Vector::repeat(by);
+    AABB::new(aabb.min - by, aabb.max + by)
+}
+
+/// Object whose surface is where a signed distance function is zero, found by
+/// sphere tracing: stepping along rays by the distance to the nearest surface,
+/// which cannot be crossed in one step.
+pub struct SdfObject {
+    material: Arc<Material>,
+    sdf: Sdf,
+    aabb: AABB,
+    lipschitz: f64,
+}
+
+/// Shortest step along a ray, so that rays approaching a surface at a shallow
+/// angle cross it. Crossings are then found exactly by bisection.
+const MIN_STEP: f64 = 1e-4;
+const MAX_STEPS: usize = 1000;
+
+impl SdfObject {
+    pub fn new(
+        material: Arc<Material>,
+        sdf: Sdf,
+        aabb: AABB,
+    ) -> Arc<SdfObject> {
+        Arc::new(SdfObject {
+            material,
+            lipschitz: sdf.lipschitz(),
+            sdf,
+            aabb,
+        })
+    }
+
+    /// Gradient of the distance function, by differences over the corners
+    /// of a small tetrahedron.
+    fn gradient(&self, p: Point) -> Vector {
+        let h = MIN_STEP / 2.0;
+        [
+            Vector::new(1.0, -1.0, -1.0),
+            Vector::new(-1.0, -1.0, 1.0),
+            Vector::new(-1.0, 1.0, -1.0),
+            Vector::new(1.0, 1.0, 1.0),
+        ]
+        .iter()
+        .map(|k| k * self.sdf.distance(p + h * k))
+        .sum()
+    }
+}
+
+impl Hittable for SdfObject {
+    fn is_light(&self) -> bool {
+        false
+    }
+
+    fn bounding_box(&self) -> AABB {
+        self.aabb
+    }
+
+    fn hit(&self, ray: Ray, range: Range<f64>) -> Option<Hit> {
+        let range = self.aabb.clip(ray, range)?;
+        let speed = ray.dir.norm();
+        let distance = |t: f64| self.sdf.distance(ray.at(t));
+
+        // Trace away from the side of the surface the ray starts on. Rays
+        // starting on the surface are on the side they are heading into.
+        let mut t = range.start;
+        let d = distance(t);
+        let side = if d.abs() < MIN_STEP {
+            self.gradient(ray.at(t)).dot(&ray.dir).signum()
+        } else {
+            d.signum()
+        };
+        let mut prev = t;
+        for step in 0..MAX_STEPS {
+            let d = side * distance(t);
+            if d < 0.0 && step > 0 {
+                // Find where the ray crossed the surface since the last
+                // step.
+                let (mut lo, mut hi) = (prev, t);
+                for _ in 0..40 {
+                    let mid = 0.5 * (lo + hi);
+                    if side * distance(mid) < 0.0 {
+                        hi = mid;
+                    } else {
+                        lo = mid;
+                    }
+                }
+                t = hi;
+                break;
+            }
+            prev = t;
+            t += (d.abs() / self.lipschitz).max(MIN_STEP) / speed;
+            if t >= range.end {
+                return None;
+            }
+        }
+        if side * distance(t) >= 0.0 {
+            return None;
+        }
+
+        let p = ray.at(t);
+        let normal = Unit::new_normalize(self.gradient(p));
+        Some(Hit::new(ray, normal, t, &self.material, (0.0, 0.0)))
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn sphere_tracing_finds_blended_and_twisted_surfaces() {
+    let desc: SdfDesc = toml::from_str(
+        r#"
+        kind = "Union"
+        smoothness = 1
+        [[shapes]]
+        kind = "Sphere"
+        radius = 1
+        translate = [-1, 0, 0]
+        [[shapes]]
+        kind = "Twist"
+        rate = 90
+        translate = [1, 0, 0]
+        shape = { kind = "Box", size = [1, 2, 1] }
+        "#,
+    )
+    .unwrap();
+    let sdf = desc.build().unwrap();
+    let bounds = sdf.bounds().unwrap();
+    let material = Arc::new(Material::Metal(Color::repeat(0.5), 0.0));
+    let object = SdfObject::new(material, sdf, bounds);
+    let range = 0.0001..f64::INFINITY;
+
+    // The sphere is exact away from the blend.
+    let ray = Ray::new(Point::new(-5.0, 0.0, 0.0), Vector::x());
+    let hit = object.hit(ray, range.clone()).unwrap();
+    assert!((hit.t - 3.0).abs() < 1e-6, "{}", hit.t);
+    assert!((*hit.normal + Vector::x()).norm() < 1e-3);
+    // The blend fills in the gap where the shapes meet.
+    let ray = Ray::new(Point::new(0.0, 5.0, 0.0), -Vector::y());
+    let hit = object.hit(ray, range.clone()).unwrap();
+    assert!(hit.point.y > 0.0 && hit.point.y < 1.0);
+    // The box is turned by 45 degrees halfway up, so that its corner
+    // reaches out along x.
+    let ray = Ray::new(Point::new(5.0, 0.5, 0.0), -Vector::x());
+    let hit = object.hit(ray, range.clone()).unwrap();
+    assert!((hit.point.x - (1.0 + 0.5 * 2.0f64.sqrt())).abs() < 1e-3);
+
+    // Rays leaving the inside find the surface they cross.
+    let ray = Ray::new(Point::new(-1.0, 0.0, 0.0), -Vector::x());
+    let hit = object.hit(ray, range).unwrap();
+    assert!((hit.t - 1.0).abs() < 1e-6 && !hit.front_facing);
+}
@@ -0,0 +1,298 @@
+use super::*;
+use crate::spectrum::{blackbody_rgb, xyz_to_rgb};
+use crate::texture::HdrImage;
+use nalgebra::{Matrix3, Rotation3};
+use std::f64::consts::PI;
+
+/// Daylight sky following "A Practical Analytic Model for Daylight",
+/// Preetham, Shirley and Smits, 1999, with a solar disc.
+///
+/// Radiance is in units of 1000 cd/m^2 before scaling by `intensity`.
+pub struct Sky {
+    model: Preetham,
+    cos_sun_radius: f64,
+    sun_radiance: Color,
+    /// Radiance reflected from the ground below the horizon.
+    ground: Color,
+    intensity: f64,
+    /// Tabulated sky without the sun, used for importance sampling.
+    table: EnvMap,
+}
+
+/// Sky radiance distribution, excluding the sun itself.
+struct Preetham {
+    sun_dir: Unit<Vector>,
+    /// Perez distribution coefficients for luminance and each chromaticity
+    /// coordinate.
+    perez: [[f64; 5]; 3],
+    /// Luminance and chromaticity at the zenith.
+    zenith: [f64; 3],
+    xyz_to_rgb: Matrix3<f64>,
+}
+
+/// Probability of sampling the solar disc rather than the rest of the sky.
+const SUN_SAMPLE_PROBABILITY: f64 = 0.5;
+
+/// Luminance of the sun seen from the top of the atmosphere, in 1000 cd/m^2.
+const SUN_LUMINANCE: f64 = 1.6e6;
+
+impl Sky {
+    /// Creates a sky with the sun at `elevation` degrees above the horizon
+    /// and `azimuth` degrees from the z axis towards the x axis.
+    /// `turbidity` ranges from 2 for a very clear sky to 10 for haze, and
+    /// `ground` is the albedo of the ground below the horizon.
+    pub fn new(
+        elevation: f64,
+        azimuth: f64,
+        turbidity: f64,
+        sun_radius: f64,
+        ground: Color,
+        intensity: f64,
+    ) -> Sky {
+        let (el, az) = (elevation.to_radians(), azimuth.to_radians());
+        let sun_dir = Unit::new_normalize(Vector::new(
+            el.cos() * az.sin(),
+            el.sin(),
+            el.cos() * az.cos(),
+        ));
+        let t = turbidity;
+        let theta_s = PI / 2.0 - el.max(0.0);
+
+        let perez = [
+            [
+                0.1787 * t - 1.4630,
+                -0.3554 * t + 0.4275,
+                -0.0227 * t + 5.3251,
+                0.1206 * t - 2.5771,
+                -0.0670 * t + 0.3703,
+            ],
+            [
+                -0.0193 * t - 0.2592,
+                -0.0665 * t + 0.0008,
+                -0.0004 * t + 0.2125,
+                -0.0641 * t - 0.8989,
+                -0.0033 * t + 0.0452,
+            ],
+            [
+                -0.0167 * t - 0.2608,
+                -0.0950 * t + 0.0092,
+                -0.0079 * t + 0.2102,
+                -0.0441 * t - 1.6537,
+                -0.0109 * t + 0.0529,
+            ],
+        ];
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let zenith_y = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let cubic = |c: [f64; 4]| {
+            c[0] * theta_s.powi(3)
+                + c[1] * theta_s.powi(2)
+                + c[2] * theta_s
+                + c[3]
+        };
+        let zenith_x = t * t * cubic([0.00166, -0.00375, 0.00209, 0.0])
+            + t * cubic([-0.02903, 0.06377, -0.03202, 0.00394])
+            + cubic([0.11693, -0.21196, 0.06052, 0.25886]);
+        let zenith_yc = t * t * cubic([0.00275, -0.00610, 0.00317, 0.0])
+            + t * cubic([-0.04214, 0.08970, -0.04153, 0.00516])
+            + cubic([0.15346, -0.26756, 0.06670, 0.26688]);
+
+        let model = Preetham {
+            sun_dir,
+            perez,
+            zenith: [zenith_y.max(0.0), zenith_x, zenith_yc],
+            xyz_to_rgb: xyz_to_rgb(),
+        };
+        let cos_sun_radius = sun_radius.to_radians().cos();
+        let sun_radiance = if elevation > -sun_radius {
+            SUN_LUMINANCE
+                * blackbody_rgb(5778.0)
+                    .component_mul(&transmittance(theta_s, turbidity))
+        } else {
+            Color::zeros()
+        };
+
+        // Tabulate the sky to importance sample it, and to estimate the
+        // irradiance which lights the ground.
+        const WIDTH: usize = 128;
+        const HEIGHT: usize = 64;
+        let mut pixels = Vec::with_capacity(WIDTH * HEIGHT);
+        let mut irradiance = Color::zeros();
+        for y in 0..HEIGHT {
+            let theta = PI * (y as f64 + 0.5) / HEIGHT as f64;
+            for x in 0..WIDTH {
+                let phi = 2.0 * PI * (x as f64 + 0.5) / WIDTH as f64;
+                let dir = Vector::new(
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    theta.sin() * phi.sin(),
+                );
+                let radiance = model.radiance(dir);
+                if dir.y > 0.0 {
+                    let solid_angle =
+                        2.0 * PI * PI * theta.sin() / (WIDTH * HEIGHT) as f64;
+                    irradiance += radiance * dir.y * solid_angle;
+                }
+                pixels.push(radiance);
+            }
+        }
+        let sun_solid_angle = 2.0 * PI * (1.0 - cos_sun_radius);
+        irradiance += sun_radiance * sun_solid_angle * sun_dir.y.max(0.0);
+        let ground = ground.component_mul(&irradiance) / PI;
+        for pixel in &mut pixels[WIDTH * HEIGHT / 2..] {
+            *pixel = ground;
+        }
+        let table = EnvMap::new(
+            HdrImage {
+                width: WIDTH,
+                height: HEIGHT,
+                pixels,
+            },
+            Rotation3::identity(),
+            1.0,
+        );
+
+        Sky {
+            model,
+            cos_sun_radius,
+            sun_radiance,
+            ground,
+            intensity,
+            table,
+        }
+    }
+
+    pub fn eval(&self, dir: Vector) -> Color {
+        let dir = dir.normalize();
+        let radiance = if dir.y < 0.0 {
+            self.ground
+        } else if dir.dot(&self.model.sun_dir) >= self.cos_sun_radius {
+            self.model.radiance(dir) + self.sun_radiance
+        } else {
+            self.model.radiance(dir)
+        };
+        self.intensity * radiance
+    }
+
+    /// Samples a direction towards either the sun or the sky.
+    pub fn sample(&self) -> Vector {
+        if random() < SUN_SAMPLE_PROBABILITY {
+            let cos_theta = 1.0 - random() * (1.0 - self.cos_sun_radius);
+            let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+            let phi = 2.0 * PI * random();
+            ONB::from_w(self.model.sun_dir).localize(Vector::new(
+                sin_theta * phi.cos(),
+                sin_theta * phi.sin(),
+                cos_theta,
+            ))
+        } else {
+            self.table.sample()
+        }
+    }
+
+    /// Solid angle density with which `sample` generates `dir`.
+    pub fn pdf(&self, dir: Vector) -> f64 {
+        let cos = dir.normalize().dot(&self.model.sun_dir);
+        let sun = if cos >= self.cos_sun_radius {
+            1.0 / (2.0 * PI * (1.0 - self.cos_sun_radius))
+        } else {
+            0.0
+        };
+        SUN_SAMPLE_PROBABILITY * sun
+            + (1.0 - SUN_SAMPLE_PROBABILITY) * self.table.pdf(dir)
+    }
+}
+
+impl Preetham {
+    /// Radiance in the unit direction `dir`, continued below the horizon by
+    /// its value at the horizon.
+    fn radiance(&self, dir: Vector) -> Color {
+        let cos_theta = dir.y.max(0.01);
+        let cos_gamma = fclamp(dir.dot(&self.sun_dir), -1.0, 1.0);
+        let gamma = cos_gamma.acos();
+        let theta_s = self.sun_dir.y.max(0.0).acos();
+
+        let perez = |c: &[f64; 5], cos_theta: f64, gamma: f64| {
+            (1.0 + c[0] * (c[1] / cos_theta).exp())
+                * (1.0
+                    + c[2] * (c[3] * gamma).exp()
+                    + c[4] * gamma.cos() * gamma.cos())
+        };
+        let value = |i: usize| {
+            self.zenith[i] * perez(&self.perez[i], cos_theta, gamma)
+                / perez(&self.perez[i], 1.0, theta_s)
+        };
+        let (lum, x, y) = (value(0), value(1), value(2));
+        if y <= 0.0 {
+            return Color::zeros();
+        }
+        let xyz = Color::new(x / y * lum, lum, (1.0 - x - y) / y * lum);
+        (self.xyz_to_rgb * xyz).map(|c| c.max(0.0))
+    }
+}
+
+/// Fraction of sunlight reaching the ground through the atmosphere at the
+/// red, green and blue wavelengths, following the appendix of the Preetham
+/// paper for Rayleigh and aerosol scattering.
+fn transmittance(theta_s: f64, turbidity: f64) -> Color {
+    // Relative optical mass of the air the light passes through.
+    let degrees = theta_s.to_degrees().min(93.0);
+    let mass = 1.0 / (theta_s.cos() + 0.15 * (93.885 - degrees).powf(-1.253));
+    let beta = 0.04608 * turbidity - 0.04586;
+    const ALPHA: f64 = 1.3;
+    Color::from_iterator([0.68, 0.55, 0.44].iter().map(|&lambda: &f64| {
+        let rayleigh = (-0.008735 * lambda.powf(-4.08) * mass).exp();
+        let aerosol = (-beta * lambda.powf(-ALPHA) * mass).exp();
+        rayleigh * aerosol
+    }))
+}
+
+#[cfg(test)]
+#[test]
+fn sky_pdf_integrates_to_one_and_matches_samples() {
+    let sky = Sky::new(30.0, 40.0, 3.0, 2.0, Color::repeat(0.2), 1.0);
+    let frame = ONB::from_w(sky.model.sun_dir);
+    // Integrate over rings around the sun, with a ring boundary at the edge
+    // of the solar disc, accumulating the mass above the horizon as well.
+    let (mut total, mut above) = (0.0, 0.0);
+    let mut ring = |cos_min: f64, cos_max: f64, rows: usize| {
+        let cols = 1000;
+        let d_cos = (cos_max - cos_min) / rows as f64;
+        for i in 0..rows {
+            let cos_theta = cos_min + (i as f64 + 0.5) * d_cos;
+            let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+            for j in 0..cols {
+                let phi = 2.0 * PI * (j as f64 + 0.5) / cols as f64;
+                let dir = frame.localize(Vector::new(
+                    sin_theta * phi.cos(),
+                    sin_theta * phi.sin(),
+                    cos_theta,
+                ));
+                let mass = sky.pdf(dir) * d_cos * 2.0 * PI / cols as f64;
+                total += mass;
+                if dir.y > 0.0 {
+                    above += mass;
+                }
+            }
+        }
+    };
+    ring(sky.cos_sun_radius, 1.0, 10);
+    ring(-1.0, sky.cos_sun_radius, 1000);
+    assert!((total - 1.0).abs() < 1e-3, "total {}", total);
+
+    let n = 100_000;
+    let (mut in_sun, mut sampled_above) = (0, 0);
+    for _ in 0..n {
+        let dir = sky.sample();
+        assert!(sky.pdf(dir) > 0.0);
+        if dir.normalize().dot(&sky.model.sun_dir) >= sky.cos_sun_radius {
+            in_sun += 1;
+        }
+        if dir.y > 0.0 {
+            sampled_above += 1;
+        }
+    }
+    let fraction = |count: usize| count as f64 / n as f64;
+    assert!((fraction(in_sun) - SUN_SAMPLE_PROBABILITY).abs() < 0.01);
+    assert!((fraction(sampled_above) - above).abs() < 0.01);
+}
@@ -12,6 +12,9 @@ pub struct Sphere {
     material: Arc<Material>,
     center: Point,
     radius: f64,
+    /// Displacement of the center from time 0 to time 1, outside which the
+    /// sphere stays still.
+    motion: Vector,
 }
 
 impl Sphere {
@@ -19,11 +22,23 @@ impl Sphere {
         material: Arc<Material>,
         center: Point,
         radius: f64,
+    ) -> Arc<Sphere> {
+        Sphere::moving(material, center, center, radius)
+    }
+
+    /// Creates a sphere which moves from `center` at time 0 to `end` at
+    /// time 1.
+    pub fn moving(
+        material: Arc<Material>,
+        center: Point,
+        end: Point,
+        radius: f64,
     ) -> Arc<Sphere> {
         Arc::new(Sphere {
             material,
             center,
             radius,
+            motion: end - center,
         })
     }
 }
@@ -37,14 +52,14 @@ impl Hittable for Sphere {
     }
 
     fn bounding_box(&self) -> AABB {
-        AABB::new(
-            self.center - Vector::repeat(self.radius),
-            self.center + Vector::repeat(self.radius),
-        )
+        let r = Vector::repeat(self.radius);
+        let end = self.center + self.motion;
+        AABB::new(self.center.inf(&end) - r, self.center.sup(&end) + r)
     }
 
     fn hit(&self, ray: Ray, range: Range<f64>) -> Option<Hit> {
-        let oc = ray.origin - self.center;
+        let center = self.center_at(ray.time);
+        let oc = ray.origin - center;
         // Solve the quadratic formula.
         let (a, half_b, c) = (
             ray.dir.norm_squared(),
@@ -69,16 +84,13 @@ impl Hittable for Sphere {
                 return None;
             };
             let point = ray.at(t);
-            let normal = Unit::new_unchecked(
-                (point - self.center) * self.radius.recip(),
-            );
-            Some(Hit::new(
-                ray,
-                normal,
-                t,
-                &self.material,
-                self.get_uv(*normal),
-            ))
+            let normal =
+                Unit::new_unchecked((point - center) * self.radius.recip());
+            let (dpdu, dpdv) = self.get_partials(*normal);
+            Some(
+                Hit::new(ray, normal, t, &self.material, self.get_uv(*normal))
+                    .with_partials(ray, dpdu, dpdv),
+            )
         }
     }
 
@@ -86,43 +98,59 @@ impl Hittable for Sphere {
         match self.hit(ray, 0.0001..f64::INFINITY) {
             None => 0.0,
             Some(_hit) => {
-                let norm_squared = (self.center - ray.origin).norm_squared();
-                let cos_theta_max =
-                    (1.0 - self.radius * self.radius / norm_squared).sqrt();
+                let center = self.center_at(ray.time);
+                let norm_squared = (center - ray.origin).norm_squared();
+                let sin_squared = self.radius * self.radius / norm_squared;
+                if sin_squared >= 1.0 {
+                    // Every direction from inside the sphere hits it.
+                    return 1.0 / (4.0 * PI);
+                }
+                let cos_theta_max = (1.0 - sin_squared).sqrt();
                 let solid_angle = 2.0 * PI * (1.0 - cos_theta_max);
                 1.0 / solid_angle
             }
         }
     }
 
-    fn random(&self, origin: Point) -> Vector {
-        let dir = self.center - origin;
+    fn random(&self, origin: Point, time: f64) -> Vector {
+        let dir = self.center_at(time) - origin;
+        if dir.norm_squared() <= self.radius * self.radius {
+            return random_unit_vector();
+        }
         let uvw = ONB::from_w(Unit::new_normalize(dir));
         uvw.localize(self.random_to_sphere(dir))
     }
 
-    fn emit(&self) -> (Ray, Unit<Vector>, Color) {
-        let dir = random_unit_vector();
-        let ray = Ray {
-            origin: self.center + (self.radius * dir),
-            dir,
+    fn emit(&self, time: f64) -> (Ray, Unit<Vector>, Color) {
+        let outward = Unit::new_unchecked(random_unit_vector());
+        let normal = if self.material.choose_emitting_side() {
+            outward
+        } else {
+            -outward
         };
-        let normal = Unit::new_unchecked(dir);
-        (
-            ray,
-            normal,
-            self.material.emitted(&Hit::new(
-                ray,
-                normal,
-                0.0,
-                &self.material,
-                (0.0, 0.0),
-            )),
-        )
+        let dir = ONB::from_w(normal).localize(random_cosine_dir());
+        let mut ray =
+            Ray::new(self.center_at(time) + self.radius * *outward, dir);
+        ray.time = time;
+        // Radiance is evaluated as if seen from along the emitted ray.
+        let inbound = Ray::new(ray.origin + dir, -dir);
+        let uv = self.get_uv(*outward);
+        let hit = Hit::new(inbound, outward, 1.0, &self.material, uv);
+        (ray, normal, self.material.emitted(&inbound, &hit))
+    }
+
+    fn power(&self) -> f64 {
+        let area = 4.0 * PI * self.radius * self.radius;
+        self.material.emitted_power(area)
     }
 }
 
 impl Sphere {
+    /// Center of the sphere at `time`.
+    fn center_at(&self, time: f64) -> Point {
+        self.center + fclamp(time, 0.0, 1.0) * self.motion
+    }
+
     fn get_uv(&self, loc: Vector) -> (f64, f64) {
         let phi = loc.z.atan2(loc.x);
         let theta = loc.y.asin();
@@ -131,6 +159,18 @@ impl Sphere {
         (u, v)
     }
 
+    /// Partial derivatives of the surface point with respect to the UVs
+    /// produced by `get_uv`.
+    fn get_partials(&self, loc: Vector) -> (Vector, Vector) {
+        let dpdu = 2.0 * PI * self.radius * Vector::new(loc.z, 0.0, -loc.x);
+        let cos_theta = f64::max(loc.x.hypot(loc.z), 1e-8);
+        let (cos_phi, sin_phi) = (loc.x / cos_theta, loc.z / cos_theta);
+        let dpdv = PI
+            * self.radius
+            * Vector::new(-loc.y * cos_phi, cos_theta, -loc.y * sin_phi);
+        (dpdu, dpdv)
+    }
+
     fn random_to_sphere(&self, dir: Vector) -> Vector {
         let norm_squared = dir.norm_squared();
         let r1 = random();
@@ -147,3 +187,31 @@ impl Sphere {
         Vector::new(x, y, z)
     }
 }
+
+#[cfg(test)]
+#[test]
+fn moving_spheres_are_sampled_where_they_are() {
+    let material = Arc::new(Material::Metal(Color::repeat(0.5), 0.0));
+    let sphere = Sphere::moving(
+        material,
+        Point::origin(),
+        Point::new(4.0, 0.0, 0.0),
+        0.5,
+    );
+    let origin = Point::new(2.0, 0.0, -5.0);
+    for &time in &[0.0, 0.5, 1.0] {
+        for _ in 0..100 {
+            let mut ray = Ray::new(origin, sphere.random(origin, time));
+            ray.time = time;
+            assert!(sphere.hit(ray, 0.0001..f64::INFINITY).is_some());
+            assert!(sphere.pdf(ray) > 0.0);
+        }
+        // The pdf matches the solid angle the sphere covers at this time.
+        let center = sphere.center_at(time);
+        let mut ray = Ray::new(origin, center - origin);
+        ray.time = time;
+        let sin_squared = 0.25 / (center - origin).norm_squared();
+        let solid_angle = 2.0 * PI * (1.0 - (1.0 - sin_squared).sqrt());
+        assert!((sphere.pdf(ray) * solid_angle - 1.0).abs() < 1e-9);
+    }
+}
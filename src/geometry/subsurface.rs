@@ -0,0 +1,95 @@
+use super::*;
+use crate::material::Medium;
+use std::ops::Range;
+
+/// Closed object with a `Material::Subsurface` surface, whose interior is a
+/// homogeneous medium. Rays from outside hit the surface, while rays inside
+/// collide with the medium at random distances until they reach the surface
+/// again, so refracted light performs a random walk through the object.
+pub struct SubsurfaceObject {
+    boundary: Arc<dyn Hittable>,
+    interior: Material,
+    medium: Medium,
+}
+
+impl SubsurfaceObject {
+    /// Fills `boundary`, whose surface must be a `Material::Subsurface`.
+    pub fn new(
+        boundary: Arc<dyn Hittable>,
+        material: &Material,
+    ) -> Result<Arc<SubsurfaceObject>, String> {
+        let medium = match material {
+            Material::Subsurface(subsurface) => subsurface.medium,
+            _ => {
+                return Err(
+                    "subsurface object requires a subsurface material".into()
+                )
+            }
+        };
+        Ok(Arc::new(SubsurfaceObject {
+            boundary,
+            interior: Material::Interior(medium),
+            medium,
+        }))
+    }
+}
+
+impl Hittable for SubsurfaceObject {
+    fn is_light(&self) -> bool {
+        false
+    }
+
+    fn bounding_box(&self) -> AABB {
+        self.boundary.bounding_box()
+    }
+
+    fn hit(&self, ray: Ray, range: Range<f64>) -> Option<Hit> {
+        let surface = self.boundary.hit(ray, range.clone())?;
+        // Surfaces face outwards, so a ray which reaches the back of one
+        // starts inside.
+        if surface.front_facing {
+            return Some(surface);
+        }
+
+        let speed = ray.dir.norm();
+        let distance = self.medium.sample_distance();
+        if distance >= (surface.t - range.start) * speed {
+            return Some(surface);
+        }
+        let t = range.start + distance / speed;
+        let normal = Unit::new_normalize(-ray.dir);
+        Some(Hit::new(ray, normal, t, &self.interior, (0.0, 0.0)))
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn rays_inside_collide_before_leaving() {
+    use crate::material::Subsurface;
+    let material = Arc::new(Material::Subsurface(Subsurface::new(
+        Color::repeat(0.8),
+        Color::repeat(1.0),
+        1.3,
+        0.0,
+    )));
+    let boundary = Sphere::new(material.clone(), Point::origin(), 2.0);
+    let object = SubsurfaceObject::new(boundary, &material).unwrap();
+
+    // From outside, rays stop at the surface.
+    let ray = Ray::new(Point::new(0.0, 0.0, -5.0), Vector::z());
+    let hit = object.hit(ray, 0.0001..f64::INFINITY).unwrap();
+    assert!(hit.front_facing && (hit.t - 3.0).abs() < 1e-9);
+    assert!(matches!(hit.material, Material::Subsurface(..)));
+
+    // From the center, rays cross the medium before reaching the surface.
+    let ray = Ray::new(Point::origin(), Vector::z());
+    let n = 20000;
+    let escaped = (0..n)
+        .filter(|_| {
+            let hit = object.hit(ray, 0.0001..f64::INFINITY).unwrap();
+            matches!(hit.material, Material::Subsurface(..))
+        })
+        .count();
+    let actual = escaped as f64 / n as f64;
+    assert!((actual - (-2.0f64).exp()).abs() < 0.02, "{}", actual);
+}
@@ -0,0 +1,167 @@
+use super::*;
+
+/// Density over solid angle of the directions from `ray.origin` towards
+/// points chosen uniformly over the `area` of `shape`. Rays may pass through
+/// several such points on shapes which are not convex, and the densities of
+/// each add up.
+pub(super) fn area_pdf(shape: &dyn Hittable, ray: Ray, area: f64) -> f64 {
+    let mut pdf = 0.0;
+    let mut start = 0.0001;
+    // Tori, the least convex of the shapes, are crossed at most four times.
+    for _ in 0..4 {
+        let hit = match shape.hit(ray, start..f64::INFINITY) {
+            Some(hit) => hit,
+            None => break,
+        };
+        let norm_squared = hit.t * hit.t * ray.dir.norm_squared();
+        let cos = (ray.dir.dot(&hit.normal) / ray.dir.norm()).abs();
+        pdf += norm_squared / (cos * area);
+        start = hit.t + 0.0001;
+    }
+    pdf
+}
+
+/// Emits a ray from `point` on a surface of `material` whose outward normal
+/// is `normal`, as `Hittable::emit` does.
+pub(super) fn emit_from(
+    material: &Material,
+    point: Point,
+    normal: Unit<Vector>,
+    uv: (f64, f64),
+) -> (Ray, Unit<Vector>, Color) {
+    let side = if material.choose_emitting_side() {
+        normal
+    } else {
+        -normal
+    };
+    let dir = ONB::from_w(side).localize(random_cosine_dir());
+    let ray = Ray::new(point, dir);
+    // Radiance is evaluated as if seen from along the emitted ray.
+    let inbound = Ray::new(point + dir, -dir);
+    let hit = Hit::new(inbound, normal, 1.0, material, uv);
+    (ray, side, material.emitted(&inbound, &hit))
+}
+
+/// Bounds of a disk of `radius` around `center` facing along `normal`.
+pub(super) fn disk_bounds(
+    center: Point,
+    normal: Unit<Vector>,
+    radius: f64,
+) -> AABB {
+    let extent = normal.map(|n| radius * (1.0 - n * n).max(0.0).sqrt());
+    let pad = Vector::repeat(0.001);
+    AABB::new(center - extent - pad, center + extent + pad)
+}
+
+#[cfg(test)]
+#[test]
+fn sampled_directions_cover_each_surface() {
+    use std::f64::consts::PI;
+
+    let material = Arc::new(Material::Lambertian(Arc::new(Texture::Solid(
+        Color::repeat(0.5),
+    ))));
+    let m = || material.clone();
+    let shapes: Vec<(&str, Arc<dyn Hittable>)> = vec![
+        (
+            "disk",
+            Disk::new(m(), Point::origin(), Vector::y_axis(), 1.0, 0.0),
+        ),
+        (
+            "annulus",
+            Disk::new(m(), Point::origin(), Vector::x_axis(), 1.5, 0.5),
+        ),
+        (
+            "cylinder",
+            Cone::new(
+                m(),
+                Point::origin(),
+                Point::new(0.0, 1.0, 1.0),
+                0.6,
+                0.6,
+                true,
+            ),
+        ),
+        (
+            "tube",
+            Cone::new(
+                m(),
+                Point::origin(),
+                Point::new(0.0, 2.0, 0.0),
+                0.8,
+                0.8,
+                false,
+            ),
+        ),
+        (
+            "cone",
+            Cone::new(
+                m(),
+                Point::origin(),
+                Point::new(1.0, 1.0, 0.0),
+                1.0,
+                0.0,
+                true,
+            ),
+        ),
+        (
+            "torus",
+            Torus::new(m(), Point::origin(), Vector::z_axis(), 1.0, 0.3),
+        ),
+        (
+            "square",
+            Quad::new(m(), Point::origin(), Vector::x(), Vector::z()),
+        ),
+        (
+            "rhombus",
+            Quad::new(
+                m(),
+                Point::origin(),
+                Vector::x(),
+                Vector::new(0.5, 1.0, 0.0),
+            ),
+        ),
+    ];
+
+    // The solid angle which the shape covers is found both by counting the
+    // rays which hit it, stratified by the cosine and longitude of their
+    // directions, and from the mean reciprocal density of sampled directions.
+    let origin = Point::new(0.4, 1.5, -2.0);
+    let n = 300;
+    for (name, shape) in &shapes {
+        let mut hits = 0;
+        for i in 0..n {
+            for j in 0..n {
+                let z = 1.0 - 2.0 * (i as f64 + random()) / n as f64;
+                let phi = 2.0 * PI * (j as f64 + random()) / n as f64;
+                let r = (1.0 - z * z).sqrt();
+                let dir = Vector::new(r * phi.cos(), r * phi.sin(), z);
+                let ray = Ray::new(origin, dir);
+                if shape.hit(ray, 0.0001..f64::INFINITY).is_some() {
+                    hits += 1;
+                }
+            }
+        }
+        let solid_angle = 4.0 * PI * hits as f64 / (n * n) as f64;
+
+        let samples = 50000;
+        let mut total = 0.0;
+        for _ in 0..samples {
+            let ray = Ray::new(origin, shape.random(origin, 0.0));
+            assert!(
+                shape.hit(ray, 0.0001..f64::INFINITY).is_some(),
+                "{}",
+                name
+            );
+            total += shape.pdf(ray).recip();
+        }
+        let estimate = total / samples as f64;
+        assert!(
+            (estimate / solid_angle - 1.0).abs() < 0.02,
+            "{}: {} != {}",
+            name,
+            estimate,
+            solid_angle
+        );
+    }
+}
@@ -0,0 +1,250 @@
+use super::surface::{area_pdf, emit_from};
+use super::*;
+use std::f64::consts::PI;
+
+/// Ring made by sweeping a circle of the `minor` radius around a circle of
+/// the `major` radius, centered on `center` and facing along `axis`. The `u`
+/// coordinate runs around the axis and `v` around the tube, starting from
+/// its outer edge.
+pub struct Torus {
+    material: Arc<Material>,
+    center: Point,
+    /// Frame with `w` along the axis.
+    frame: ONB,
+    major: f64,
+    minor: f64,
+}
+
+impl Torus {
+    pub fn new(
+        material: Arc<Material>,
+        center: Point,
+        axis: Unit<Vector>,
+        major: f64,
+        minor: f64,
+    ) -> Arc<Torus> {
+        Arc::new(Torus {
+            material,
+            center,
+            frame: ONB::from_w(axis),
+            major,
+            minor,
+        })
+    }
+
+    pub fn area(&self) -> f64 {
+        4.0 * PI * PI * self.major * self.minor
+    }
+
+    /// Point at angle `phi` around the axis and `theta` around the tube,
+    /// with its outward normal.
+    fn point(&self, phi: f64, theta: f64) -> (Point, Unit<Vector>) {
+        let (sin_phi, cos_phi) = phi.sin_cos();
+        let (sin_theta, cos_theta) = theta.sin_cos();
+        let normal =
+            Vector::new(cos_theta * cos_phi, cos_theta * sin_phi, sin_theta);
+        let ring = Vector::new(self.major * cos_phi, self.major * sin_phi, 0.0);
+        let local = ring + self.minor * normal;
+        (
+            self.center + self.frame.localize(local),
+            Unit::new_unchecked(self.frame.localize(normal)),
+        )
+    }
+
+    /// Point chosen uniformly over the area of the torus, on which the
+    /// circles around the axis are longer the further out they are.
+    fn random_point(&self) -> (Point, Unit<Vector>, (f64, f64)) {
+        let theta = loop {
+            let theta = 2.0 * PI * random();
+            let length = self.major + self.minor * theta.cos();
+            if random() * (self.major + self.minor) < length {
+                break theta;
+            }
+        };
+        let u = random();
+        let (point, normal) = self.point(2.0 * PI * u, theta);
+        (point, normal, (u, theta / (2.0 * PI)))
+    }
+}
+
+impl Hittable for Torus {
+    fn is_light(&self) -> bool {
+        matches!(self.material.as_ref(), Material::Emission(..))
+    }
+
+    fn bounding_box(&self) -> AABB {
+        let w = self.frame.w;
+        let extent =
+            w.map(|n| self.major * (1.0 - n * n).max(0.0).sqrt() + self.minor);
+        AABB::new(self.center - extent, self.center + extent)
+    }
+
+    fn hit(&self, ray: Ray, range: Range<f64>) -> Option<Hit> {
+        let mut o = self.frame.project(ray.origin - self.center);
+        let d = self.frame.project(ray.dir);
+
+        // Only search along the part of the ray inside a slightly larger
+        // bounding sphere, starting from where it enters it to keep the
+        // quartic well conditioned.
+        let radius = 1.001 * (self.major + self.minor);
+        let (a, half_b) = (d.norm_squared(), o.dot(&d));
+        let c = o.norm_squared() - radius * radius;
+        let discriminant = half_b * half_b - a * c;
+        if discriminant < 0.0 {
+            return None;
+        }
+        let sqrt_disc = discriminant.sqrt();
+        let start = f64::max(range.start, (-half_b - sqrt_disc) / a);
+        let end = f64::min(range.end, (-half_b + sqrt_disc) / a);
+        if start >= end {
+            return None;
+        }
+        o += start * d;
+        // Roots are sought after `range.start`, which may be before the ray
+        // enters the sphere.
+        let lo = range.start - start;
+
+        // Substitute the ray into (|p|^2 + R^2 - r^2)^2 = 4 R^2 (x^2 + y^2).
+        let (r2, big_r2) = (self.minor * self.minor, self.major * self.major);
+        let (dd, od) = (d.norm_squared(), o.dot(&d));
+        let k = o.norm_squared() + big_r2 - r2;
+        let coeffs = [
+            k * k - 4.0 * big_r2 * (o.x * o.x + o.y * o.y),
+            4.0 * od * k - 8.0 * big_r2 * (o.x * d.x + o.y * d.y),
+            2.0 * dd * k + 4.0 * od * od
+                - 4.0 * big_r2 * (d.x * d.x + d.y * d.y),
+            4.0 * dd * od,
+            dd * dd,
+        ];
+        let s = first_root(&coeffs, lo, end - start)?;
+        let t = start + s;
+
+        let local = o + s * d;
+        let phi = local.y.atan2(local.x);
+        let ring = local.x.hypot(local.y) - self.major;
+        let theta = local.z.atan2(ring);
+        let wrap = |angle: f64| {
+            if angle < 0.0 {
+                angle / (2.0 * PI) + 1.0
+            } else {
+                angle / (2.0 * PI)
+            }
+        };
+        let (_, normal) = self.point(phi, theta);
+        let (sin_phi, cos_phi) = phi.sin_cos();
+        let (sin_theta, cos_theta) = theta.sin_cos();
+        let dpdu = 2.0 * PI * Vector::new(-local.y, local.x, 0.0);
+        let dpdv = 2.0
+            * PI
+            * self.minor
+            * Vector::new(
+                -sin_theta * cos_phi,
+                -sin_theta * sin_phi,
+                cos_theta,
+            );
+        let uv = (wrap(phi), wrap(theta));
+        Some(Hit::new(ray, normal, t, &self.material, uv).with_partials(
+            ray,
+            self.frame.localize(dpdu),
+            self.frame.localize(dpdv),
+        ))
+    }
+
+    fn pdf(&self, ray: Ray) -> f64 {
+        area_pdf(self, ray, self.area())
+    }
+
+    fn random(&self, origin: Point, _time: f64) -> Vector {
+        self.random_point().0 - origin
+    }
+
+    fn emit(&self, _time: f64) -> (Ray, Unit<Vector>, Color) {
+        let (point, normal, uv) = self.random_point();
+        emit_from(&self.material, point, normal, uv)
+    }
+
+    fn power(&self) -> f64 {
+        self.material.emitted_power(self.area())
+    }
+}
+
+/// Value of the polynomial with coefficients `coeffs`, lowest order first.
+fn eval(coeffs: &[f64], x: f64) -> f64 {
+    coeffs.iter().rev().fold(0.0, |sum, c| sum * x + c)
+}
+
+/// Smallest root of a polynomial within `(lo, hi]`. The roots of its
+/// derivative split the interval into pieces on which it is monotonic, each
+/// of which holds a root only if its ends differ in sign.
+fn first_root(coeffs: &[f64], lo: f64, hi: f64) -> Option<f64> {
+    if coeffs.len() <= 2 {
+        let root = -coeffs[0] / coeffs.get(1).copied().unwrap_or(0.0);
+        return if root > lo && root <= hi {
+            Some(root)
+        } else {
+            None
+        };
+    }
+    let derivative: Vec<f64> = coeffs
+        .iter()
+        .enumerate()
+        .skip(1)
+        .map(|(i, c)| i as f64 * c)
+        .collect();
+    let mut x0 = lo;
+    loop {
+        let x1 = first_root(&derivative, x0, hi).unwrap_or(hi);
+        if let Some(root) = bisect(coeffs, x0, x1) {
+            return Some(root);
+        }
+        if x1 >= hi {
+            return None;
+        }
+        x0 = x1;
+    }
+}
+
+/// Root of a monotonic polynomial within `(lo, hi]`, if it has one.
+fn bisect(coeffs: &[f64], mut lo: f64, mut hi: f64) -> Option<f64> {
+    let (f_lo, f_hi) = (eval(coeffs, lo), eval(coeffs, hi));
+    if f_lo == 0.0 || f_lo.signum() == f_hi.signum() {
+        return None;
+    }
+    let rising = f_hi > f_lo;
+    for _ in 0..64 {
+        let mid = 0.5 * (lo + hi);
+        if (eval(coeffs, mid) > 0.0) == rising {
+            hi = mid;
+        } else {
+            lo = mid;
+        }
+    }
+    Some(hi)
+}
+
+#[cfg(test)]
+#[test]
+fn rays_through_the_hole_miss_the_torus() {
+    let material = Arc::new(Material::Metal(Color::repeat(0.5), 0.0));
+    let torus =
+        Torus::new(material, Point::origin(), Vector::y_axis(), 2.0, 0.5);
+    let range = 0.0001..f64::INFINITY;
+
+    // Down through the hole, then through the tube from above and sideways.
+    let ray = Ray::new(Point::new(0.0, 5.0, 0.0), -Vector::y());
+    assert!(torus.hit(ray, range.clone()).is_none());
+    let ray = Ray::new(Point::new(2.0, 5.0, 0.0), -Vector::y());
+    let hit = torus.hit(ray, range.clone()).unwrap();
+    assert!((hit.t - 4.5).abs() < 1e-9);
+    assert!((*hit.normal - Vector::y()).norm() < 1e-9);
+    let ray = Ray::new(Point::new(-5.0, 0.0, 0.0), Vector::x());
+    let hit = torus.hit(ray, range.clone()).unwrap();
+    assert!((hit.t - 2.5).abs() < 1e-9);
+    let hit = torus.hit(ray, hit.t + 0.01..f64::INFINITY).unwrap();
+    assert!((hit.t - 3.5).abs() < 1e-9);
+    assert!(!hit.front_facing);
+
+    // From inside the tube, the ray leaves it before reaching the far side.
+    let ray = Ray::new(Point::new(2.0, 0.0, 0.0), Vector::x());
+    assert!((torus.hit(ray, range).unwrap().t - 0.5).abs() < 1e-9);
+}
@@ -1,5 +1,5 @@
 use super::*;
-use nalgebra::{Rotation3, UnitQuaternion};
+use nalgebra::{Matrix3, Matrix4, Rotation3, UnitQuaternion, U3};
 use std::ops::Range;
 
 pub struct Translate {
@@ -27,17 +27,36 @@ impl Hittable for Translate {
     }
 
     fn hit(&self, ray: Ray, range: Range<f64>) -> Option<Hit> {
-        let moved_ray = Ray {
-            origin: ray.origin - self.offset,
-            dir: ray.dir,
-        };
+        let moved_ray = ray.map(|p| p - self.offset, |v| *v);
         match self.target.hit(moved_ray, range) {
             None => None,
             Some(hit) => {
-                Some(Hit::new(ray, hit.normal, hit.t, hit.material, hit.uv))
+                let mut result =
+                    Hit::new(ray, hit.normal, hit.t, hit.material, hit.uv);
+                result.front_facing = hit.front_facing;
+                result.duv = hit.duv;
+                result.tangent = hit.tangent;
+                Some(result)
             }
         }
     }
+
+    fn pdf(&self, ray: Ray) -> f64 {
+        self.target.pdf(ray.map(|p| p - self.offset, |v| *v))
+    }
+
+    fn random(&self, origin: Point, time: f64) -> Vector {
+        self.target.random(origin - self.offset, time)
+    }
+
+    fn emit(&self, time: f64) -> (Ray, Unit<Vector>, Color) {
+        let (ray, normal, color) = self.target.emit(time);
+        (ray.map(|p| p + self.offset, |v| *v), normal, color)
+    }
+
+    fn power(&self) -> f64 {
+        self.target.power()
+    }
 }
 
 pub struct Rotate {
@@ -92,19 +111,392 @@ impl Hittable for Rotate {
     }
 
     fn hit(&self, ray: Ray, range: Range<f64>) -> Option<Hit> {
-        let rotated_ray = Ray {
-            origin: self.offset.inverse_transform_point(&ray.origin),
-            dir: self.offset.inverse_transform_vector(&ray.dir),
-        };
+        let rotated_ray = ray.map(
+            |p| self.offset.inverse_transform_point(p),
+            |v| self.offset.inverse_transform_vector(v),
+        );
         match self.target.hit(rotated_ray, range) {
             None => None,
-            Some(hit) => Some(Hit::new(
-                ray,
-                Unit::new_unchecked(self.offset.transform_vector(&*hit.normal)),
-                hit.t,
-                hit.material,
-                hit.uv,
-            )),
+            Some(hit) => {
+                let mut result = Hit::new(
+                    ray,
+                    Unit::new_unchecked(
+                        self.offset.transform_vector(&*hit.normal),
+                    ),
+                    hit.t,
+                    hit.material,
+                    hit.uv,
+                );
+                result.front_facing = hit.front_facing;
+                result.duv = hit.duv;
+                result.tangent = hit.tangent.map(|t| {
+                    Unit::new_unchecked(self.offset.transform_vector(&t))
+                });
+                Some(result)
+            }
         }
     }
+
+    fn pdf(&self, ray: Ray) -> f64 {
+        self.target.pdf(ray.map(
+            |p| self.offset.inverse_transform_point(p),
+            |v| self.offset.inverse_transform_vector(v),
+        ))
+    }
+
+    fn random(&self, origin: Point, time: f64) -> Vector {
+        let local = self.offset.inverse_transform_point(&origin);
+        self.offset
+            .transform_vector(&self.target.random(local, time))
+    }
+
+    fn emit(&self, time: f64) -> (Ray, Unit<Vector>, Color) {
+        let (ray, normal, color) = self.target.emit(time);
+        let ray = ray.map(
+            |p| self.offset.transform_point(p),
+            |v| self.offset.transform_vector(v),
+        );
+        (ray, self.offset * normal, color)
+    }
+
+    fn power(&self) -> f64 {
+        self.target.power()
+    }
+}
+
+/// Object under an invertible affine transform, which may scale it unevenly
+/// or shear it. Normals are transformed by the inverse transpose.
+pub struct Affine {
+    target: Arc<dyn Hittable>,
+    /// Transform from the target's space to the scene, and its inverse.
+    matrix: Matrix4<f64>,
+    inverse: Matrix4<f64>,
+    /// Linear part of `matrix`, and the inverse transpose of it.
+    linear: Matrix3<f64>,
+    normal: Matrix3<f64>,
+}
+
+impl Affine {
+    /// Transforms `target` by `matrix`, which must be invertible with a last
+    /// row of (0, 0, 0, 1).
+    pub(super) fn new(
+        target: Arc<dyn Hittable>,
+        matrix: Matrix4<f64>,
+    ) -> Arc<Affine> {
+        let inverse = matrix.try_inverse().expect("singular transform");
+        let linear: Matrix3<f64> = matrix.fixed_slice::<U3, U3>(0, 0).into();
+        let normal = inverse.fixed_slice::<U3, U3>(0, 0).transpose();
+        Arc::new(Affine {
+            target,
+            matrix,
+            inverse,
+            linear,
+            normal,
+        })
+    }
+
+    fn to_local(&self, ray: Ray) -> Ray {
+        ray.map(
+            |p| self.inverse.transform_point(p),
+            |v| self.inverse.transform_vector(v),
+        )
+    }
+}
+
+impl Hittable for Affine {
+    fn is_light(&self) -> bool {
+        self.target.is_light()
+    }
+
+    fn bounding_box(&self) -> AABB {
+        let aabb = self.target.bounding_box();
+        let mut min = Point::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+        let mut max =
+            Point::new(-f64::INFINITY, -f64::INFINITY, -f64::INFINITY);
+        for i in 0..8 {
+            let corner = Point::new(
+                if i & 1 == 0 { aabb.min.x } else { aabb.max.x },
+                if i & 2 == 0 { aabb.min.y } else { aabb.max.y },
+                if i & 4 == 0 { aabb.min.z } else { aabb.max.z },
+            );
+            let p = self.matrix.transform_point(&corner);
+            min = min.inf(&p);
+            max = max.sup(&p);
+        }
+        AABB::new(min, max)
+    }
+
+    fn hit(&self, ray: Ray, range: Range<f64>) -> Option<Hit> {
+        // Directions are not normalized, so distances along the ray are the
+        // same in both spaces.
+        let hit = self.target.hit(self.to_local(ray), range)?;
+        let mut result = Hit::new(
+            ray,
+            Unit::new_normalize(self.normal * *hit.normal),
+            hit.t,
+            hit.material,
+            hit.uv,
+        );
+        result.front_facing = hit.front_facing;
+        result.duv = hit.duv;
+        result.tangent =
+            hit.tangent.map(|t| Unit::new_normalize(self.linear * *t));
+        Some(result)
+    }
+
+    fn pdf(&self, ray: Ray) -> f64 {
+        let mut local = self.to_local(ray);
+        local.dir = local.dir.normalize();
+        // The transform stretches solid angles around a direction w by
+        // |det A| / |A w|^3.
+        let stretch = (self.linear * local.dir).norm().powi(3)
+            / self.linear.determinant().abs();
+        self.target.pdf(local) * stretch
+    }
+
+    fn random(&self, origin: Point, time: f64) -> Vector {
+        let local = self.inverse.transform_point(&origin);
+        self.linear * self.target.random(local, time)
+    }
+
+    fn emit(&self, time: f64) -> (Ray, Unit<Vector>, Color) {
+        let (ray, normal, color) = self.target.emit(time);
+        let ray = ray.map(
+            |p| self.matrix.transform_point(p),
+            |v| self.matrix.transform_vector(v),
+        );
+        (ray, Unit::new_normalize(self.normal * *normal), color)
+    }
+
+    fn power(&self) -> f64 {
+        // Areas grow by roughly the square of the change in length.
+        self.target.power() * self.linear.determinant().abs().powf(2.0 / 3.0)
+    }
+}
+
+/// Placement of an animated object at a given time.
+#[derive(Debug, Copy, Clone)]
+pub struct Keyframe {
+    pub time: f64,
+    pub translate: Vector,
+    pub rotate: UnitQuaternion<f64>,
+}
+
+/// Object which rotates and then translates along a path through
+/// keyframes, interpolated linearly and held at the first and last ones.
+pub struct Animated {
+    target: Arc<dyn Hittable>,
+    /// Keyframes in increasing order of time.
+    keyframes: Vec<Keyframe>,
+}
+
+impl Animated {
+    pub(super) fn new(
+        target: Arc<dyn Hittable>,
+        mut keyframes: Vec<Keyframe>,
+    ) -> Arc<Animated> {
+        assert!(!keyframes.is_empty(), "animation requires keyframes");
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Arc::new(Animated { target, keyframes })
+    }
+
+    fn at(&self, time: f64) -> (Vector, UnitQuaternion<f64>) {
+        let i = self.keyframes.iter().filter(|k| k.time <= time).count();
+        if i == 0 || i == self.keyframes.len() {
+            let k = self.keyframes[i.saturating_sub(1)];
+            return (k.translate, k.rotate);
+        }
+        let (a, b) = (self.keyframes[i - 1], self.keyframes[i]);
+        let s = (time - a.time) / (b.time - a.time);
+        let rotate = a.rotate.try_slerp(&b.rotate, s, 1e-9).unwrap_or(a.rotate);
+        (a.translate.lerp(&b.translate, s), rotate)
+    }
+}
+
+impl Hittable for Animated {
+    fn is_light(&self) -> bool {
+        self.target.is_light()
+    }
+
+    fn bounding_box(&self) -> AABB {
+        // Bound the target by a sphere, whose box only depends on where its
+        // center goes, and follow the center through each segment. Between
+        // steps it strays from a straight line by at most the sagitta of
+        // the arc it sweeps.
+        const STEPS: usize = 16;
+        let aabb = self.target.bounding_box();
+        let center = nalgebra::center(&aabb.min, &aabb.max);
+        let radius = (aabb.max - center).norm();
+        let mut min = Point::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+        let mut max =
+            Point::new(-f64::INFINITY, -f64::INFINITY, -f64::INFINITY);
+        let mut pad: f64 = 0.0;
+        for pair in self.keyframes.windows(2) {
+            let angle = pair[0].rotate.angle_to(&pair[1].rotate);
+            let step = angle / STEPS as f64;
+            pad = pad.max(center.coords.norm() * (1.0 - (step / 2.0).cos()));
+        }
+        let (start, end) =
+            (self.keyframes[0].time, self.keyframes.last().unwrap().time);
+        let samples = STEPS * self.keyframes.len();
+        for i in 0..=samples {
+            let time = start + (end - start) * i as f64 / samples as f64;
+            let (translate, rotate) = self.at(time);
+            let p = rotate * center + translate;
+            min = min.inf(&p);
+            max = max.sup(&p);
+        }
+        let r = Vector::repeat(radius + pad);
+        AABB::new(min - r, max + r)
+    }
+
+    fn hit(&self, ray: Ray, range: Range<f64>) -> Option<Hit> {
+        let (translate, rotate) = self.at(ray.time);
+        let local_ray = ray.map(
+            |p| rotate.inverse_transform_point(&(p - translate)),
+            |v| rotate.inverse_transform_vector(v),
+        );
+        let hit = self.target.hit(local_ray, range)?;
+        let mut result = Hit::new(
+            ray,
+            Unit::new_unchecked(rotate.transform_vector(&*hit.normal)),
+            hit.t,
+            hit.material,
+            hit.uv,
+        );
+        result.front_facing = hit.front_facing;
+        result.duv = hit.duv;
+        result.tangent = hit
+            .tangent
+            .map(|t| Unit::new_unchecked(rotate.transform_vector(&t)));
+        Some(result)
+    }
+
+    fn pdf(&self, ray: Ray) -> f64 {
+        let (translate, rotate) = self.at(ray.time);
+        self.target.pdf(ray.map(
+            |p| rotate.inverse_transform_point(&(p - translate)),
+            |v| rotate.inverse_transform_vector(v),
+        ))
+    }
+
+    fn random(&self, origin: Point, time: f64) -> Vector {
+        let (translate, rotate) = self.at(time);
+        let local = rotate.inverse_transform_point(&(origin - translate));
+        rotate.transform_vector(&self.target.random(local, time))
+    }
+
+    fn emit(&self, time: f64) -> (Ray, Unit<Vector>, Color) {
+        let (translate, rotate) = self.at(time);
+        let (ray, normal, color) = self.target.emit(time);
+        let ray = ray.map(
+            |p| rotate.transform_point(p) + translate,
+            |v| rotate.transform_vector(v),
+        );
+        (ray, rotate * normal, color)
+    }
+
+    fn power(&self) -> f64 {
+        self.target.power()
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn animated_objects_stay_inside_their_bounds() {
+    use std::f64::consts::PI;
+    let material = Arc::new(Material::Metal(Color::repeat(0.5), 0.0));
+    let sphere = Sphere::new(material, Point::new(2.0, 0.0, 0.0), 0.5);
+    let keyframes = vec![
+        Keyframe {
+            time: 0.0,
+            translate: Vector::zeros(),
+            rotate: UnitQuaternion::identity(),
+        },
+        Keyframe {
+            time: 1.0,
+            translate: Vector::new(0.0, 3.0, 0.0),
+            rotate: UnitQuaternion::from_scaled_axis(Vector::y() * PI),
+        },
+    ];
+    let animated = Animated::new(sphere, keyframes);
+    let aabb = animated.bounding_box();
+    for i in 0..=100 {
+        let time = i as f64 / 100.0;
+        let (translate, rotate) = animated.at(time);
+        let center = rotate * Point::new(2.0, 0.0, 0.0) + translate;
+        for c in 0..3 {
+            assert!(center[c] - 0.5 >= aabb.min[c] - 1e-9);
+            assert!(center[c] + 0.5 <= aabb.max[c] + 1e-9);
+        }
+        // Rays at this time find the sphere where it has moved to.
+        let mut ray = Ray::new(center - 5.0 * Vector::z(), Vector::z());
+        ray.time = time;
+        let hit = animated.hit(ray, 0.0001..f64::INFINITY).unwrap();
+        assert!((hit.t - 4.5).abs() < 1e-9);
+        // So do directions sampled towards it as a light.
+        assert!(animated.pdf(ray) > 0.0);
+        let mut sampled =
+            Ray::new(ray.origin, animated.random(ray.origin, time));
+        sampled.time = time;
+        assert!(animated.hit(sampled, 0.0001..f64::INFINITY).is_some());
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn affine_transforms_stretch_normals_and_solid_angles() {
+    let material = Arc::new(Material::Metal(Color::repeat(0.5), 0.0));
+    let sphere = Sphere::new(material, Point::origin(), 1.0);
+    let scale = Matrix4::new_nonuniform_scaling(&Vector::new(2.0, 1.0, 1.0));
+    let ellipsoid =
+        Affine::new(sphere, Matrix4::new_translation(&Vector::z()) * scale);
+
+    let ray = Ray::new(Point::new(-5.0, 0.0, 1.0), Vector::x());
+    let hit = ellipsoid.hit(ray, 0.0001..f64::INFINITY).unwrap();
+    assert!((hit.t - 3.0).abs() < 1e-9);
+    // Normals follow the gradient of x^2 / 4 + y^2 + (z - 1)^2.
+    let dir = Vector::new(1.0, 1.0, 0.0).normalize();
+    let ray = Ray::new(Point::new(0.0, 0.0, 1.0), dir);
+    let hit = ellipsoid.hit(ray, 0.0001..f64::INFINITY).unwrap();
+    let p = hit.point - Vector::z();
+    let gradient = Vector::new(p.x / 4.0, p.y, p.z).normalize();
+    assert!((*hit.normal + gradient).norm() < 1e-9);
+
+    // The mean reciprocal density of directions sampled towards the
+    // ellipsoid is the solid angle it covers, found by counting the rays
+    // which hit it on a grid of directions.
+    let origin = Point::new(0.0, 0.0, -4.0);
+    let n = 400;
+    let mut hits = 0;
+    for i in 0..n {
+        for j in 0..n {
+            let z = 1.0 - 2.0 * (i as f64 + 0.5) / n as f64;
+            let phi = 2.0 * std::f64::consts::PI * (j as f64 + 0.5) / n as f64;
+            let r = (1.0 - z * z).sqrt();
+            let dir = Vector::new(r * phi.cos(), r * phi.sin(), z);
+            if ellipsoid.hit(Ray::new(origin, dir), 0.0001..10.0).is_some() {
+                hits += 1;
+            }
+        }
+    }
+    let solid_angle = 4.0 * std::f64::consts::PI * hits as f64 / (n * n) as f64;
+    let samples = 100000;
+    let total: f64 = (0..samples)
+        .map(|_| {
+            let ray = Ray::new(origin, ellipsoid.random(origin, 0.0));
+            ellipsoid.pdf(ray).recip()
+        })
+        .sum();
+    let estimate = total / samples as f64;
+    assert!(
+        (estimate / solid_angle - 1.0).abs() < 0.02,
+        "{} != {}",
+        estimate,
+        solid_angle
+    );
+    let dir = ellipsoid.random(origin, 0.0);
+    assert!(ellipsoid
+        .hit(Ray::new(origin, dir), 0.0001..f64::INFINITY)
+        .is_some());
 }
@@ -0,0 +1,366 @@
+use crate::color::{luminance, Color};
+use crate::geometry::{Hit, Ray, Vector, ONB};
+use crate::material::Scatter;
+use crate::pdf::PDF;
+use crate::util::*;
+use nalgebra::Unit;
+use std::f64::consts::PI;
+
+/// Number of lobes followed through the fibre explicitly: reflection off its
+/// surface (R), transmission through it (TT), and reflection off its far side
+/// (TRT). Light leaving after more internal reflections is gathered into one
+/// more lobe, spread evenly around the fibre.
+const P_MAX: usize = 3;
+
+/// Scattering from hair and fur, modelled as rough dielectric cylinders with
+/// tilted cuticle scales around a pigmented interior, after "A Practical and
+/// Controllable Hair and Fur Model for Production Path Tracing", Chiang et
+/// al, 2016. Each lobe is separable into a longitudinal part, along the
+/// fibre, and an azimuthal part, around it.
+#[derive(Debug, Copy, Clone)]
+pub struct Hair {
+    /// Absorption coefficient of the interior per unit of fibre radius.
+    absorption: Color,
+    ior: f64,
+    /// Variance of the longitudinal scattering of each lobe.
+    v: [f64; P_MAX + 1],
+    /// Scale of the logistic distribution of azimuthal scattering.
+    s: f64,
+    /// Sine and cosine of the tilt of the scales, and of twice and four
+    /// times it.
+    tilt: [(f64, f64); 3],
+}
+
+impl Hair {
+    /// Creates a fibre with the given longitudinal and azimuthal roughness,
+    /// each from 0 to 1, whose scales tilt by `alpha` degrees towards its
+    /// root.
+    pub fn new(
+        absorption: Color,
+        ior: f64,
+        beta_m: f64,
+        beta_n: f64,
+        alpha: f64,
+    ) -> Hair {
+        // Roughnesses are mapped to variances so that they look roughly
+        // linear, using the fits in the paper.
+        let v0 =
+            (0.726 * beta_m + 0.812 * beta_m.powi(2) + 3.7 * beta_m.powi(20))
+                .powi(2);
+        let s = (PI / 8.0).sqrt()
+            * (0.265 * beta_n
+                + 1.194 * beta_n.powi(2)
+                + 5.372 * beta_n.powi(22));
+        let sin = alpha.to_radians().sin();
+        let mut tilt = [(sin, (1.0 - sin * sin).sqrt()); 3];
+        for i in 1..3 {
+            let (sin, cos) = tilt[i - 1];
+            tilt[i] = (2.0 * sin * cos, cos * cos - sin * sin);
+        }
+        Hair {
+            absorption,
+            ior,
+            v: [v0, 0.25 * v0, 4.0 * v0, 4.0 * v0],
+            s,
+            tilt,
+        }
+    }
+
+    /// Absorption of a fibre with the given concentrations of the dark
+    /// eumelanin and the reddish pheomelanin, which range from about 0 for
+    /// white hair to 8 for black hair.
+    pub fn melanin(eumelanin: f64, pheomelanin: f64) -> Color {
+        eumelanin * Color::new(0.419, 0.697, 1.37)
+            + pheomelanin * Color::new(0.187, 0.4, 1.05)
+    }
+
+    /// Absorption which gives hair of the given azimuthal roughness roughly
+    /// `color` after multiple scattering between many fibres.
+    pub fn absorption_for(color: Color, beta_n: f64) -> Color {
+        let d = 5.969 - 0.215 * beta_n + 2.532 * beta_n.powi(2)
+            - 10.73 * beta_n.powi(3)
+            + 5.574 * beta_n.powi(4)
+            + 0.245 * beta_n.powi(5);
+        color.map(|c| (c.max(1e-4).ln() / d).powi(2))
+    }
+
+    /// Chooses a lobe at `hit` by its share of the light scattered back
+    /// along `inbound`, and weights it to compensate.
+    pub fn scatter(&self, inbound: &Ray, hit: &Hit) -> Scatter<'static> {
+        let wo = -inbound.dir.normalize();
+        // Surfaces other than curves have no direction along them, so the
+        // fibres run across their normals in an arbitrary direction.
+        let tangent = hit.tangent.unwrap_or_else(|| ONB::from_w(hit.normal).u);
+        // Curves are hit at an offset from -1 to 1 across their width.
+        let h = fclamp(2.0 * hit.uv.1 - 1.0, -1.0, 1.0);
+        let lobes = self.lobes(wo, tangent, h);
+        let weights: Vec<f64> =
+            lobes.iter().map(|(a, _)| luminance(a)).collect();
+        let total: f64 = weights.iter().sum();
+        let mut choice = random() * total;
+        let mut p = 0;
+        while p < P_MAX && choice >= weights[p] {
+            choice -= weights[p];
+            p += 1;
+        }
+        let (attenuation, lobe) = lobes[p];
+        Scatter {
+            specular: None,
+            pdf: Some(PDF::Hair(lobe)),
+            attenuation: attenuation * total / weights[p],
+        }
+    }
+
+    /// Fraction of the light leaving the fibre towards `wo` in each lobe,
+    /// and the distribution of directions from which it arrives, for a
+    /// fibre along `tangent` seen at offset `h`.
+    fn lobes(
+        &self,
+        wo: Vector,
+        tangent: Unit<Vector>,
+        h: f64,
+    ) -> [(Color, HairLobe); P_MAX + 1] {
+        // Azimuths are measured around the fibre from its `v` axis, so that
+        // `wo` is always at a right angle to it.
+        let w = Unit::try_new(wo - wo.dot(&tangent) * *tangent, 1e-9)
+            .unwrap_or_else(|| ONB::from_w(tangent).u);
+        let frame = ONB {
+            u: tangent,
+            v: Unit::new_unchecked(w.cross(&tangent)),
+            w,
+        };
+        let sin_theta_o = fclamp(wo.dot(&tangent), -1.0, 1.0);
+        let cos_theta_o = (1.0 - sin_theta_o * sin_theta_o).max(1e-9).sqrt();
+
+        // Refraction into the fibre, projected onto the plane across it.
+        let gamma_o = h.asin();
+        let sin_theta_t = sin_theta_o / self.ior;
+        let cos_theta_t = (1.0 - sin_theta_t * sin_theta_t).sqrt();
+        let eta = (self.ior * self.ior - sin_theta_o * sin_theta_o).sqrt()
+            / cos_theta_o;
+        let sin_gamma_t = fclamp(h / eta, -1.0, 1.0);
+        let gamma_t = sin_gamma_t.asin();
+        let cos_gamma_t = (1.0 - sin_gamma_t * sin_gamma_t).sqrt();
+        let crossing = 2.0 * cos_gamma_t / cos_theta_t;
+        let transmittance = self.absorption.map(|a| (-a * crossing).exp());
+
+        let f = fresnel(cos_theta_o * (1.0 - h * h).sqrt(), self.ior);
+        let mut attenuation = [Color::repeat(f); P_MAX + 1];
+        attenuation[1] = (1.0 - f).powi(2) * transmittance;
+        for p in 2..P_MAX {
+            attenuation[p] =
+                attenuation[p - 1].component_mul(&transmittance) * f;
+        }
+        // The rest of the light is a geometric series of reflections.
+        attenuation[P_MAX] = (attenuation[P_MAX - 1] * f)
+            .component_mul(&transmittance)
+            .component_div(&transmittance.map(|t| 1.0 - t * f));
+
+        let mut lobes = [(Color::zeros(), HairLobe::default()); P_MAX + 1];
+        for (p, lobe) in lobes.iter_mut().enumerate() {
+            // The scales shift the R lobe towards the tip of the fibre by
+            // twice their tilt, and the others towards the root.
+            let ((sin, cos), sign) = match p {
+                0 => (self.tilt[1], -1.0),
+                1 => (self.tilt[0], 1.0),
+                2 => (self.tilt[2], 1.0),
+                _ => ((0.0, 1.0), 0.0),
+            };
+            let centre =
+                2.0 * p as f64 * gamma_t - 2.0 * gamma_o + p as f64 * PI;
+            *lobe = (
+                attenuation[p],
+                HairLobe {
+                    frame,
+                    sin_theta_o: sin_theta_o * cos + sign * cos_theta_o * sin,
+                    cos_theta_o: (cos_theta_o * cos - sign * sin_theta_o * sin)
+                        .abs(),
+                    v: self.v[p],
+                    azimuth: if p < P_MAX { Some(centre) } else { None },
+                    s: self.s,
+                },
+            );
+        }
+        lobes
+    }
+}
+
+/// Distribution of the directions from which light arrives in one lobe of
+/// a hair fibre.
+#[derive(Debug, Copy, Clone)]
+pub struct HairLobe {
+    /// Frame with the fibre along `u` and the outbound direction in the
+    /// plane of `u` and `w`.
+    frame: ONB,
+    /// Longitudinal angle of the outbound direction, shifted by the scales.
+    sin_theta_o: f64,
+    cos_theta_o: f64,
+    v: f64,
+    /// Azimuth around which the lobe is centred, relative to the outbound
+    /// direction, or `None` if it is uniform.
+    azimuth: Option<f64>,
+    s: f64,
+}
+
+impl Default for HairLobe {
+    fn default() -> HairLobe {
+        HairLobe {
+            frame: ONB::from_w(Vector::z_axis()),
+            sin_theta_o: 0.0,
+            cos_theta_o: 1.0,
+            v: 1.0,
+            azimuth: None,
+            s: 1.0,
+        }
+    }
+}
+
+impl HairLobe {
+    pub fn value(&self, dir: Vector) -> f64 {
+        let wi = self.frame.project(dir.normalize());
+        let sin_theta_i = fclamp(wi.x, -1.0, 1.0);
+        let cos_theta_i = (1.0 - sin_theta_i * sin_theta_i).sqrt();
+        let m = longitudinal(
+            sin_theta_i,
+            cos_theta_i,
+            self.sin_theta_o,
+            self.cos_theta_o,
+            self.v,
+        );
+        let n = match self.azimuth {
+            Some(azimuth) => {
+                let phi = wi.z.atan2(wi.y) - PI / 2.0;
+                let offset = (phi - azimuth + PI).rem_euclid(2.0 * PI) - PI;
+                logistic(offset, self.s) / logistic_span(self.s)
+            }
+            None => 1.0 / (2.0 * PI),
+        };
+        m * n
+    }
+
+    pub fn gen(&self) -> Vector {
+        // The longitudinal lobe is the marginal of a von Mises-Fisher
+        // distribution around the mirrored outbound direction.
+        let u = random().max(1e-5);
+        let cos_theta =
+            1.0 + self.v * (u + (1.0 - u) * (-2.0 / self.v).exp()).ln();
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let cos_phi = (2.0 * PI * random()).cos();
+        let sin_theta_i = fclamp(
+            -cos_theta * self.sin_theta_o
+                + sin_theta * cos_phi * self.cos_theta_o,
+            -1.0,
+            1.0,
+        );
+        let cos_theta_i = (1.0 - sin_theta_i * sin_theta_i).sqrt();
+        let phi = match self.azimuth {
+            Some(azimuth) => {
+                // Invert the CDF of the logistic trimmed to [-pi, pi].
+                let low = logistic_cdf(-PI, self.s);
+                let cdf = random() * logistic_span(self.s) + low;
+                azimuth + fclamp(-self.s * (1.0 / cdf - 1.0).ln(), -PI, PI)
+            }
+            None => 2.0 * PI * random(),
+        } + PI / 2.0;
+        self.frame.localize(Vector::new(
+            sin_theta_i,
+            cos_theta_i * phi.cos(),
+            cos_theta_i * phi.sin(),
+        ))
+    }
+}
+
+/// Density of longitudinal scattering with variance `v`, per unit of
+/// `cos_theta_i` times the inbound angle.
+fn longitudinal(
+    sin_theta_i: f64,
+    cos_theta_i: f64,
+    sin_theta_o: f64,
+    cos_theta_o: f64,
+    v: f64,
+) -> f64 {
+    let a = cos_theta_i * cos_theta_o / v;
+    let b = sin_theta_i * sin_theta_o / v;
+    if v <= 0.1 {
+        // Narrow lobes overflow unless evaluated as logarithms.
+        (log_bessel_i0(a) - b - 1.0 / v + 2f64.ln() + (0.5 / v).ln()).exp()
+    } else {
+        (-b).exp() * bessel_i0(a) / ((1.0 / v).sinh() * 2.0 * v)
+    }
+}
+
+/// Modified Bessel function of the first kind of order 0.
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let x2 = x * x / 4.0;
+    for i in 1..10 {
+        term *= x2 / (i * i) as f64;
+        sum += term;
+    }
+    sum
+}
+
+fn log_bessel_i0(x: f64) -> f64 {
+    if x > 12.0 {
+        x + 0.5 * (-(2.0 * PI).ln() - x.ln() + 1.0 / (8.0 * x))
+    } else {
+        bessel_i0(x).ln()
+    }
+}
+
+fn logistic(x: f64, s: f64) -> f64 {
+    let e = (-x.abs() / s).exp();
+    e / (s * (1.0 + e) * (1.0 + e))
+}
+
+fn logistic_cdf(x: f64, s: f64) -> f64 {
+    1.0 / (1.0 + (-x / s).exp())
+}
+
+/// Probability of the logistic distribution within [-pi, pi].
+fn logistic_span(s: f64) -> f64 {
+    logistic_cdf(PI, s) - logistic_cdf(-PI, s)
+}
+
+/// Fresnel reflectance of unpolarized light arriving from air at a smooth
+/// dielectric of index `ior`.
+fn fresnel(cos_theta: f64, ior: f64) -> f64 {
+    let cos_i = fclamp(cos_theta, 0.0, 1.0);
+    let sin_t = (1.0 - cos_i * cos_i).sqrt() / ior;
+    if sin_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin_t * sin_t).sqrt();
+    let parallel = (ior * cos_i - cos_t) / (ior * cos_i + cos_t);
+    let perpendicular = (cos_i - ior * cos_t) / (cos_i + ior * cos_t);
+    (parallel * parallel + perpendicular * perpendicular) / 2.0
+}
+
+#[cfg(test)]
+#[test]
+fn clear_fibres_scatter_all_light_in_sampled_lobes() {
+    // Without absorption the lobes carry all the light between them, so
+    // sampling them evenly and weighting by the mixture of their densities
+    // integrates to one only if each lobe samples its own density.
+    let hair = Hair::new(Color::zeros(), 1.55, 0.3, 0.3, 2.0);
+    let tangent = Unit::new_normalize(Vector::new(0.2, 1.0, -0.1));
+    for &(theta, h) in &[(0.0f64, 0.0), (0.6, -0.7), (-1.2, 0.5), (0.3, 0.95)] {
+        let across = ONB::from_w(tangent).u;
+        let wo = theta.sin() * *tangent + f64::cos(theta) * *across;
+        let lobes = hair.lobes(wo, tangent, h);
+        let n = 20000;
+        let mut sum = 0.0;
+        for i in 0..n {
+            let dir = lobes[i % lobes.len()].1.gen();
+            let (f, pdf) =
+                lobes.iter().fold((0.0, 0.0), |(f, pdf), (a, lobe)| {
+                    let value = lobe.value(dir);
+                    (f + a.x * value, pdf + value / lobes.len() as f64)
+                });
+            sum += f / pdf;
+        }
+        let mean = sum / n as f64;
+        assert!((mean - 1.0).abs() < 0.02, "{} {}: {}", theta, h, mean);
+    }
+}
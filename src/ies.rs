@@ -0,0 +1,202 @@
+use crate::geometry::{Vector, ONB};
+use crate::util::fclamp;
+use nalgebra::Unit;
+use std::f64::consts::PI;
+use std::path::Path;
+
+/// Goniometric light distribution read from an IESNA LM-63 photometric file,
+/// using type C photometry: vertical angles are measured from the nadir of
+/// the luminaire and horizontal angles around it.
+pub struct IESProfile {
+    /// Vertical angles in degrees, ascending.
+    vertical: Vec<f64>,
+    /// Horizontal angles in degrees, ascending.
+    horizontal: Vec<f64>,
+    /// Candela values for each horizontal angle, then each vertical angle,
+    /// normalized to a peak of 1.
+    values: Vec<Vec<f64>>,
+}
+
+impl IESProfile {
+    pub fn load(path: &Path) -> Result<IESProfile, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|err| format!("{}: {}", path.display(), err))?;
+        IESProfile::parse(&text)
+            .map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    fn parse(text: &str) -> Result<IESProfile, String> {
+        // Everything before the TILT line is free-form keywords.
+        let tilt = text
+            .find("TILT=")
+            .ok_or_else(|| "missing TILT line".to_string())?;
+        let rest = &text[tilt..];
+        let (tilt_line, data) =
+            rest.split_at(rest.find('\n').unwrap_or(rest.len()));
+        if tilt_line.trim() != "TILT=NONE" {
+            return Err("only TILT=NONE is supported".into());
+        }
+        let mut numbers = data.split_whitespace().map(|s| {
+            s.trim_end_matches(',')
+                .parse::<f64>()
+                .map_err(|_| format!("invalid number `{}`", s))
+        });
+        let mut next = || -> Result<f64, String> {
+            numbers
+                .next()
+                .unwrap_or_else(|| Err("unexpected end of file".into()))
+        };
+
+        let _lamps = next()?;
+        let _lumens_per_lamp = next()?;
+        let multiplier = next()?;
+        let num_vertical = next()? as usize;
+        let num_horizontal = next()? as usize;
+        let photometric_type = next()? as u32;
+        let _units = next()?;
+        let _width = next()?;
+        let _length = next()?;
+        let _height = next()?;
+        let ballast = next()?;
+        let _future_use = next()?;
+        let _input_watts = next()?;
+        if photometric_type != 1 {
+            return Err("only type C photometry is supported".into());
+        }
+        if num_vertical == 0 || num_horizontal == 0 {
+            return Err("profile has no angles".into());
+        }
+
+        let vertical = (0..num_vertical)
+            .map(|_| next())
+            .collect::<Result<Vec<f64>, String>>()?;
+        let horizontal = (0..num_horizontal)
+            .map(|_| next())
+            .collect::<Result<Vec<f64>, String>>()?;
+        let mut values = vec![];
+        for _ in 0..num_horizontal {
+            let row = (0..num_vertical)
+                .map(|_| Ok(next()? * multiplier * ballast))
+                .collect::<Result<Vec<f64>, String>>()?;
+            values.push(row);
+        }
+
+        let peak = values.iter().flatten().cloned().fold(0.0, f64::max);
+        if peak <= 0.0 {
+            return Err("profile emits no light".into());
+        }
+        for v in values.iter_mut().flatten() {
+            *v /= peak;
+        }
+
+        Ok(IESProfile {
+            vertical,
+            horizontal,
+            values,
+        })
+    }
+
+    /// Relative intensity in [0, 1] emitted in direction `dir`, for a
+    /// luminaire whose nadir points along `frame.w`.
+    pub fn eval(&self, frame: &ONB, dir: Unit<Vector>) -> f64 {
+        let cos = dir.dot(&frame.w);
+        let vertical = fclamp(cos, -1.0, 1.0).acos().to_degrees();
+        let horizontal = dir.dot(&frame.v).atan2(dir.dot(&frame.u));
+        let horizontal = (horizontal.to_degrees() + 360.0) % 360.0;
+        let horizontal = self.fold_horizontal(horizontal);
+
+        let (h0, h1, ht) = bracket(&self.horizontal, horizontal);
+        let (v0, v1, vt) = bracket(&self.vertical, vertical);
+        let lerp = |row: &[f64]| (1.0 - vt) * row[v0] + vt * row[v1];
+        (1.0 - ht) * lerp(&self.values[h0]) + ht * lerp(&self.values[h1])
+    }
+
+    /// Maps a horizontal angle in [0, 360) into the range covered by the
+    /// file, which may only store one half or quadrant of a symmetric
+    /// distribution.
+    fn fold_horizontal(&self, angle: f64) -> f64 {
+        let last = *self.horizontal.last().unwrap();
+        if last <= 0.0 {
+            // Rotationally symmetric.
+            0.0
+        } else if last <= 90.0 {
+            // Symmetric in each quadrant.
+            let a = angle % 180.0;
+            if a > 90.0 {
+                180.0 - a
+            } else {
+                a
+            }
+        } else if last <= 180.0 {
+            // Symmetric about the 0-180 degree plane.
+            if angle > 180.0 {
+                360.0 - angle
+            } else {
+                angle
+            }
+        } else {
+            angle
+        }
+    }
+
+    /// Estimates the ratio of the total emitted flux to that of an isotropic
+    /// emitter at the peak intensity.
+    pub fn average(&self) -> f64 {
+        let frame = ONB::from_w(-Vector::y_axis());
+        const N: usize = 64;
+        let mut sum = 0.0;
+        for i in 0..N {
+            let z = 1.0 - 2.0 * (i as f64 + 0.5) / N as f64;
+            let r = (1.0 - z * z).sqrt();
+            for j in 0..N {
+                let phi = 2.0 * PI * (j as f64 + 0.5) / N as f64;
+                let dir = Unit::new_normalize(Vector::new(
+                    r * phi.cos(),
+                    z,
+                    r * phi.sin(),
+                ));
+                sum += self.eval(&frame, dir);
+            }
+        }
+        sum / (N * N) as f64
+    }
+}
+
+/// Finds the indices of the entries bracketing `x` in ascending `xs`, and the
+/// interpolation factor between them.
+fn bracket(xs: &[f64], x: f64) -> (usize, usize, f64) {
+    if x <= xs[0] {
+        return (0, 0, 0.0);
+    }
+    for i in 1..xs.len() {
+        if x <= xs[i] {
+            let t = (x - xs[i - 1]) / (xs[i] - xs[i - 1]);
+            return (i - 1, i, t);
+        }
+    }
+    let last = xs.len() - 1;
+    (last, last, 0.0)
+}
+
+#[cfg(test)]
+#[test]
+fn profiles_interpolate_and_mirror_symmetric_halves() {
+    let profile = IESProfile::parse(
+        "IESNA:LM-63-2002\n[TEST] half\nTILT=NONE\n\
+         1 -1 1 3 2 1 2 0 0 0 1 1 100\n\
+         0 45 90\n0 180\n\
+         100 50 0\n100 100 0\n",
+    )
+    .unwrap();
+    let frame = ONB::from_w(-Vector::y_axis());
+    let down = Unit::new_normalize(-Vector::y());
+    assert!((profile.eval(&frame, down) - 1.0).abs() < 1e-6);
+    // Horizontal angles of 90 and 270 degrees both fall halfway between the
+    // two measured planes.
+    let side = |axis: Vector| {
+        profile.eval(&frame, Unit::new_normalize(*frame.w + axis))
+    };
+    assert!((side(*frame.u) - 0.5).abs() < 1e-6);
+    assert!((side(*frame.v) - 0.75).abs() < 1e-6);
+    assert!((side(-*frame.v) - 0.75).abs() < 1e-6);
+}
@@ -4,11 +4,15 @@ use structopt::StructOpt;
 mod bdpt;
 mod camera;
 mod config;
+mod distribution;
 mod geometry;
+mod hair;
+mod ies;
 mod material;
 mod pdf;
 mod raytrace;
 mod renderer;
+mod spectrum;
 mod texture;
 mod udpt;
 #[allow(dead_code)]
@@ -17,6 +21,11 @@ mod util;
 mod color {
     use nalgebra::Vector3;
     pub type Color = Vector3<f64>;
+
+    /// Relative luminance of a linear Rec. 709 color.
+    pub fn luminance(c: &Color) -> f64 {
+        0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
+    }
 }
 
 fn main() {
@@ -1,9 +1,13 @@
-use crate::color::Color;
-use crate::geometry::{Hit, Ray};
+use crate::color::{luminance, Color};
+use crate::geometry::{Hit, Ray, Vector, Volume, ONB};
+use crate::hair::Hair;
+use crate::ies::IESProfile;
 use crate::pdf::PDF;
 use crate::texture::Texture;
 use crate::util::*;
-use nalgebra::Unit;
+use nalgebra::{Matrix4, Unit};
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
 use std::sync::Arc;
 
 #[derive(Clone)]
@@ -11,8 +15,258 @@ pub enum Material {
     Lambertian(Arc<Texture>),
     Phong(f64, Arc<Texture>, Arc<Texture>, u32),
     Metal(Color, f64),
-    Dielectric(f64),
-    Emission(Arc<Texture>),
+    Dielectric(IOR),
+    Emission(Arc<Texture>, Emitter),
+    /// The interior of a participating medium, hit where a ray collides
+    /// with it.
+    Medium(Medium),
+    /// A medium whose density and emission vary through a voxel grid.
+    Volume(Arc<Volume>),
+    /// The smooth dielectric surface of an object whose interior scatters
+    /// light beneath it.
+    Subsurface(Subsurface),
+    /// The interior of a subsurface scattering object, hit where a ray
+    /// starting inside it collides with its medium.
+    Interior(Medium),
+    /// Fibres of hair or fur, best applied to curves.
+    Hair(Hair),
+}
+
+/// Coefficients of a homogeneous participating medium per unit length, with
+/// the asymmetry `g` of its Henyey-Greenstein phase function.
+#[derive(Debug, Copy, Clone)]
+pub struct Medium {
+    pub absorption: Color,
+    pub scattering: Color,
+    pub g: f64,
+}
+
+impl Medium {
+    pub fn extinction(&self) -> Color {
+        self.absorption + self.scattering
+    }
+
+    /// Extinction coefficient of the densest channel, with which collisions
+    /// are sampled.
+    pub fn majorant(&self) -> f64 {
+        self.extinction().max()
+    }
+
+    /// Fraction of the collisions sampled with the majorant which are null
+    /// collisions in each channel.
+    pub fn null_fraction(&self) -> Color {
+        Color::repeat(1.0) - self.extinction() / self.majorant()
+    }
+
+    /// Fraction of light in each channel which travels `distance` through
+    /// the medium without colliding.
+    pub fn transmittance(&self, distance: f64) -> Color {
+        self.extinction().map(|sigma| (-sigma * distance).exp())
+    }
+
+    /// Samples the distance to a collision using the extinction of a
+    /// channel chosen at random, as in "Path Tracing in Production", Fong
+    /// et al, 2017. Unlike sampling with the majorant, this keeps the
+    /// weights of media with very different extinction in each channel
+    /// from varying wildly between paths.
+    pub fn sample_distance(&self) -> f64 {
+        let channel = ((random() * 3.0) as usize).min(2);
+        -(1.0 - random()).ln() / self.extinction()[channel]
+    }
+
+    /// Weight for light which travelled `distance` from its last collision,
+    /// sampled by `sample_distance`, to either a scattering collision or
+    /// the end of the medium.
+    fn spectral_weight(&self, distance: f64, scattered: bool) -> Color {
+        let transmittance = self.transmittance(distance);
+        if scattered {
+            let pdf = self.extinction().component_mul(&transmittance).mean();
+            self.scattering.component_mul(&transmittance) / pdf
+        } else {
+            transmittance / transmittance.mean()
+        }
+    }
+
+    /// Handles a collision sampled with the majorant at `hit`. Channels with
+    /// less extinction than the majorant also see null collisions, which
+    /// leave the ray unchanged, so the kind of collision is chosen by the
+    /// average over channels and the result weighted to compensate.
+    fn collide(&self, inbound: &Ray, hit: &Hit) -> Scatter<'static> {
+        let extinction = self.extinction();
+        let null = Color::repeat(self.majorant()) - extinction;
+        let real = extinction.mean() / self.majorant();
+        if random() < real {
+            let dir = Unit::new_normalize(inbound.dir);
+            Scatter {
+                specular: None,
+                pdf: Some(PDF::henyey_greenstein(self.g, dir)),
+                attenuation: self.scattering / extinction.mean(),
+            }
+        } else {
+            let mut out = Ray::new(hit.point, inbound.dir);
+            out.wavelength = inbound.wavelength;
+            Scatter {
+                specular: Some(out),
+                pdf: None,
+                attenuation: null / null.mean(),
+            }
+        }
+    }
+}
+
+/// Subsurface scattering through a dielectric boundary into a medium, which
+/// light performs a random walk through before leaving the object.
+#[derive(Debug, Copy, Clone)]
+pub struct Subsurface {
+    pub ior: f64,
+    pub medium: Medium,
+}
+
+impl Subsurface {
+    /// Creates a material whose interior has the given `mean_free_path` in
+    /// each channel, and which shows roughly the given diffuse `albedo`
+    /// after multiple scattering.
+    pub fn new(albedo: Color, mean_free_path: Color, ior: f64, g: f64) -> Self {
+        // Invert the multiple scattering albedo of a random walk to the
+        // single scattering albedo which produces it, following the fit in
+        // "Practical and Controllable Subsurface Scattering for Production
+        // Path Tracing", Chiang et al, 2016.
+        let single = albedo.map(|a| {
+            let a = fclamp(a, 0.0, 1.0);
+            let s = 4.09712 + 4.20863 * a
+                - (9.59217 + 41.6808 * a + 17.7126 * a * a).sqrt();
+            1.0 - s * s
+        });
+        let extinction = mean_free_path.map(|d| 1.0 / d.max(1e-6));
+        let scattering = single.component_mul(&extinction);
+        Subsurface {
+            ior,
+            medium: Medium {
+                absorption: extinction - scattering,
+                scattering,
+                g,
+            },
+        }
+    }
+}
+
+/// Sides of a surface from which an emitter radiates. The front is the side
+/// the geometric normal points towards: outwards for spheres and blocks, and
+/// along the positive axis for rects.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Sides {
+    Both,
+    Front,
+    Back,
+}
+
+/// Describes how an emissive surface distributes its radiance.
+#[derive(Clone)]
+pub struct Emitter {
+    /// Radiance scale applied to the emission texture.
+    pub scale: Color,
+    pub sides: Sides,
+    /// Goniometric profile of the luminaire and the frame whose `w` axis is
+    /// the profile's nadir, in world space.
+    pub profile: Option<(Arc<IESProfile>, ONB)>,
+}
+
+impl Emitter {
+    pub fn new(scale: Color) -> Emitter {
+        Emitter {
+            scale,
+            sides: Sides::Both,
+            profile: None,
+        }
+    }
+
+    /// Scale factor for the radiance leaving the surface at `hit` towards
+    /// the origin of `inbound`.
+    fn weight(&self, inbound: &Ray, hit: &Hit) -> f64 {
+        let visible = match self.sides {
+            Sides::Both => true,
+            Sides::Front => hit.front_facing,
+            Sides::Back => !hit.front_facing,
+        };
+        if !visible {
+            return 0.0;
+        }
+        match &self.profile {
+            Some((profile, frame)) => {
+                profile.eval(frame, Unit::new_normalize(-inbound.dir))
+            }
+            None => 1.0,
+        }
+    }
+
+    /// Ratio of the flux emitted by a surface of unit area and radiance to
+    /// `PI`, the flux of a one-sided diffuse emitter.
+    pub fn relative_flux(&self) -> f64 {
+        let sides = match self.sides {
+            Sides::Both => 2.0,
+            Sides::Front | Sides::Back => 1.0,
+        };
+        let profile = match &self.profile {
+            Some((profile, _)) => profile.average(),
+            None => 1.0,
+        };
+        sides * profile
+    }
+
+    /// Frame with `axis` as the nadir of a goniometric profile.
+    pub fn frame(axis: Vector) -> ONB {
+        ONB::from_w(Unit::new_normalize(axis))
+    }
+
+    /// Carries the frame of the profile from object space into the scene,
+    /// keeping its azimuth turned with the object.
+    pub fn transform(&mut self, matrix: &Matrix4<f64>) {
+        if let Some((_, frame)) = &mut self.profile {
+            let w = Unit::new_normalize(matrix.transform_vector(&frame.w));
+            let u = matrix.transform_vector(&frame.u);
+            let u = Unit::new_normalize(u - u.dot(&w) * *w);
+            let v = Unit::new_normalize(u.cross(&w));
+            *frame = ONB { u, v, w };
+        }
+    }
+}
+
+/// Index of refraction of a dielectric, optionally varying with wavelength.
+#[derive(Debug, Copy, Clone)]
+pub enum IOR {
+    Constant(f64),
+    /// Cauchy's equation `A + B / lambda^2`, with `lambda` in micrometres.
+    Cauchy(f64, f64),
+    /// Sellmeier's equation with the B and C coefficients of its three
+    /// terms, with `lambda` in micrometres.
+    Sellmeier([f64; 3], [f64; 3]),
+}
+
+impl IOR {
+    /// Wavelength at which dispersive IORs are evaluated when rendering in
+    /// RGB: the sodium D line, at which IORs are conventionally quoted.
+    const DEFAULT_WAVELENGTH: f64 = 589.3;
+
+    /// Evaluates the IOR at `wavelength` in nanometres.
+    pub fn at(&self, wavelength: Option<f64>) -> f64 {
+        let lambda = wavelength.unwrap_or(Self::DEFAULT_WAVELENGTH) / 1000.0;
+        match self {
+            &IOR::Constant(ior) => ior,
+            &IOR::Cauchy(a, b) => a + b / (lambda * lambda),
+            IOR::Sellmeier(b, c) => {
+                let l2 = lambda * lambda;
+                let sum: f64 = (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum();
+                (1.0 + sum).sqrt()
+            }
+        }
+    }
+
+    pub fn is_dispersive(&self) -> bool {
+        match self {
+            IOR::Constant(..) => false,
+            IOR::Cauchy(..) | IOR::Sellmeier(..) => true,
+        }
+    }
 }
 
 pub struct Scatter<'scene> {
@@ -27,7 +281,7 @@ impl Material {
             Material::Lambertian(albedo) => {
                 let pdf = PDF::cosine(hit.normal);
                 Some(Scatter {
-                    attenuation: albedo.value(hit.uv, hit.point),
+                    attenuation: albedo.value(hit),
                     pdf: Some(pdf),
                     specular: None,
                 })
@@ -37,7 +291,7 @@ impl Material {
                     // Diffuse, use standard cosine PDF.
                     let pdf = PDF::cosine(hit.normal);
                     Some(Scatter {
-                        attenuation: diffuse.value(hit.uv, hit.point),
+                        attenuation: diffuse.value(hit),
                         pdf: Some(pdf),
                         specular: None,
                     })
@@ -49,63 +303,178 @@ impl Material {
                     ));
                     let pdf = PDF::phong(hit.normal, reflect_dir, *shininess);
                     Some(Scatter {
-                        attenuation: specular.value(hit.uv, hit.point),
+                        attenuation: specular.value(hit),
                         pdf: Some(pdf),
-                        specular: Some(Ray {
-                            origin: hit.point,
-                            dir: pdf.gen(),
-                        }),
+                        specular: Some(Ray::new(hit.point, pdf.gen())),
                     })
                 }
             }
             &Material::Metal(albedo, roughness) => {
                 let scatter_dir = reflect(inbound.dir.normalize(), hit.normal);
                 Some(Scatter {
-                    specular: Some(Ray {
-                        origin: hit.point,
-                        dir: scatter_dir
-                            + (roughness * random_in_unit_sphere()),
-                    }),
+                    specular: Some(Ray::new(
+                        hit.point,
+                        scatter_dir + (roughness * random_in_unit_sphere()),
+                    )),
                     attenuation: albedo,
                     pdf: None,
                 })
             }
-            &Material::Dielectric(ior) => {
-                let eta = if hit.front_facing { 1.0 / ior } else { ior };
-                let dir = inbound.dir.normalize();
-
-                let cos_theta = f64::min((-dir).dot(&hit.normal), 1.0);
-                let sin_theta = (1.0 - (cos_theta * cos_theta)).sqrt();
-
-                let scatter_dir = if (eta * sin_theta) > 1.0
-                    || random() < schlick(cos_theta, ior)
-                {
-                    // Must reflect.
-                    reflect(dir, hit.normal)
-                } else {
-                    refract(dir, hit.normal, eta)
-                };
-                let out = Ray {
-                    origin: hit.point,
-                    dir: scatter_dir,
-                };
+            Material::Dielectric(ior) => {
+                let ior = ior.at(inbound.wavelength);
+                let out = Ray::new(hit.point, dielectric(inbound, hit, ior));
                 Some(Scatter {
                     specular: Some(out),
                     attenuation: Color::new(1.0, 1.0, 1.0),
                     pdf: None,
                 })
             }
+            Material::Subsurface(subsurface) => {
+                let dir = inbound.dir.normalize();
+                let cos_theta = (-dir).dot(&hit.normal);
+                let attenuation = if hit.front_facing {
+                    Color::new(1.0, 1.0, 1.0)
+                } else {
+                    let distance = (hit.point - inbound.origin).norm();
+                    subsurface.medium.spectral_weight(distance, false)
+                };
+                let specular = if hit.front_facing {
+                    Some(dielectric(inbound, hit, subsurface.ior))
+                } else if random() < schlick(cos_theta, subsurface.ior) {
+                    Some(reflect(dir, hit.normal))
+                } else {
+                    None
+                };
+                if let Some(dir) = specular {
+                    let mut out = Ray::new(hit.point, dir);
+                    out.wavelength = inbound.wavelength;
+                    Some(Scatter {
+                        specular: Some(out),
+                        attenuation,
+                        pdf: None,
+                    })
+                } else {
+                    // After many collisions the direction in which light
+                    // leaves is close to diffuse, and treating it as such
+                    // lets the exit point sample lights directly.
+                    Some(Scatter {
+                        specular: None,
+                        attenuation,
+                        pdf: Some(PDF::cosine(-hit.normal)),
+                    })
+                }
+            }
+            Material::Hair(hair) => Some(hair.scatter(inbound, hit)),
             Material::Emission(..) => None,
+            Material::Medium(medium) => Some(medium.collide(inbound, hit)),
+            Material::Interior(medium) => {
+                let distance = (hit.point - inbound.origin).norm();
+                Some(Scatter {
+                    specular: None,
+                    pdf: Some(PDF::henyey_greenstein(
+                        medium.g,
+                        Unit::new_normalize(inbound.dir),
+                    )),
+                    attenuation: medium.spectral_weight(distance, true),
+                })
+            }
+            Material::Volume(volume) => {
+                Some(volume.medium().collide(inbound, hit))
+            }
         }
     }
 
-    pub fn emitted(&self, hit: &Hit) -> Color {
+    /// Coefficients of the participating medium for materials which fill
+    /// volumes, relative to the density at a collision.
+    pub fn medium(&self) -> Option<&Medium> {
+        match self {
+            Material::Medium(medium) | Material::Interior(medium) => {
+                Some(medium)
+            }
+            Material::Volume(volume) => Some(volume.medium()),
+            _ => None,
+        }
+    }
+
+    /// Whether light can reach hits on the material directly. Lights are
+    /// hidden from the interiors of subsurface scattering objects by their
+    /// surfaces, so sampling them there only adds noise to long random
+    /// walks.
+    pub fn samples_lights(&self) -> bool {
+        !matches!(self, Material::Interior(..))
+    }
+
+    /// Whether scattering depends on the wavelength of the inbound ray, in
+    /// which case only the hero wavelength of a spectral path can continue.
+    pub fn is_dispersive(&self) -> bool {
+        match self {
+            Material::Dielectric(ior) => ior.is_dispersive(),
+            _ => false,
+        }
+    }
+
+    /// Estimated power emitted by a surface of `area` made of this material,
+    /// as a luminance.
+    pub fn emitted_power(&self, area: f64) -> f64 {
+        match self {
+            Material::Emission(tex, emitter) => {
+                let radiance = tex.average().component_mul(&emitter.scale);
+                PI * area * emitter.relative_flux() * luminance(&radiance)
+            }
+            _ => 0.0,
+        }
+    }
+
+    /// Chooses a side of the surface from which to emit light, returning
+    /// whether it is the front, and picking either if both emit.
+    pub fn choose_emitting_side(&self) -> bool {
+        match self {
+            Material::Emission(_, emitter) => match emitter.sides {
+                Sides::Both => random() < 0.5,
+                Sides::Front => true,
+                Sides::Back => false,
+            },
+            _ => true,
+        }
+    }
+
+    /// Radiance emitted from `hit` back along `inbound`.
+    pub fn emitted(&self, inbound: &Ray, hit: &Hit) -> Color {
         match self {
             Material::Lambertian(..) => Color::zeros(),
             Material::Phong(..) => Color::zeros(),
             Material::Metal(..) => Color::zeros(),
             Material::Dielectric(..) => Color::zeros(),
-            Material::Emission(tex) => tex.value(hit.uv, hit.point),
+            Material::Subsurface(..) => Color::zeros(),
+            Material::Interior(..) => Color::zeros(),
+            Material::Medium(..) => Color::zeros(),
+            Material::Hair(..) => Color::zeros(),
+            Material::Volume(volume) => volume.emission(hit.point),
+            Material::Emission(tex, emitter) => {
+                let weight = emitter.weight(inbound, hit);
+                if weight > 0.0 {
+                    weight * tex.value(hit).component_mul(&emitter.scale)
+                } else {
+                    Color::zeros()
+                }
+            }
         }
     }
 }
+
+/// Reflects or refracts `inbound` at a smooth boundary with a dielectric of
+/// index `ior`, choosing between them by their Fresnel reflectance.
+fn dielectric(inbound: &Ray, hit: &Hit, ior: f64) -> Vector {
+    let eta = if hit.front_facing { 1.0 / ior } else { ior };
+    let dir = inbound.dir.normalize();
+
+    let cos_theta = f64::min((-dir).dot(&hit.normal), 1.0);
+    let sin_theta = (1.0 - (cos_theta * cos_theta)).sqrt();
+
+    if (eta * sin_theta) > 1.0 || random() < schlick(cos_theta, ior) {
+        // Must reflect.
+        reflect(dir, hit.normal)
+    } else {
+        refract(dir, hit.normal, eta)
+    }
+}
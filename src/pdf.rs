@@ -1,5 +1,6 @@
-use crate::geometry::Hittable;
+use crate::geometry::{EnvMap, Hittable, Sky};
 use crate::geometry::{Point, Ray, Vector, ONB};
+use crate::hair::HairLobe;
 use crate::util::*;
 use nalgebra::Unit;
 use std::f64::consts::PI;
@@ -8,7 +9,15 @@ use std::f64::consts::PI;
 pub enum PDF<'scene> {
     Cosine(ONB),
     Phong(ONB, Unit<Vector>, u32),
-    Hittable(Point, &'scene dyn Hittable),
+    /// Henyey-Greenstein phase function with asymmetry `g` around the
+    /// direction of travel `w`.
+    HenyeyGreenstein(f64, ONB),
+    /// One lobe of scattering from a hair fibre.
+    Hair(HairLobe),
+    /// Directions from a point towards a hittable as it is at a time.
+    Hittable(Point, f64, &'scene dyn Hittable),
+    Environment(&'scene EnvMap),
+    Sky(&'scene Sky),
     Mix(f64, &'scene PDF<'scene>, &'scene PDF<'scene>),
 }
 
@@ -25,11 +34,16 @@ impl<'scene> PDF<'scene> {
         PDF::Phong(ONB::from_w(w), outbound, n)
     }
 
+    pub fn henyey_greenstein(g: f64, dir: Unit<Vector>) -> PDF<'scene> {
+        PDF::HenyeyGreenstein(g, ONB::from_w(dir))
+    }
+
     pub fn hittable(
         origin: Point,
+        time: f64,
         hittable: &'scene dyn Hittable,
     ) -> PDF<'scene> {
-        PDF::Hittable(origin, hittable)
+        PDF::Hittable(origin, time, hittable)
     }
 
     pub fn mix(
@@ -60,10 +74,19 @@ impl<'scene> PDF<'scene> {
                     * cos_theta_s.powi(n as i32 + 1)
                     * sin_theta_s
             }
-            PDF::Hittable(origin, hittable) => hittable.pdf(Ray {
-                origin: *origin,
-                dir,
-            }),
+            &PDF::HenyeyGreenstein(g, uvw) => {
+                let cos = dir.normalize().dot(&uvw.w);
+                let denom = 1.0 + g * g - 2.0 * g * cos;
+                (1.0 - g * g) / (4.0 * PI * denom * denom.sqrt())
+            }
+            PDF::Hair(lobe) => lobe.value(dir),
+            PDF::Hittable(origin, time, hittable) => {
+                let mut ray = Ray::new(*origin, dir);
+                ray.time = *time;
+                hittable.pdf(ray)
+            }
+            PDF::Environment(map) => map.pdf(dir),
+            PDF::Sky(sky) => sky.pdf(dir),
             PDF::Mix(bias, pdf1, pdf2) => {
                 bias * pdf1.value(dir) + (1.0 - bias) * pdf2.value(dir)
             }
@@ -85,7 +108,28 @@ impl<'scene> PDF<'scene> {
                 let z = phi.cos();
                 ONB::from_w(*outbound).localize(Vector::new(x, y, z))
             }
-            PDF::Hittable(origin, hittable) => hittable.random(*origin),
+            &PDF::HenyeyGreenstein(g, uvw) => {
+                let u = random();
+                let cos_theta = if g.abs() < 1e-3 {
+                    1.0 - 2.0 * u
+                } else {
+                    let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * u);
+                    fclamp((1.0 + g * g - s * s) / (2.0 * g), -1.0, 1.0)
+                };
+                let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+                let phi = 2.0 * PI * random();
+                uvw.localize(Vector::new(
+                    sin_theta * phi.cos(),
+                    sin_theta * phi.sin(),
+                    cos_theta,
+                ))
+            }
+            PDF::Hair(lobe) => lobe.gen(),
+            PDF::Hittable(origin, time, hittable) => {
+                hittable.random(*origin, *time)
+            }
+            PDF::Environment(map) => map.sample(),
+            PDF::Sky(sky) => sky.sample(),
             PDF::Mix(bias, pdf1, pdf2) => {
                 if random() < *bias {
                     pdf1.gen()
@@ -5,12 +5,14 @@ use crate::color::Color;
 use crate::config::Config;
 use crate::geometry::*;
 use crate::renderer::Buffer;
+use crate::spectrum::{self, Wavelengths};
 #[allow(unused_imports)]
 use crate::udpt::UDPT;
 use crate::util::*;
 use crossbeam::thread;
 use image::{ImageBuffer, ImageFormat, Rgb};
 use std::ops::Range;
+use std::path::Path;
 use std::sync::Arc;
 use std::sync::RwLock;
 use std::time::Instant;
@@ -31,6 +33,14 @@ fn raytrace_rows(
     let mut row_backlog: Vec<(usize, Vec<u32>)> = vec![];
     let mut tracer = UDPT::new(scene);
     // let mut tracer = BDPT::new(scene, config.samples);
+    // Differentials span the distance between samples rather than pixels,
+    // so that textures are filtered less as the sample count rises.
+    let spread = f64::max(0.125, (config.samples as f64).sqrt().recip());
+    let xyz_to_rgb = spectrum::xyz_to_rgb();
+    let footprint = (
+        spread / (config.width as f64 - 1.0),
+        -spread / (config.height as f64 - 1.0),
+    );
     for r in rows {
         let mut row: Vec<u32> = vec![0; config.width];
         for (c, result) in row.iter_mut().enumerate() {
@@ -39,11 +49,21 @@ fn raytrace_rows(
                 let u = (c as f64 + random()) / (config.width as f64 - 1.0);
                 let v = ((config.height - r) as f64 + random())
                     / (config.height as f64 - 1.0);
-                let ray = camera.get_ray(u, v);
-                let color = tracer.sample(ray, false);
-                color_sum += color;
+                let mut ray = match camera.get_ray(u, v, footprint) {
+                    Some(ray) => ray,
+                    None => continue,
+                };
+                if config.spectral {
+                    let wavelengths = Wavelengths::sample(random());
+                    ray.wavelength = Some(wavelengths.hero());
+                    let values = tracer.sample(ray, false);
+                    color_sum += xyz_to_rgb * wavelengths.to_xyz(values);
+                } else {
+                    let color = tracer.sample(ray, false);
+                    color_sum += color;
+                }
             }
-            *result = write_color(config, color_sum);
+            *result = write_color(config, color_sum * camera.exposure);
         }
         row_backlog.push((r, row));
         if let Ok(mut b) = buf.try_write() {
@@ -65,6 +85,7 @@ pub fn raytrace<'scene>(
     scene: &'scene Scene,
     camera: &'scene Camera,
     buf: Arc<RwLock<Buffer>>,
+    output: Option<&Path>,
 ) {
     let rows_per = (config.height / config.threads) + 1;
 
@@ -85,7 +106,7 @@ pub fn raytrace<'scene>(
 
     println!("Render time: {} ms", elapsed.as_millis());
 
-    if let Some(output) = &config.output {
+    if let Some(output) = output {
         let buf = buf.read().unwrap();
         let img: ImageBuffer<Rgb<u8>, Vec<u8>> = ImageBuffer::from_fn(
             config.width as u32,
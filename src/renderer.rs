@@ -1,8 +1,9 @@
 use crate::config::Config;
-use crate::geometry::Scene;
+use crate::geometry::SceneLoader;
 use crate::raytrace;
 
 use minifb::{Window, WindowOptions};
+use std::path::{Path, PathBuf};
 use std::sync::mpsc;
 use std::sync::Arc;
 use std::sync::RwLock;
@@ -13,13 +14,33 @@ pub type Buffer = Vec<u32>;
 pub fn render(config: Arc<Config>) {
     let mut window = make_window(&config);
     let buf = Arc::new(RwLock::new(make_buffer(&config)));
-    let (scene, camera) = Scene::from_config(&config);
+    let mut loader = SceneLoader::new(&config);
+    let (first, last) = match config.frames {
+        Some(frames) => (frames.first, frames.last),
+        None => (0, 0),
+    };
+    // Build the first frame here, so that errors in the scene are reported
+    // before the window waits for it.
+    loader.frame(first);
 
     {
         let buf = buf.clone();
         let config = config.clone();
         thread::spawn(move || {
-            raytrace::raytrace(config, &scene, &camera, buf);
+            for frame in first..=last {
+                let (scene, camera) = loader.frame(frame);
+                let output = match (&config.output, config.frames) {
+                    (Some(output), Some(_)) => Some(frame_path(output, frame)),
+                    (output, _) => output.clone(),
+                };
+                raytrace::raytrace(
+                    config.clone(),
+                    scene,
+                    camera,
+                    buf.clone(),
+                    output.as_deref(),
+                );
+            }
         });
     }
 
@@ -50,6 +71,16 @@ pub fn render(config: Arc<Config>) {
     }
 }
 
+/// Numbers an image of an animation, as in `output_0012.png`.
+fn frame_path(output: &Path, frame: u32) -> PathBuf {
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+    let mut name = format!("{}_{:04}", stem, frame);
+    if let Some(ext) = output.extension() {
+        name = format!("{}.{}", name, ext.to_string_lossy());
+    }
+    output.with_file_name(name)
+}
+
 fn make_window(config: &Config) -> Window {
     Window::new(
         "Raest",
@@ -0,0 +1,246 @@
+use crate::color::Color;
+use nalgebra::Matrix3;
+
+/// Shortest wavelength sampled in spectral mode, in nanometres.
+pub const LAMBDA_MIN: f64 = 360.0;
+/// Longest wavelength sampled in spectral mode, in nanometres.
+pub const LAMBDA_MAX: f64 = 830.0;
+
+/// Number of wavelengths traced together along each path. Each one occupies
+/// a channel of a `Color`, with the hero wavelength first.
+const N_LAMBDA: usize = 3;
+
+/// Wavelengths traced along a single path: the hero wavelength followed by
+/// copies rotated evenly through the visible range.
+#[derive(Debug, Copy, Clone)]
+pub struct Wavelengths([f64; N_LAMBDA]);
+
+impl Wavelengths {
+    /// Picks a hero wavelength uniformly from `u` in [0, 1).
+    pub fn sample(u: f64) -> Wavelengths {
+        Wavelengths::from_hero(LAMBDA_MIN + u * (LAMBDA_MAX - LAMBDA_MIN))
+    }
+
+    pub fn from_hero(hero: f64) -> Wavelengths {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let mut result = [hero; N_LAMBDA];
+        for (i, lambda) in result.iter_mut().enumerate().skip(1) {
+            let shifted = hero + (i as f64 / N_LAMBDA as f64) * range;
+            *lambda = if shifted > LAMBDA_MAX {
+                shifted - range
+            } else {
+                shifted
+            };
+        }
+        Wavelengths(result)
+    }
+
+    pub fn hero(&self) -> f64 {
+        self.0[0]
+    }
+
+    /// Per-channel weight that drops the secondary wavelengths, leaving the
+    /// hero wavelength to carry the weight of all of them.
+    pub fn terminate_secondary() -> Color {
+        let mut weight = Color::zeros();
+        weight[0] = N_LAMBDA as f64;
+        weight
+    }
+
+    /// Samples the smooth spectrum corresponding to an RGB reflectance or
+    /// emission at each wavelength.
+    pub fn upsample(&self, rgb: Color) -> Color {
+        Color::from_iterator(self.0.iter().map(|&l| rgb_to_spectrum(rgb, l)))
+    }
+
+    /// Converts spectral radiance at these wavelengths into an unbiased
+    /// estimate of its CIE XYZ coordinates, normalized so that Y is
+    /// luminance.
+    pub fn to_xyz(self, values: Color) -> Color {
+        // Each wavelength is sampled uniformly, so the Monte Carlo estimate
+        // of the CIE integrals weights every sample by the range width.
+        let weight =
+            (LAMBDA_MAX - LAMBDA_MIN) / (N_LAMBDA as f64 * CIE_Y_INTEGRAL);
+        let mut xyz = Color::zeros();
+        for (i, &lambda) in self.0.iter().enumerate() {
+            xyz += values[i] * weight * cie_xyz(lambda);
+        }
+        xyz
+    }
+}
+
+/// Smits' RGB to spectrum conversion basis, sampled in ten bins evenly spaced
+/// from 380nm to 720nm.
+/// "An RGB to Spectrum Conversion for Reflectances", Brian Smits, 1999.
+const SMITS_WHITE: [f64; 10] = [
+    1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000,
+    1.0000,
+];
+const SMITS_CYAN: [f64; 10] = [
+    0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000,
+    0.0000,
+];
+const SMITS_MAGENTA: [f64; 10] = [
+    1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000,
+    0.9959,
+];
+const SMITS_YELLOW: [f64; 10] = [
+    0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685,
+    0.9840,
+];
+const SMITS_RED: [f64; 10] = [
+    0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149,
+    1.0149,
+];
+const SMITS_GREEN: [f64; 10] = [
+    0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000,
+    0.0025,
+];
+const SMITS_BLUE: [f64; 10] = [
+    1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483,
+    0.0496,
+];
+
+/// Linearly interpolates a Smits basis spectrum at `lambda`.
+fn smits(table: &[f64; 10], lambda: f64) -> f64 {
+    const START: f64 = 380.0;
+    const END: f64 = 720.0;
+    let x = (lambda - START) / (END - START) * 9.0;
+    if x <= 0.0 {
+        return table[0];
+    }
+    if x >= 9.0 {
+        return table[9];
+    }
+    let i = x.floor() as usize;
+    let t = x - i as f64;
+    (1.0 - t) * table[i] + t * table[i + 1]
+}
+
+fn rgb_to_spectrum(c: Color, lambda: f64) -> f64 {
+    let (r, g, b) = (c.x, c.y, c.z);
+    let basis = |t: &[f64; 10]| smits(t, lambda);
+    // Remove as much white as possible, then the largest secondary color,
+    // then the remaining primary.
+    if r <= g && r <= b {
+        let mut result = r * basis(&SMITS_WHITE);
+        if g <= b {
+            result += (g - r) * basis(&SMITS_CYAN);
+            result += (b - g) * basis(&SMITS_BLUE);
+        } else {
+            result += (b - r) * basis(&SMITS_CYAN);
+            result += (g - b) * basis(&SMITS_GREEN);
+        }
+        result
+    } else if g <= r && g <= b {
+        let mut result = g * basis(&SMITS_WHITE);
+        if r <= b {
+            result += (r - g) * basis(&SMITS_MAGENTA);
+            result += (b - r) * basis(&SMITS_BLUE);
+        } else {
+            result += (b - g) * basis(&SMITS_MAGENTA);
+            result += (r - b) * basis(&SMITS_RED);
+        }
+        result
+    } else {
+        let mut result = b * basis(&SMITS_WHITE);
+        if r <= g {
+            result += (r - b) * basis(&SMITS_YELLOW);
+            result += (g - r) * basis(&SMITS_GREEN);
+        } else {
+            result += (g - b) * basis(&SMITS_YELLOW);
+            result += (r - g) * basis(&SMITS_RED);
+        }
+        result
+    }
+}
+
+/// Integral of the CIE y matching function, used to normalize luminance.
+const CIE_Y_INTEGRAL: f64 = 106.856895;
+
+/// CIE 1931 colour matching functions, using the multi-lobe fit from
+/// "Simple Analytic Approximations to the CIE XYZ Color Matching Functions",
+/// Wyman, Sloan and Shirley, 2013.
+fn cie_xyz(lambda: f64) -> Color {
+    let g = |mu: f64, sigma1: f64, sigma2: f64| {
+        let t = (lambda - mu) / if lambda < mu { sigma1 } else { sigma2 };
+        (-0.5 * t * t).exp()
+    };
+    Color::new(
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7)
+            - 0.065 * g(501.1, 20.4, 26.2),
+        0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+        1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8),
+    )
+}
+
+/// Linear RGB color of a blackbody radiator at `kelvin`, normalized to unit
+/// luminance.
+pub fn blackbody_rgb(kelvin: f64) -> Color {
+    const STEPS: usize = 470;
+    let step = (LAMBDA_MAX - LAMBDA_MIN) / STEPS as f64;
+    let mut xyz = Color::zeros();
+    for i in 0..STEPS {
+        let lambda = LAMBDA_MIN + (i as f64 + 0.5) * step;
+        xyz += planck(lambda, kelvin) * cie_xyz(lambda);
+    }
+    let rgb = xyz_to_rgb() * (xyz / xyz.y);
+    // Very hot or cold sources fall slightly outside the sRGB gamut.
+    rgb.map(|c| c.max(0.0))
+}
+
+/// Spectral radiance of a blackbody at `lambda` nanometres, up to a constant
+/// factor.
+fn planck(lambda: f64, kelvin: f64) -> f64 {
+    // Second radiation constant hc/k, in nanometre kelvins.
+    const C2: f64 = 1.4387769e7;
+    let l = lambda / 1000.0;
+    1.0 / (l.powi(5) * ((C2 / (lambda * kelvin)).exp() - 1.0))
+}
+
+/// Converts XYZ to linear sRGB, white balanced so that a flat spectrum maps
+/// to white and neutral colors survive the round trip through the spectral
+/// domain.
+pub fn xyz_to_rgb() -> Matrix3<f64> {
+    let balance = Matrix3::from_diagonal(&white_balance());
+    balance * xyz_to_srgb()
+}
+
+fn xyz_to_srgb() -> Matrix3<f64> {
+    Matrix3::new(
+        3.2404542, -1.5371385, -0.4985314, //
+        -0.9692660, 1.8760108, 0.0415560, //
+        0.0556434, -0.2040259, 1.0572252,
+    )
+}
+
+/// Per-channel scale mapping a flat spectrum to RGB white.
+fn white_balance() -> Color {
+    const STEPS: usize = 470;
+    let step = (LAMBDA_MAX - LAMBDA_MIN) / STEPS as f64;
+    let mut xyz = Color::zeros();
+    for i in 0..STEPS {
+        xyz += cie_xyz(LAMBDA_MIN + (i as f64 + 0.5) * step) * step;
+    }
+    let rgb = xyz_to_srgb() * (xyz / CIE_Y_INTEGRAL);
+    rgb.map(f64::recip)
+}
+
+#[cfg(test)]
+#[test]
+fn round_trip_preserves_rgb() {
+    for &rgb in &[
+        Color::new(1.0, 1.0, 1.0),
+        Color::new(0.8, 0.2, 0.1),
+        Color::new(0.1, 0.5, 0.9),
+    ] {
+        const N: usize = 1000;
+        let mut sum = Color::zeros();
+        for i in 0..N {
+            let lambdas = Wavelengths::sample((i as f64 + 0.5) / N as f64);
+            sum += lambdas.to_xyz(lambdas.upsample(rgb));
+        }
+        let result = xyz_to_rgb() * (sum / N as f64);
+        assert!((result - rgb).norm() < 0.1, "{:?} -> {:?}", rgb, result);
+    }
+}
@@ -0,0 +1,354 @@
+//! Minimal reader for OpenEXR scanline images, supporting the uncompressed,
+//! RLE and ZIP compression schemes.
+
+use crate::color::Color;
+use std::convert::TryInto;
+
+const MAGIC: [u8; 4] = [0x76, 0x2f, 0x31, 0x01];
+const TILED_FLAG: u32 = 0x200;
+const MULTIPART_FLAG: u32 = 0x1000;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum PixelType {
+    Uint,
+    Half,
+    Float,
+}
+
+impl PixelType {
+    fn size(self) -> usize {
+        match self {
+            PixelType::Half => 2,
+            PixelType::Uint | PixelType::Float => 4,
+        }
+    }
+}
+
+struct Channel {
+    name: String,
+    pixel_type: PixelType,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Compression {
+    None,
+    RLE,
+    ZIPS,
+    ZIP,
+}
+
+impl Compression {
+    fn lines_per_block(self) -> usize {
+        match self {
+            Compression::None | Compression::RLE | Compression::ZIPS => 1,
+            Compression::ZIP => 16,
+        }
+    }
+}
+
+/// Cursor over the bytes of the file, reporting truncation as an error.
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.pos + n > self.data.len() {
+            return Err("unexpected end of file".into());
+        }
+        let result = &self.data[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(result)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn i32(&mut self) -> Result<i32, String> {
+        Ok(i32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let rest = &self.data[self.pos.min(self.data.len())..];
+        let len = rest
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or_else(|| "unterminated string".to_string())?;
+        let s = String::from_utf8_lossy(&rest[..len]).into_owned();
+        self.pos += len + 1;
+        Ok(s)
+    }
+}
+
+/// Decodes an EXR file into its width, height and linear RGB pixels in
+/// row-major order from the top. Missing color channels are treated as
+/// zero, and a single `Y` channel as gray.
+pub fn decode(data: &[u8]) -> Result<(usize, usize, Vec<Color>), String> {
+    let mut r = Reader { data, pos: 0 };
+    if r.bytes(4)? != MAGIC {
+        return Err("not an OpenEXR file".into());
+    }
+    let version = r.i32()? as u32;
+    if version & (TILED_FLAG | MULTIPART_FLAG) != 0 {
+        return Err("only single part scanline images are supported".into());
+    }
+
+    let mut channels = vec![];
+    let mut compression = None;
+    let mut window = None;
+    loop {
+        let name = r.string()?;
+        if name.is_empty() {
+            break;
+        }
+        let _kind = r.string()?;
+        let size = r.i32()? as usize;
+        let value = r.bytes(size)?;
+        let mut v = Reader {
+            data: value,
+            pos: 0,
+        };
+        match name.as_str() {
+            "channels" => loop {
+                let name = v.string()?;
+                if name.is_empty() {
+                    break;
+                }
+                let pixel_type = match v.i32()? {
+                    0 => PixelType::Uint,
+                    1 => PixelType::Half,
+                    2 => PixelType::Float,
+                    t => return Err(format!("unknown pixel type {}", t)),
+                };
+                v.bytes(4)?;
+                if v.i32()? != 1 || v.i32()? != 1 {
+                    return Err("subsampled channels are not supported".into());
+                }
+                channels.push(Channel { name, pixel_type });
+            },
+            "compression" => {
+                compression = Some(match v.u8()? {
+                    0 => Compression::None,
+                    1 => Compression::RLE,
+                    2 => Compression::ZIPS,
+                    3 => Compression::ZIP,
+                    c => return Err(format!("unsupported compression {}", c)),
+                })
+            }
+            "dataWindow" => {
+                window = Some((v.i32()?, v.i32()?, v.i32()?, v.i32()?));
+            }
+            _ => {}
+        }
+    }
+    let compression =
+        compression.ok_or_else(|| "missing compression".to_string())?;
+    let (xmin, ymin, xmax, ymax) =
+        window.ok_or_else(|| "missing dataWindow".to_string())?;
+    if channels.is_empty() || xmax < xmin || ymax < ymin {
+        return Err("image is empty".into());
+    }
+    let width = (xmax - xmin + 1) as usize;
+    let height = (ymax - ymin + 1) as usize;
+
+    // Map each channel to the color component it contributes to.
+    let targets: Vec<Vec<usize>> = channels
+        .iter()
+        .map(|c| match c.name.as_str() {
+            "R" => vec![0],
+            "G" => vec![1],
+            "B" => vec![2],
+            "Y" => vec![0, 1, 2],
+            _ => vec![],
+        })
+        .collect();
+
+    let lines_per_block = compression.lines_per_block();
+    let blocks = (height - 1) / lines_per_block + 1;
+    let mut offsets = vec![];
+    for _ in 0..blocks {
+        offsets.push(r.u64()? as usize);
+    }
+
+    let line_size: usize =
+        channels.iter().map(|c| c.pixel_type.size() * width).sum();
+    let mut pixels = vec![Color::zeros(); width * height];
+    for offset in offsets {
+        let mut chunk = Reader { data, pos: offset };
+        let y = chunk.i32()? - ymin;
+        let size = chunk.i32()? as usize;
+        let packed = chunk.bytes(size)?;
+        if y < 0 || y as usize >= height {
+            return Err(format!("scanline {} out of range", y + ymin));
+        }
+        let y = y as usize;
+        let lines = lines_per_block.min(height - y);
+        let expected = line_size * lines;
+        let block = if size == expected {
+            packed.to_vec()
+        } else {
+            decompress(compression, packed, expected)?
+        };
+        if block.len() != expected {
+            return Err("corrupt scanline block".into());
+        }
+
+        let mut pos = 0;
+        for line in 0..lines {
+            let row = &mut pixels[(y + line) * width..(y + line + 1) * width];
+            for (channel, target) in channels.iter().zip(&targets) {
+                let n = channel.pixel_type.size();
+                for pixel in row.iter_mut() {
+                    let value = read_value(channel.pixel_type, &block[pos..]);
+                    for &i in target {
+                        pixel[i] = value;
+                    }
+                    pos += n;
+                }
+            }
+        }
+    }
+    Ok((width, height, pixels))
+}
+
+fn read_value(pixel_type: PixelType, bytes: &[u8]) -> f64 {
+    match pixel_type {
+        PixelType::Uint => {
+            u32::from_le_bytes(bytes[..4].try_into().unwrap()) as f64
+        }
+        PixelType::Half => {
+            half_to_f64(u16::from_le_bytes(bytes[..2].try_into().unwrap()))
+        }
+        PixelType::Float => {
+            f32::from_le_bytes(bytes[..4].try_into().unwrap()) as f64
+        }
+    }
+}
+
+fn half_to_f64(h: u16) -> f64 {
+    let sign = if h & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((h >> 10) & 0x1f) as i32;
+    let mantissa = (h & 0x3ff) as f64;
+    sign * match exponent {
+        0 => mantissa * 2f64.powi(-24),
+        31 if mantissa == 0.0 => f64::INFINITY,
+        31 => f64::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2f64.powi(exponent - 15),
+    }
+}
+
+fn decompress(
+    compression: Compression,
+    packed: &[u8],
+    expected: usize,
+) -> Result<Vec<u8>, String> {
+    let data = match compression {
+        Compression::None => return Ok(packed.to_vec()),
+        Compression::RLE => {
+            let mut out = Vec::with_capacity(expected);
+            let mut i = 0;
+            while i < packed.len() {
+                let count = packed[i] as i8;
+                i += 1;
+                if count < 0 {
+                    let n = (-(count as i32)) as usize;
+                    let run = packed
+                        .get(i..i + n)
+                        .ok_or_else(|| "corrupt RLE data".to_string())?;
+                    out.extend_from_slice(run);
+                    i += n;
+                } else {
+                    let value = *packed
+                        .get(i)
+                        .ok_or_else(|| "corrupt RLE data".to_string())?;
+                    out.resize(out.len() + count as usize + 1, value);
+                    i += 1;
+                }
+            }
+            out
+        }
+        Compression::ZIPS | Compression::ZIP => {
+            miniz_oxide::inflate::decompress_to_vec_zlib(packed)
+                .map_err(|err| format!("corrupt ZIP data: {:?}", err))?
+        }
+    };
+    Ok(reorder(predict(data)))
+}
+
+/// Undoes the delta encoding applied before compression.
+fn predict(mut data: Vec<u8>) -> Vec<u8> {
+    for i in 1..data.len() {
+        data[i] = data[i - 1].wrapping_add(data[i]).wrapping_sub(128);
+    }
+    data
+}
+
+/// Undoes the split of the data into its even and odd bytes.
+fn reorder(data: Vec<u8>) -> Vec<u8> {
+    let half = data.len() - data.len() / 2;
+    let mut out = Vec::with_capacity(data.len());
+    for i in 0..half {
+        out.push(data[i]);
+        if half + i < data.len() {
+            out.push(data[half + i]);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+#[test]
+fn decodes_uncompressed_half_and_float_channels() {
+    fn attribute(out: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+        out.extend_from_slice(name.as_bytes());
+        out.push(0);
+        out.extend_from_slice(kind.as_bytes());
+        out.push(0);
+        out.extend_from_slice(&(value.len() as i32).to_le_bytes());
+        out.extend_from_slice(value);
+    }
+    let mut channels = vec![];
+    for &(name, kind) in &[("B", 2i32), ("G", 1), ("R", 2)] {
+        channels.extend_from_slice(name.as_bytes());
+        channels.push(0);
+        channels.extend_from_slice(&kind.to_le_bytes());
+        channels.extend_from_slice(&[0, 0, 0, 0]);
+        channels.extend_from_slice(&1i32.to_le_bytes());
+        channels.extend_from_slice(&1i32.to_le_bytes());
+    }
+    channels.push(0);
+    let window: Vec<u8> = [0i32, 0, 1, 0]
+        .iter()
+        .flat_map(|v| v.to_le_bytes().to_vec())
+        .collect();
+
+    let mut file = MAGIC.to_vec();
+    file.extend_from_slice(&2i32.to_le_bytes());
+    attribute(&mut file, "channels", "chlist", &channels);
+    attribute(&mut file, "compression", "compression", &[0]);
+    attribute(&mut file, "dataWindow", "box2i", &window);
+    file.push(0);
+    let offset = file.len() as u64 + 8;
+    file.extend_from_slice(&offset.to_le_bytes());
+    file.extend_from_slice(&0i32.to_le_bytes());
+    file.extend_from_slice(&20i32.to_le_bytes());
+    for &b in &[0.25f32, 4.0] {
+        file.extend_from_slice(&b.to_le_bytes());
+    }
+    // Half precision 0.5 and 2.0.
+    file.extend_from_slice(&[0x00, 0x38, 0x00, 0x40]);
+    for &r in &[1.0f32, 16.0] {
+        file.extend_from_slice(&r.to_le_bytes());
+    }
+
+    let (width, height, pixels) = decode(&file).unwrap();
+    assert_eq!((width, height), (2, 1));
+    assert_eq!(pixels[0], Color::new(1.0, 0.5, 0.25));
+    assert_eq!(pixels[1], Color::new(16.0, 2.0, 4.0));
+}
@@ -0,0 +1,78 @@
+use super::exr;
+use crate::color::Color;
+use crate::util::clamp;
+use image::hdr::HdrDecoder;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+/// High dynamic range image with linear RGB pixels, stored row by row from
+/// the top.
+pub struct HdrImage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Color>,
+}
+
+impl HdrImage {
+    /// Loads a Radiance `.hdr` or OpenEXR `.exr` file.
+    pub fn load(path: &Path) -> Result<HdrImage, String> {
+        let err =
+            |e: &dyn std::fmt::Display| format!("{}: {}", path.display(), e);
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_lowercase);
+        match extension.as_deref() {
+            Some("hdr") => {
+                let file = File::open(path).map_err(|e| err(&e))?;
+                let decoder = HdrDecoder::new(BufReader::new(file))
+                    .map_err(|e| err(&e))?;
+                let meta = decoder.metadata();
+                let pixels = decoder.read_image_hdr().map_err(|e| err(&e))?;
+                Ok(HdrImage {
+                    width: meta.width as usize,
+                    height: meta.height as usize,
+                    pixels: pixels
+                        .iter()
+                        .map(|p| {
+                            Color::new(p[0] as f64, p[1] as f64, p[2] as f64)
+                        })
+                        .collect(),
+                })
+            }
+            Some("exr") => {
+                let data = std::fs::read(path).map_err(|e| err(&e))?;
+                let (width, height, pixels) =
+                    exr::decode(&data).map_err(|e| err(&e))?;
+                Ok(HdrImage {
+                    width,
+                    height,
+                    pixels,
+                })
+            }
+            _ => Err(err(&"expected an .hdr or .exr image")),
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> Color {
+        self.pixels[y * self.width + x]
+    }
+
+    /// Bilinearly interpolates the image at `(u, v)` in [0, 1], with `v`
+    /// increasing downwards. Wraps horizontally and clamps vertically, as
+    /// suits latitude-longitude maps.
+    pub fn lookup(&self, (u, v): (f64, f64)) -> Color {
+        let x = u * self.width as f64 - 0.5;
+        let y = v * self.height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        let wrap = |x: i64| x.rem_euclid(self.width as i64) as usize;
+        let clamp_y = |y: i64| clamp(y, 0, self.height as i64 - 1) as usize;
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let (xa, xb, ya, yb) =
+            (wrap(x0), wrap(x0 + 1), clamp_y(y0), clamp_y(y0 + 1));
+        (1.0 - ty) * ((1.0 - tx) * self.pixel(xa, ya) + tx * self.pixel(xb, ya))
+            + ty * ((1.0 - tx) * self.pixel(xa, yb) + tx * self.pixel(xb, yb))
+    }
+}
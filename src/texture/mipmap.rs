@@ -0,0 +1,313 @@
+use super::{ColorSpace, WrapMode};
+use crate::color::Color;
+use image::{DynamicImage, GenericImageView, Pixel};
+use serde::{Deserialize, Serialize};
+
+/// Algorithm used to reconstruct a texture value from the texels around it.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub enum FilterMode {
+    Nearest,
+    Bilinear,
+    Trilinear,
+    EWA,
+}
+
+/// Most elongated an EWA filter ellipse may be before its minor axis is
+/// widened, bounding the number of texels it covers.
+const MAX_ANISOTROPY: f64 = 8.0;
+
+struct Level {
+    width: usize,
+    height: usize,
+    texels: Vec<Color>,
+}
+
+impl Level {
+    fn texel(&self, x: i64, y: i64, wrap: WrapMode) -> Color {
+        let x = wrap.apply_texel(x, self.width);
+        let y = wrap.apply_texel(y, self.height);
+        self.texels[y * self.width + x]
+    }
+
+    /// Downsamples by a factor of two with a box filter. Odd dimensions
+    /// spread three texels over each one with polyphase weights, so that
+    /// every texel contributes equally and the mean is preserved.
+    fn downsample(&self) -> Level {
+        let width = usize::max(1, self.width / 2);
+        let height = usize::max(1, self.height / 2);
+        let mut texels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let rows = taps(self.height, y);
+            for x in 0..width {
+                let cols = taps(self.width, x);
+                let mut sum = Color::zeros();
+                for &(ty, wy) in &rows {
+                    for &(tx, wx) in &cols {
+                        sum += wx * wy * self.texels[ty * self.width + tx];
+                    }
+                }
+                texels.push(sum);
+            }
+        }
+        Level {
+            width,
+            height,
+            texels,
+        }
+    }
+}
+
+/// Texels of a row or column of `n` covered by texel `i` of the next level,
+/// with their weights.
+fn taps(n: usize, i: usize) -> Vec<(usize, f64)> {
+    if n == 1 {
+        vec![(0, 1.0)]
+    } else if n % 2 == 1 {
+        let (half, n) = ((n / 2) as f64, n as f64);
+        vec![
+            (2 * i, (half - i as f64) / n),
+            (2 * i + 1, half / n),
+            (2 * i + 2, (i + 1) as f64 / n),
+        ]
+    } else {
+        vec![(2 * i, 0.5), (2 * i + 1, 0.5)]
+    }
+}
+
+/// Image pyramid of linear floating point texels, from full resolution down
+/// to a single texel.
+pub struct MipMap {
+    levels: Vec<Level>,
+}
+
+impl MipMap {
+    pub fn new(img: &DynamicImage, color_space: ColorSpace) -> MipMap {
+        let (width, height) = (img.width() as usize, img.height() as usize);
+        let mut texels = Vec::with_capacity(width * height);
+        for y in 0..img.height() {
+            for x in 0..img.width() {
+                let pixel = img.get_pixel(x, y).to_rgb().0;
+                let decode = |c: u8| color_space.decode(c as f64 / 255.0);
+                texels.push(Color::new(
+                    decode(pixel[0]),
+                    decode(pixel[1]),
+                    decode(pixel[2]),
+                ));
+            }
+        }
+        let mut levels = vec![Level {
+            width,
+            height,
+            texels,
+        }];
+        loop {
+            let last = levels.last().unwrap();
+            if last.width == 1 && last.height == 1 {
+                break;
+            }
+            let next = last.downsample();
+            levels.push(next);
+        }
+        MipMap { levels }
+    }
+
+    /// Mean color of the whole image, from the single texel of the last
+    /// level.
+    pub fn average(&self) -> Color {
+        self.levels.last().unwrap().texels[0]
+    }
+
+    /// Looks up the texture at `(u, v)`, where `duv` are the derivatives of
+    /// the lookup coordinates across the pixel footprint (in texture space,
+    /// with `v` increasing downwards).
+    pub fn lookup(
+        &self,
+        (u, v): (f64, f64),
+        duv: Option<((f64, f64), (f64, f64))>,
+        filter: FilterMode,
+        wrap: WrapMode,
+    ) -> Color {
+        let ((dudx, dvdx), (dudy, dvdy)) =
+            duv.unwrap_or(((0.0, 0.0), (0.0, 0.0)));
+        match filter {
+            FilterMode::Nearest => {
+                let level = &self.levels[0];
+                level.texel(
+                    (u * level.width as f64).floor() as i64,
+                    (v * level.height as f64).floor() as i64,
+                    wrap,
+                )
+            }
+            FilterMode::Bilinear => self.bilinear(0, (u, v), wrap),
+            FilterMode::Trilinear => {
+                let width = 2.0
+                    * dudx
+                        .abs()
+                        .max(dvdx.abs())
+                        .max(dudy.abs())
+                        .max(dvdy.abs());
+                self.trilinear((u, v), width, wrap)
+            }
+            FilterMode::EWA => {
+                self.ewa((u, v), (dudx, dvdx), (dudy, dvdy), wrap)
+            }
+        }
+    }
+
+    fn bilinear(
+        &self,
+        level: usize,
+        (u, v): (f64, f64),
+        wrap: WrapMode,
+    ) -> Color {
+        let level = &self.levels[usize::min(level, self.levels.len() - 1)];
+        let x = u * level.width as f64 - 0.5;
+        let y = v * level.height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (dx, dy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        (1.0 - dx) * (1.0 - dy) * level.texel(x0, y0, wrap)
+            + dx * (1.0 - dy) * level.texel(x0 + 1, y0, wrap)
+            + (1.0 - dx) * dy * level.texel(x0, y0 + 1, wrap)
+            + dx * dy * level.texel(x0 + 1, y0 + 1, wrap)
+    }
+
+    /// Blends the two levels whose texel spacing brackets `width`.
+    fn trilinear(&self, uv: (f64, f64), width: f64, wrap: WrapMode) -> Color {
+        let lod = self.lod(width);
+        let base = lod.floor();
+        let frac = lod - base;
+        let base = base as usize;
+        if frac == 0.0 {
+            return self.bilinear(base, uv, wrap);
+        }
+        (1.0 - frac) * self.bilinear(base, uv, wrap)
+            + frac * self.bilinear(base + 1, uv, wrap)
+    }
+
+    /// Continuous level of detail at which a texel spans `width`, clamped to
+    /// the available levels.
+    fn lod(&self, width: f64) -> f64 {
+        let max = (self.levels.len() - 1) as f64;
+        let lod = max + f64::max(width, 1e-8).log2();
+        lod.max(0.0).min(max)
+    }
+
+    /// Elliptically weighted average filtering, following Heckbert's
+    /// formulation as presented in PBRT.
+    fn ewa(
+        &self,
+        uv: (f64, f64),
+        mut axis0: (f64, f64),
+        mut axis1: (f64, f64),
+        wrap: WrapMode,
+    ) -> Color {
+        let len = |(a, b): (f64, f64)| a.hypot(b);
+        if len(axis0) < len(axis1) {
+            std::mem::swap(&mut axis0, &mut axis1);
+        }
+        let major = len(axis0);
+        let mut minor = len(axis1);
+        if minor * MAX_ANISOTROPY < major && minor > 0.0 {
+            let scale = major / (minor * MAX_ANISOTROPY);
+            axis1 = (axis1.0 * scale, axis1.1 * scale);
+            minor *= scale;
+        }
+        if minor == 0.0 {
+            return self.bilinear(0, uv, wrap);
+        }
+        let lod = self.lod(minor);
+        let base = lod.floor();
+        let frac = lod - base;
+        let base = base as usize;
+        (1.0 - frac) * self.ewa_level(base, uv, axis0, axis1, wrap)
+            + frac * self.ewa_level(base + 1, uv, axis0, axis1, wrap)
+    }
+
+    fn ewa_level(
+        &self,
+        level: usize,
+        (u, v): (f64, f64),
+        axis0: (f64, f64),
+        axis1: (f64, f64),
+        wrap: WrapMode,
+    ) -> Color {
+        let level = &self.levels[usize::min(level, self.levels.len() - 1)];
+        let (w, h) = (level.width as f64, level.height as f64);
+        let (s, t) = (u * w - 0.5, v * h - 0.5);
+        let (ds0, dt0) = (axis0.0 * w, axis0.1 * h);
+        let (ds1, dt1) = (axis1.0 * w, axis1.1 * h);
+
+        // Implicit ellipse equation A s^2 + B s t + C t^2 = 1, grown by a
+        // texel so that it always covers at least one sample.
+        let mut a = dt0 * dt0 + dt1 * dt1 + 1.0;
+        let mut b = -2.0 * (ds0 * dt0 + ds1 * dt1);
+        let mut c = ds0 * ds0 + ds1 * ds1 + 1.0;
+        let inv_f = (a * c - b * b * 0.25).recip();
+        a *= inv_f;
+        b *= inv_f;
+        c *= inv_f;
+
+        let det = -b * b + 4.0 * a * c;
+        let inv_det = det.recip();
+        let s_sqrt = (det * c).sqrt();
+        let t_sqrt = (a * det).sqrt();
+        let s0 = (s - 2.0 * inv_det * s_sqrt).ceil() as i64;
+        let s1 = (s + 2.0 * inv_det * s_sqrt).floor() as i64;
+        let t0 = (t - 2.0 * inv_det * t_sqrt).ceil() as i64;
+        let t1 = (t + 2.0 * inv_det * t_sqrt).floor() as i64;
+
+        const ALPHA: f64 = 2.0;
+        let mut sum = Color::zeros();
+        let mut weight_sum = 0.0;
+        for it in t0..=t1 {
+            let tt = it as f64 - t;
+            for is in s0..=s1 {
+                let ss = is as f64 - s;
+                let r2 = a * ss * ss + b * ss * tt + c * tt * tt;
+                if r2 < 1.0 {
+                    let weight = (-ALPHA * r2).exp() - (-ALPHA).exp();
+                    sum += weight * level.texel(is, it, wrap);
+                    weight_sum += weight;
+                }
+            }
+        }
+        if weight_sum <= 0.0 {
+            return level.texel(s.round() as i64, t.round() as i64, wrap);
+        }
+        sum / weight_sum
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn filters_preserve_constant_images() {
+    use image::{ImageBuffer, Rgb};
+    let img = DynamicImage::ImageRgb8(ImageBuffer::from_pixel(
+        13,
+        7,
+        Rgb([255u8, 0, 255]),
+    ));
+    let mipmap = MipMap::new(&img, ColorSpace::Linear);
+    assert_eq!(mipmap.levels.len(), 4);
+    let duv = Some(((0.3, 0.01), (-0.02, 0.05)));
+    for &filter in &[
+        FilterMode::Nearest,
+        FilterMode::Bilinear,
+        FilterMode::Trilinear,
+        FilterMode::EWA,
+    ] {
+        for &wrap in &[WrapMode::Repeat, WrapMode::Clamp, WrapMode::Mirror] {
+            let c = mipmap.lookup((1.7, -0.2), duv, filter, wrap);
+            assert!((c - Color::new(1.0, 0.0, 1.0)).norm() < 1e-9);
+        }
+    }
+
+    // Odd sizes keep every texel in the average.
+    let img = DynamicImage::ImageRgb8(ImageBuffer::from_fn(5, 3, |x, y| {
+        Rgb([(x * 50) as u8, (y * 100) as u8, 0])
+    }));
+    let mipmap = MipMap::new(&img, ColorSpace::Linear);
+    let mean = Color::new(100.0, 100.0, 0.0) / 255.0;
+    assert!((mipmap.average() - mean).norm() < 1e-9);
+}
@@ -0,0 +1,334 @@
+mod exr;
+mod hdr;
+mod mipmap;
+mod noise;
+
+pub use hdr::HdrImage;
+pub use mipmap::{FilterMode, MipMap};
+pub use noise::{Fractal, NoiseBasis, Pattern, VoronoiFeature};
+
+use crate::color::{luminance, Color};
+use crate::geometry::*;
+use crate::util::*;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+#[derive(Clone)]
+pub enum Texture {
+    Solid(Color),
+    Checker(Arc<Texture>, Arc<Texture>),
+    Image(Arc<MipMap>, ImageMapping),
+    /// Blends from the first texture to the second by a procedural pattern.
+    Pattern(Pattern, Arc<Texture>, Arc<Texture>),
+    /// Blends from the first texture to the second by the third, per channel.
+    Mix(Arc<Texture>, Arc<Texture>, Arc<Texture>),
+    Multiply(Arc<Texture>, Arc<Texture>),
+    Add(Arc<Texture>, Arc<Texture>),
+    Invert(Arc<Texture>),
+    /// Maps the luminance of the input through a piecewise linear gradient
+    /// given as `(position, color)` stops sorted by position.
+    ColorRamp(Arc<Texture>, Vec<(f64, Color)>),
+    /// Shifts the hue of the input by degrees and scales its saturation and
+    /// value.
+    HSV(Arc<Texture>, f64, f64, f64),
+    /// The surface UVs as the red and green channels.
+    UV,
+    /// The surface point in world space.
+    Position,
+    /// The shading normal in world space.
+    Normal,
+    /// Projects the input along the three axes using the scaled surface point
+    /// as UVs, blending by the normal raised to `sharpness`.
+    Triplanar(Arc<Texture>, f64, f64),
+}
+
+/// How UV coordinates outside of [0, 1] are mapped back onto the image.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub enum WrapMode {
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+impl WrapMode {
+    /// Maps texel index `x` into an image row or column of length `n`.
+    fn apply_texel(&self, x: i64, n: usize) -> usize {
+        let n = n as i64;
+        let x = match self {
+            WrapMode::Repeat => x.rem_euclid(n),
+            WrapMode::Clamp => clamp(x, 0, n - 1),
+            WrapMode::Mirror => {
+                let t = x.rem_euclid(2 * n);
+                if t >= n {
+                    2 * n - 1 - t
+                } else {
+                    t
+                }
+            }
+        };
+        x as usize
+    }
+}
+
+/// Encoding of the values stored in an image.
+/// Color images are usually sRGB encoded and must be linearized before use,
+/// while data such as roughness or normal maps are stored linearly.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ColorSpace {
+    SRGB,
+    Linear,
+}
+
+impl ColorSpace {
+    fn decode(&self, x: f64) -> f64 {
+        match self {
+            ColorSpace::SRGB => {
+                if x <= 0.04045 {
+                    x / 12.92
+                } else {
+                    ((x + 0.055) / 1.055).powf(2.4)
+                }
+            }
+            ColorSpace::Linear => x,
+        }
+    }
+}
+
+/// Describes how a surface's UV coordinates are mapped onto an image.
+/// UVs are scaled, then rotated counter-clockwise by `rotation` degrees
+/// about the origin, then offset before wrapping.
+#[derive(Debug, Copy, Clone)]
+pub struct ImageMapping {
+    pub scale: (f64, f64),
+    pub offset: (f64, f64),
+    pub rotation: f64,
+    pub wrap: WrapMode,
+    pub color_space: ColorSpace,
+    pub filter: FilterMode,
+}
+
+impl Default for ImageMapping {
+    fn default() -> ImageMapping {
+        ImageMapping {
+            scale: (1.0, 1.0),
+            offset: (0.0, 0.0),
+            rotation: 0.0,
+            wrap: WrapMode::Repeat,
+            color_space: ColorSpace::SRGB,
+            filter: FilterMode::Trilinear,
+        }
+    }
+}
+
+impl ImageMapping {
+    /// Applies the scale and rotation to a UV offset.
+    fn map_vector(&self, (u, v): (f64, f64)) -> (f64, f64) {
+        let (u, v) = (u * self.scale.0, v * self.scale.1);
+        let (sin, cos) = self.rotation.to_radians().sin_cos();
+        (u * cos - v * sin, u * sin + v * cos)
+    }
+
+    /// Maps surface UVs to image UVs, with `v` increasing downwards from the
+    /// top row of the image. Wrapping is left to the texel lookup.
+    fn map(&self, uv: (f64, f64)) -> (f64, f64) {
+        let (u, v) = self.map_vector(uv);
+        (u + self.offset.0, 1.0 - (v + self.offset.1))
+    }
+}
+
+impl Texture {
+    pub fn value(&self, hit: &Hit) -> Color {
+        match self {
+            Texture::Solid(c) => *c,
+            Texture::Checker(odd, even) => {
+                let amped = 10.0 * hit.point;
+                let sines = amped.x.sin() * amped.y.sin() * amped.z.sin();
+                if sines < 0.0 {
+                    odd.value(hit)
+                } else {
+                    even.value(hit)
+                }
+            }
+            Texture::Image(mipmap, mapping) => {
+                let duv = hit.duv.map(|(dx, dy)| {
+                    let flip = |(du, dv): (f64, f64)| (du, -dv);
+                    (flip(mapping.map_vector(dx)), flip(mapping.map_vector(dy)))
+                });
+                mipmap.lookup(
+                    mapping.map(hit.uv),
+                    duv,
+                    mapping.filter,
+                    mapping.wrap,
+                )
+            }
+            Texture::Pattern(pattern, low, high) => {
+                let t = pattern.eval(hit.point);
+                (1.0 - t) * low.value(hit) + t * high.value(hit)
+            }
+            Texture::Mix(a, b, factor) => {
+                let f = factor.value(hit);
+                let a = a.value(hit);
+                a + (b.value(hit) - a).component_mul(&f)
+            }
+            Texture::Multiply(a, b) => {
+                a.value(hit).component_mul(&b.value(hit))
+            }
+            Texture::Add(a, b) => a.value(hit) + b.value(hit),
+            Texture::Invert(input) => Color::repeat(1.0) - input.value(hit),
+            Texture::ColorRamp(input, stops) => {
+                let x = luminance(&input.value(hit));
+                ramp(stops, x)
+            }
+            &Texture::HSV(ref input, hue, saturation, value) => {
+                let (h, s, v) = rgb_to_hsv(input.value(hit));
+                hsv_to_rgb(
+                    h + hue / 360.0,
+                    fclamp(s * saturation, 0.0, 1.0),
+                    v * value,
+                )
+            }
+            Texture::UV => Color::new(hit.uv.0, hit.uv.1, 0.0),
+            Texture::Position => hit.point.coords,
+            Texture::Normal => *hit.normal,
+            &Texture::Triplanar(ref input, scale, sharpness) => {
+                let p = scale * hit.point;
+                let weights = hit.normal.map(|n| n.abs().powf(sharpness));
+                let weights = weights / weights.sum();
+                let project = |uv: (f64, f64)| {
+                    let mut projected = *hit;
+                    projected.uv = uv;
+                    projected.duv = None;
+                    input.value(&projected)
+                };
+                weights.x * project((p.z, p.y))
+                    + weights.y * project((p.x, p.z))
+                    + weights.z * project((p.x, p.y))
+            }
+        }
+    }
+
+    /// Rough estimate of the mean value over a surface, for weighing lights
+    /// against each other. Patterns and surface-dependent inputs are taken
+    /// to be halfway between their extremes.
+    pub fn average(&self) -> Color {
+        match self {
+            Texture::Solid(c) => *c,
+            Texture::Image(mipmap, _) => mipmap.average(),
+            Texture::Checker(a, b) | Texture::Pattern(_, a, b) => {
+                (a.average() + b.average()) / 2.0
+            }
+            Texture::Mix(a, b, factor) => {
+                let a = a.average();
+                a + (b.average() - a).component_mul(&factor.average())
+            }
+            Texture::Multiply(a, b) => a.average().component_mul(&b.average()),
+            Texture::Add(a, b) => a.average() + b.average(),
+            Texture::Invert(input) => Color::repeat(1.0) - input.average(),
+            Texture::ColorRamp(input, stops) => {
+                ramp(stops, luminance(&input.average()))
+            }
+            &Texture::HSV(ref input, hue, saturation, value) => {
+                let (h, s, v) = rgb_to_hsv(input.average());
+                hsv_to_rgb(
+                    h + hue / 360.0,
+                    fclamp(s * saturation, 0.0, 1.0),
+                    v * value,
+                )
+            }
+            Texture::UV | Texture::Position | Texture::Normal => {
+                Color::repeat(0.5)
+            }
+            Texture::Triplanar(input, ..) => input.average(),
+        }
+    }
+}
+
+fn ramp(stops: &[(f64, Color)], x: f64) -> Color {
+    match stops.iter().position(|&(pos, _)| pos > x) {
+        None => stops.last().map_or(Color::zeros(), |s| s.1),
+        Some(0) => stops[0].1,
+        Some(i) => {
+            let (p0, c0) = stops[i - 1];
+            let (p1, c1) = stops[i];
+            let t = (x - p0) / (p1 - p0);
+            (1.0 - t) * c0 + t * c1
+        }
+    }
+}
+
+/// Converts to hue, saturation and value, each in [0, 1].
+fn rgb_to_hsv(c: Color) -> (f64, f64, f64) {
+    let max = c.max();
+    let min = c.min();
+    let delta = max - min;
+    let h = if delta <= 0.0 {
+        0.0
+    } else if max == c.x {
+        ((c.y - c.z) / delta).rem_euclid(6.0)
+    } else if max == c.y {
+        (c.z - c.x) / delta + 2.0
+    } else {
+        (c.x - c.y) / delta + 4.0
+    };
+    let s = if max > 0.0 { delta / max } else { 0.0 };
+    (h / 6.0, s, max)
+}
+
+fn hsv_to_rgb(h: f64, s: f64, v: f64) -> Color {
+    let h = 6.0 * h.rem_euclid(1.0);
+    let c = v * s;
+    let x = c * (1.0 - ((h % 2.0) - 1.0).abs());
+    let (r, g, b) = match h as u32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    Color::new(r, g, b) + Color::repeat(v - c)
+}
+
+#[cfg(test)]
+#[test]
+fn image_mapping_scales_rotates_and_offsets_uvs() {
+    use crate::material::Material;
+    use image::{DynamicImage, ImageBuffer, Rgb};
+    use nalgebra::Unit;
+    // Each texel records its own column and row.
+    let img = ImageBuffer::from_fn(4, 4, |x, y| Rgb([x as u8, y as u8, 0]));
+    let mipmap = Arc::new(MipMap::new(
+        &DynamicImage::ImageRgb8(img),
+        ColorSpace::Linear,
+    ));
+    let mapping = ImageMapping {
+        scale: (2.0, 1.0),
+        offset: (0.25, 0.1),
+        rotation: 90.0,
+        filter: FilterMode::Nearest,
+        color_space: ColorSpace::Linear,
+        ..ImageMapping::default()
+    };
+    let texture = Texture::Image(mipmap, mapping);
+    let material =
+        Material::Lambertian(Arc::new(Texture::Solid(Color::zeros())));
+    let texel = |uv| {
+        let hit = Hit {
+            point: Point::origin(),
+            normal: Unit::new_normalize(Vector::z()),
+            t: 1.0,
+            front_facing: true,
+            material: &material,
+            uv,
+            duv: None,
+            tangent: None,
+        };
+        let c = texture.value(&hit) * 255.0;
+        (c.x.round() as u32, c.y.round() as u32)
+    };
+    // (0.3, 0.2) scales to (0.6, 0.2), rotates to (-0.2, 0.6) and is offset
+    // to (0.05, 0.7), which is 0.3 down from the top of the image.
+    assert_eq!(texel((0.3, 0.2)), (0, 1));
+    // (0.9, 0.5) lands at (-0.25, -0.9), and wraps around onto the image.
+    assert_eq!(texel((0.9, 0.5)), (3, 0));
+}
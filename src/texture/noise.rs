@@ -0,0 +1,356 @@
+use crate::geometry::*;
+use crate::util::fclamp;
+use serde::{Deserialize, Serialize};
+
+/// Underlying band-limited noise function used by fractal patterns.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub enum NoiseBasis {
+    Perlin,
+    Simplex,
+}
+
+impl NoiseBasis {
+    /// Noise value in roughly [-1, 1].
+    fn eval(&self, p: Point) -> f64 {
+        match self {
+            NoiseBasis::Perlin => perlin(p),
+            NoiseBasis::Simplex => simplex(p),
+        }
+    }
+}
+
+/// Feature of the distances to the nearest cell points in Voronoi noise.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub enum VoronoiFeature {
+    /// Distance to the closest point.
+    F1,
+    /// Distance to the second closest point.
+    F2,
+    /// Difference between F2 and F1, which is zero on cell borders.
+    Edge,
+}
+
+/// Sums octaves of noise, each `lacunarity` times the frequency and `gain`
+/// times the amplitude of the previous one.
+#[derive(Debug, Copy, Clone)]
+pub struct Fractal {
+    pub basis: NoiseBasis,
+    pub octaves: u32,
+    pub lacunarity: f64,
+    pub gain: f64,
+}
+
+impl Default for Fractal {
+    fn default() -> Fractal {
+        Fractal {
+            basis: NoiseBasis::Perlin,
+            octaves: 6,
+            lacunarity: 2.0,
+            gain: 0.5,
+        }
+    }
+}
+
+impl Fractal {
+    /// Fractional Brownian motion, normalized to roughly [-1, 1].
+    fn fbm(&self, p: Point) -> f64 {
+        self.sum(p, |n| n)
+    }
+
+    /// Sum of absolute noise values, normalized to roughly [0, 1].
+    fn turbulence(&self, p: Point) -> f64 {
+        self.sum(p, f64::abs)
+    }
+
+    fn sum(&self, p: Point, f: impl Fn(f64) -> f64) -> f64 {
+        let mut sum = 0.0;
+        let mut total = 0.0;
+        let mut freq = 1.0;
+        let mut amp = 1.0;
+        for _ in 0..self.octaves {
+            sum += amp * f(self.basis.eval(Point::from(freq * p.coords)));
+            total += amp;
+            freq *= self.lacunarity;
+            amp *= self.gain;
+        }
+        if total > 0.0 {
+            sum / total
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Scalar field used to blend between two textures.
+/// All patterns produce values in [0, 1] and are evaluated at the surface
+/// point multiplied by `scale`.
+#[derive(Debug, Copy, Clone)]
+pub enum Pattern {
+    Noise(NoiseBasis, f64),
+    FBm(Fractal, f64),
+    Turbulence(Fractal, f64),
+    /// Sine veins along the x axis, perturbed by `strength` turbulence.
+    Marble {
+        fractal: Fractal,
+        scale: f64,
+        strength: f64,
+    },
+    /// Concentric rings around the y axis, perturbed by `strength` fBm.
+    Wood {
+        fractal: Fractal,
+        scale: f64,
+        rings: f64,
+        strength: f64,
+    },
+    Voronoi(VoronoiFeature, f64),
+    /// Linear ramp from `start` to `end`, clamped on either side.
+    Gradient(Point, Point),
+}
+
+impl Pattern {
+    pub fn eval(&self, point: Point) -> f64 {
+        let scaled = |scale: f64| Point::from(scale * point.coords);
+        let value = match self {
+            Pattern::Noise(basis, scale) => {
+                0.5 * (1.0 + basis.eval(scaled(*scale)))
+            }
+            Pattern::FBm(fractal, scale) => {
+                0.5 * (1.0 + fractal.fbm(scaled(*scale)))
+            }
+            Pattern::Turbulence(fractal, scale) => {
+                fractal.turbulence(scaled(*scale))
+            }
+            Pattern::Marble {
+                fractal,
+                scale,
+                strength,
+            } => {
+                let p = scaled(*scale);
+                let phase = p.x + strength * fractal.turbulence(p);
+                0.5 * (1.0 + phase.sin())
+            }
+            Pattern::Wood {
+                fractal,
+                scale,
+                rings,
+                strength,
+            } => {
+                let p = scaled(*scale);
+                let dist = p.x.hypot(p.z) + strength * fractal.fbm(p);
+                let ring = rings * dist;
+                ring - ring.floor()
+            }
+            Pattern::Voronoi(feature, scale) => {
+                let (f1, f2) = voronoi(scaled(*scale));
+                match feature {
+                    VoronoiFeature::F1 => f1,
+                    VoronoiFeature::F2 => f2,
+                    VoronoiFeature::Edge => f2 - f1,
+                }
+            }
+            Pattern::Gradient(start, end) => {
+                let axis = end - start;
+                (point - start).dot(&axis) / axis.norm_squared()
+            }
+        };
+        fclamp(value, 0.0, 1.0)
+    }
+}
+
+/// Hashes integer lattice coordinates to a pseudo-random 32 bit value.
+fn hash(x: i64, y: i64, z: i64) -> u32 {
+    let mut h = (x as u32)
+        .wrapping_mul(0x8da6_b343)
+        .wrapping_add((y as u32).wrapping_mul(0xd816_3841))
+        .wrapping_add((z as u32).wrapping_mul(0xcb1a_b31f));
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x7feb_352d);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x846c_a68b);
+    h ^ (h >> 16)
+}
+
+/// Pseudo-random value in [0, 1) for a lattice point and channel.
+fn hash_unit(x: i64, y: i64, z: i64, channel: i64) -> f64 {
+    hash(x, y, z.wrapping_add(channel.wrapping_mul(7919))) as f64
+        / (u32::MAX as f64 + 1.0)
+}
+
+/// Dot product of the offset with one of the 12 cube edge gradients.
+fn grad(hash: u32, x: f64, y: f64, z: f64) -> f64 {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 {
+        y
+    } else if h == 12 || h == 14 {
+        x
+    } else {
+        z
+    };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+fn lerp(t: f64, a: f64, b: f64) -> f64 {
+    a + t * (b - a)
+}
+
+/// Ken Perlin's improved gradient noise.
+fn perlin(p: Point) -> f64 {
+    let (xf, yf, zf) = (p.x.floor(), p.y.floor(), p.z.floor());
+    let (xi, yi, zi) = (xf as i64, yf as i64, zf as i64);
+    let (x, y, z) = (p.x - xf, p.y - yf, p.z - zf);
+    let fade = |t: f64| t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+    let (u, v, w) = (fade(x), fade(y), fade(z));
+    let g = |dx: i64, dy: i64, dz: i64| {
+        grad(
+            hash(xi + dx, yi + dy, zi + dz),
+            x - dx as f64,
+            y - dy as f64,
+            z - dz as f64,
+        )
+    };
+    lerp(
+        w,
+        lerp(
+            v,
+            lerp(u, g(0, 0, 0), g(1, 0, 0)),
+            lerp(u, g(0, 1, 0), g(1, 1, 0)),
+        ),
+        lerp(
+            v,
+            lerp(u, g(0, 0, 1), g(1, 0, 1)),
+            lerp(u, g(0, 1, 1), g(1, 1, 1)),
+        ),
+    )
+}
+
+/// 3D simplex noise, following Stefan Gustavson's reference implementation.
+fn simplex(p: Point) -> f64 {
+    const F3: f64 = 1.0 / 3.0;
+    const G3: f64 = 1.0 / 6.0;
+
+    // Skew into the simplex lattice to find the containing cell.
+    let s = (p.x + p.y + p.z) * F3;
+    let (i, j, k) = (
+        (p.x + s).floor() as i64,
+        (p.y + s).floor() as i64,
+        (p.z + s).floor() as i64,
+    );
+    let t = (i + j + k) as f64 * G3;
+    let x0 = Vector::new(
+        p.x - (i as f64 - t),
+        p.y - (j as f64 - t),
+        p.z - (k as f64 - t),
+    );
+
+    // Determine which of the six tetrahedra the point lies in.
+    let (o1, o2) = if x0.x >= x0.y {
+        if x0.y >= x0.z {
+            ((1, 0, 0), (1, 1, 0))
+        } else if x0.x >= x0.z {
+            ((1, 0, 0), (1, 0, 1))
+        } else {
+            ((0, 0, 1), (1, 0, 1))
+        }
+    } else if x0.y < x0.z {
+        ((0, 0, 1), (0, 1, 1))
+    } else if x0.x < x0.z {
+        ((0, 1, 0), (0, 1, 1))
+    } else {
+        ((0, 1, 0), (1, 1, 0))
+    };
+
+    let corners = [(0, 0, 0), o1, o2, (1, 1, 1)];
+    let mut sum = 0.0;
+    for (n, &(di, dj, dk)) in corners.iter().enumerate() {
+        let offset = Vector::new(di as f64, dj as f64, dk as f64)
+            - Vector::repeat(n as f64 * G3);
+        let d = x0 - offset;
+        let falloff = 0.6 - d.norm_squared();
+        if falloff > 0.0 {
+            let h = hash(i + di, j + dj, k + dk);
+            sum += falloff.powi(4) * grad(h, d.x, d.y, d.z);
+        }
+    }
+    // Scale the result to cover [-1, 1].
+    32.0 * sum
+}
+
+/// Distances to the closest and second closest jittered cell points.
+fn voronoi(p: Point) -> (f64, f64) {
+    let (xi, yi, zi) =
+        (p.x.floor() as i64, p.y.floor() as i64, p.z.floor() as i64);
+    let mut f1 = f64::INFINITY;
+    let mut f2 = f64::INFINITY;
+    for dx in -1..=1 {
+        for dy in -1..=1 {
+            for dz in -1..=1 {
+                let (cx, cy, cz) = (xi + dx, yi + dy, zi + dz);
+                let feature = Point::new(
+                    cx as f64 + hash_unit(cx, cy, cz, 0),
+                    cy as f64 + hash_unit(cx, cy, cz, 1),
+                    cz as f64 + hash_unit(cx, cy, cz, 2),
+                );
+                let dist = (feature - p).norm();
+                if dist < f1 {
+                    f2 = f1;
+                    f1 = dist;
+                } else if dist < f2 {
+                    f2 = dist;
+                }
+            }
+        }
+    }
+    (f1, f2)
+}
+
+#[cfg(test)]
+#[test]
+fn noise_is_bounded_deterministic_and_finds_nearest_features() {
+    let fractal = Fractal::default();
+    let simplex_fbm = Fractal {
+        basis: NoiseBasis::Simplex,
+        ..Fractal::default()
+    };
+    for n in 0..2000 {
+        let p = Point::new(
+            20.0 * hash_unit(n, 0, 0, 0) - 10.0,
+            20.0 * hash_unit(n, 0, 0, 1) - 10.0,
+            20.0 * hash_unit(n, 0, 0, 2) - 10.0,
+        );
+        for &v in &[perlin(p), simplex(p), fractal.fbm(p), simplex_fbm.fbm(p)] {
+            assert!((-1.0..=1.0).contains(&v), "{} out of range at {}", v, p);
+        }
+        let turbulence = fractal.turbulence(p);
+        assert!((0.0..=1.0).contains(&turbulence));
+
+        // The same point always gives the same value.
+        assert_eq!(perlin(p).to_bits(), perlin(p).to_bits());
+        assert_eq!(fractal.fbm(p).to_bits(), fractal.fbm(p).to_bits());
+
+        // Gradient noise vanishes on the integer lattice.
+        let lattice = Point::from(p.coords.map(f64::floor));
+        assert!(perlin(lattice).abs() < 1e-12);
+
+        // Compare against a brute force search over a wider neighbourhood.
+        let (f1, f2) = voronoi(p);
+        let mut dists = Vec::new();
+        let (xi, yi, zi) =
+            (lattice.x as i64, lattice.y as i64, lattice.z as i64);
+        for cx in xi - 2..=xi + 2 {
+            for cy in yi - 2..=yi + 2 {
+                for cz in zi - 2..=zi + 2 {
+                    let feature = Point::new(
+                        cx as f64 + hash_unit(cx, cy, cz, 0),
+                        cy as f64 + hash_unit(cx, cy, cz, 1),
+                        cz as f64 + hash_unit(cx, cy, cz, 2),
+                    );
+                    dists.push((feature - p).norm());
+                }
+            }
+        }
+        dists.sort_by(f64::total_cmp);
+        assert!((f1 - dists[0]).abs() < 1e-12);
+        assert!((f2 - dists[1]).abs() < 1e-12);
+    }
+}
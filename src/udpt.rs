@@ -2,8 +2,12 @@ use crate::color::Color;
 use crate::geometry::*;
 use crate::pdf::PDF;
 use crate::raytrace::Tracer;
+use crate::spectrum::Wavelengths;
 
 const MAX_DEPTH: u32 = 25;
+/// Collisions with media don't count towards the depth, since random walks
+/// through dense media take many of them, but are limited separately.
+const MAX_COLLISIONS: u32 = 256;
 
 /// Unidirectional Path Tracer.
 pub struct UDPT<'scene> {
@@ -22,36 +26,100 @@ impl<'scene> UDPT<'scene> {
         }
         let mut ray = ray;
         let mut result = Color::new(1.0, 1.0, 1.0);
-        for _ in 0..depth {
+        // In spectral mode each channel of `result` carries one of the
+        // wavelengths, and RGB quantities are converted to match.
+        let wavelengths = ray.wavelength.map(Wavelengths::from_hero);
+        let convert = |c: Color| match &wavelengths {
+            Some(w) => w.upsample(c),
+            None => c,
+        };
+        let mut dispersed = false;
+        // Light reaching the path directly from delta lights, which can
+        // never be hit by scattered rays, and from emissive media.
+        let mut direct = Color::zeros();
+        let (mut bounces, mut collisions) = (0, 0);
+        let time = ray.time;
+        while bounces < depth && collisions < MAX_COLLISIONS {
+            ray.wavelength = wavelengths.map(|w| w.hero());
+            ray.time = time;
             match self.scene.hit(ray, 0.0001..f64::INFINITY) {
                 Some(hit) => {
-                    let emit = hit.material.emitted(&hit);
+                    if hit.material.medium().is_some() {
+                        collisions += 1;
+                    } else {
+                        bounces += 1;
+                    }
+                    let emit = convert(hit.material.emitted(&ray, &hit));
+                    if wavelengths.is_some()
+                        && !dispersed
+                        && hit.material.is_dispersive()
+                    {
+                        // Other wavelengths would refract in different
+                        // directions, so only the hero wavelength continues
+                        // and carries the weight of the whole sample.
+                        result = result
+                            .component_mul(&Wavelengths::terminate_secondary());
+                        dispersed = true;
+                    }
                     match hit.material.scatter(&ray, &hit) {
                         None => {
-                            result = result.component_mul(&emit);
-                            break;
+                            return result.component_mul(&emit) + direct;
                         }
                         Some(scatter) => {
+                            // Media emit light at collisions as well as
+                            // scattering it.
+                            direct += result.component_mul(&emit);
+                            let attenuation = convert(scatter.attenuation);
                             if let Some(specular) = scatter.specular {
-                                result =
-                                    result.component_mul(&scatter.attenuation);
+                                result = result.component_mul(&attenuation);
                                 ray = specular;
                             }
                             if let Some(scatter_pdf) = scatter.pdf {
-                                let light_pdf = PDF::hittable(
+                                if !hit.material.samples_lights() {
+                                    result = result.component_mul(&attenuation);
+                                    ray =
+                                        Ray::new(hit.point, scatter_pdf.gen());
+                                    continue;
+                                }
+                                if scatter.specular.is_none() {
+                                    let light = self.sample_delta_light(
+                                        &hit,
+                                        &scatter_pdf,
+                                        time,
+                                    );
+                                    direct += result
+                                        .component_mul(&attenuation)
+                                        .component_mul(&convert(light));
+                                }
+                                let object_pdf = PDF::hittable(
                                     hit.point,
+                                    time,
                                     &self.scene.lights,
                                 );
-                                let final_pdf = if self.scene.lights.is_empty()
-                                {
-                                    scatter_pdf
-                                } else {
-                                    PDF::mix(0.75, &scatter_pdf, &light_pdf)
+                                let background_pdf =
+                                    self.scene.background.pdf();
+                                let light_pdf = match (
+                                    self.scene.lights.is_empty(),
+                                    &background_pdf,
+                                ) {
+                                    (false, Some(background_pdf)) => {
+                                        Some(PDF::mix(
+                                            0.5,
+                                            &object_pdf,
+                                            background_pdf,
+                                        ))
+                                    }
+                                    (false, None) => Some(object_pdf),
+                                    (true, background_pdf) => *background_pdf,
                                 };
-                                let scatter_ray = Ray {
-                                    origin: hit.point,
-                                    dir: final_pdf.gen(),
+                                let final_pdf = match &light_pdf {
+                                    Some(light_pdf) => {
+                                        PDF::mix(0.75, &scatter_pdf, light_pdf)
+                                    }
+                                    None => scatter_pdf,
                                 };
+                                let scatter_ray =
+                                    Ray::new(hit.point, final_pdf.gen());
                                 // The final value is the emission plus the MC
                                 // estimate: attenuation
                                 // * color(dir) * (s(dir) / p(dir))
@@ -71,8 +139,7 @@ impl<'scene> UDPT<'scene> {
                                 // the
                                 // calculation so we actually do need to do the
                                 // division now.
-                                result = result
-                                    .component_mul(&scatter.attenuation)
+                                result = result.component_mul(&attenuation)
                                     * scatter_pdf.value(scatter_ray.dir)
                                     * final_pdf.value(scatter_ray.dir).recip();
                                 ray = scatter_ray;
@@ -82,11 +149,58 @@ impl<'scene> UDPT<'scene> {
                     }
                 }
                 None => {
-                    result = result.component_mul(&self.scene.background);
-                    break;
+                    let background = self.scene.background.eval(ray.dir);
+                    return result.component_mul(&convert(background)) + direct;
                 }
             };
         }
+        result + direct
+    }
+
+    /// Estimates the light arriving at `hit` from a randomly chosen delta
+    /// light and scattered according to `scatter_pdf`.
+    fn sample_delta_light(
+        &self,
+        hit: &Hit,
+        scatter_pdf: &PDF,
+        time: f64,
+    ) -> Color {
+        let (light, prob) = match self.scene.choose_delta_light() {
+            Some(choice) => choice,
+            None => return Color::zeros(),
+        };
+        let sample = light.sample(hit.point);
+        let weight = scatter_pdf.value(*sample.dir);
+        if weight <= 0.0 {
+            return Color::zeros();
+        }
+        let mut shadow = Ray::new(hit.point, *sample.dir);
+        shadow.time = time;
+        let transmittance = self.transmittance(shadow, sample.dist);
+        sample.irradiance.component_mul(&transmittance) * weight / prob
+    }
+
+    /// Estimates the fraction of light which travels along `ray` unoccluded
+    /// for a distance of `t` times its direction. Collisions with media are
+    /// sampled with their majorants, and only let through the fraction of
+    /// each channel which was a null collision.
+    fn transmittance(&self, ray: Ray, t: f64) -> Color {
+        let mut result = Color::repeat(1.0);
+        let mut ray = ray;
+        let mut t = t;
+        while let Some(hit) = self.scene.hit(ray, 0.0001..t) {
+            match hit.material.medium() {
+                Some(medium) => {
+                    result = result.component_mul(&medium.null_fraction());
+                    if result.max() <= 0.0 {
+                        return Color::zeros();
+                    }
+                    ray.origin = hit.point;
+                    t -= hit.t;
+                }
+                None => return Color::zeros(),
+            }
+        }
         result
     }
 }